@@ -0,0 +1,67 @@
+//! Checks the algorithms against the check values of the catalogue of parametrised CRC algorithms
+//! and the bit-by-bit definition of a CRC, and that a CRC can be computed in parts
+//!
+//! ```sh
+//! cargo run --example check
+//! ```
+use checksum::{Algorithm, CRC_16_CCITT_FALSE, CRC_32, Crc};
+
+fn main() {
+    assert_eq!(crc(&CRC_16_CCITT_FALSE, b"123456789"), 0x29B1);
+    assert_eq!(crc(&CRC_32, b"123456789"), 0xCBF4_3926);
+    assert_eq!(crc(&CRC_16_CCITT_FALSE, b""), 0xFFFF);
+    assert_eq!(crc(&CRC_32, b""), 0);
+
+    let data: Vec<u8> = (0..=255).chain((0..=255).rev()).collect();
+    for algorithm in [&CRC_16_CCITT_FALSE, &CRC_32] {
+        for len in 0..data.len() {
+            let bytes = &data[..len];
+            assert_eq!(
+                crc(algorithm, bytes),
+                by_definition(algorithm, bytes),
+                "{len} bytes"
+            );
+            let mut parts = Crc::new(algorithm);
+            let (head, tail) = bytes.split_at(len / 3);
+            parts.update(head);
+            parts.update(tail);
+            assert_eq!(parts.finish(), crc(algorithm, bytes), "{len} bytes");
+        }
+    }
+    println!("ok");
+}
+
+fn crc(algorithm: &'static Algorithm, bytes: &[u8]) -> u32 {
+    let mut crc = Crc::new(algorithm);
+    crc.update(bytes);
+    crc.finish()
+}
+
+/// The CRC as the remainder of the polynomial division of the message by the generator, one bit
+/// at a time in a register wide enough for any width
+fn by_definition(algorithm: &Algorithm, bytes: &[u8]) -> u32 {
+    let width = algorithm.width;
+    let top = 1u64 << width;
+    let mut register = algorithm.init as u64;
+    for &byte in bytes {
+        let byte = if algorithm.reflected {
+            byte.reverse_bits()
+        } else {
+            byte
+        };
+        for bit in (0..8).rev() {
+            let feedback = ((register >> (width - 1)) & 1) ^ ((byte as u64 >> bit) & 1);
+            register = (register << 1) & (top - 1);
+            if feedback == 1 {
+                register ^= algorithm.poly as u64;
+            }
+        }
+    }
+    let register = register as u32;
+    let register = if algorithm.reflected {
+        register.reverse_bits() >> (32 - width)
+    } else {
+        register
+    };
+    register ^ algorithm.xor_out
+}
@@ -0,0 +1,102 @@
+//! Cyclic redundancy checks for the integrity of link frames and flash records
+//!
+//! A [Crc] computes any CRC of up to 32 bits, given by its [Algorithm] in the terms of the
+//! catalogue of parametrised CRC algorithms. The algorithms in use are [CRC_16_CCITT_FALSE] for
+//! the frames of the link and [CRC_32] for the records of the flash store.
+#![no_std]
+// Checksums on the wire and in flash must be documented thoroughly
+#![deny(missing_docs)]
+
+/// Parameters of a CRC
+pub struct Algorithm {
+    /// Number of bits of the CRC, from 8 to 32
+    pub width: u32,
+    /// Generator polynomial, most significant bit first, without the leading term
+    pub poly: u32,
+    /// Value of the register before the first byte
+    pub init: u32,
+    /// Whether the bytes are processed least significant bit first, and the CRC is reflected
+    pub reflected: bool,
+    /// Value XORed into the register to finish the CRC
+    pub xor_out: u32,
+}
+
+/// CRC-16/CCITT-FALSE, which checks the frames of the link. The CRC of `123456789` is `0x29B1`.
+pub const CRC_16_CCITT_FALSE: Algorithm = Algorithm {
+    width: 16,
+    poly: 0x1021,
+    init: 0xFFFF,
+    reflected: false,
+    xor_out: 0,
+};
+
+/// CRC-32 of IEEE 802.3, which checks the records of the flash store. The CRC of `123456789` is
+/// `0xCBF4_3926`.
+pub const CRC_32: Algorithm = Algorithm {
+    width: 32,
+    poly: 0x04C1_1DB7,
+    init: 0xFFFF_FFFF,
+    reflected: true,
+    xor_out: 0xFFFF_FFFF,
+};
+
+/// Incremental CRC computation
+pub struct Crc {
+    algorithm: &'static Algorithm,
+    /// Polynomial in the bit order of the register
+    poly: u32,
+    /// Bits of the register that are part of the CRC
+    mask: u32,
+    register: u32,
+}
+
+impl Crc {
+    /// Starts a CRC of `algorithm`
+    pub const fn new(algorithm: &'static Algorithm) -> Self {
+        assert!(8 <= algorithm.width && algorithm.width <= 32);
+        let (poly, register) = if algorithm.reflected {
+            (
+                reflect(algorithm.poly, algorithm.width),
+                reflect(algorithm.init, algorithm.width),
+            )
+        } else {
+            (algorithm.poly, algorithm.init)
+        };
+        Self {
+            algorithm,
+            poly,
+            mask: u32::MAX >> (32 - algorithm.width),
+            register,
+        }
+    }
+
+    /// Adds `bytes` to the CRC
+    pub fn update(&mut self, bytes: &[u8]) {
+        let width = self.algorithm.width;
+        for &byte in bytes {
+            if self.algorithm.reflected {
+                self.register ^= byte as u32;
+                for _ in 0..8 {
+                    let mask = (self.register & 1).wrapping_neg();
+                    self.register = (self.register >> 1) ^ (self.poly & mask);
+                }
+            } else {
+                self.register ^= (byte as u32) << (width - 8);
+                for _ in 0..8 {
+                    let mask = ((self.register >> (width - 1)) & 1).wrapping_neg();
+                    self.register = ((self.register << 1) ^ (self.poly & mask)) & self.mask;
+                }
+            }
+        }
+    }
+
+    /// Returns the CRC of the bytes added so far, in the low [Algorithm::width] bits
+    pub fn finish(&self) -> u32 {
+        (self.register ^ self.algorithm.xor_out) & self.mask
+    }
+}
+
+/// Reverses the order of the low `width` bits of `value`
+const fn reflect(value: u32, width: u32) -> u32 {
+    value.reverse_bits() >> (32 - width)
+}
@@ -0,0 +1,90 @@
+//! Exercises the store against an in-memory flash, cutting the power at every possible point
+//!
+//! ```sh
+//! cargo run --example power_loss
+//! ```
+use flash_store::{MemFlash, Store};
+
+/// Four sectors of 256 bytes, small enough that the records move between sectors often
+type Flash = MemFlash<1024, 256>;
+
+const SECTORS: u32 = 4;
+const COUNTER: u16 = 1;
+const NAME: u16 = 2;
+
+fn main() {
+    wear_levelling();
+    power_loss();
+    println!("ok");
+}
+
+/// Writes the counter often enough for the records to move through every sector many times
+fn wear_levelling() {
+    let mut store = Store::mount(Flash::new(), 0, SECTORS).unwrap();
+    store.write(NAME, b"reliable-serial").unwrap();
+    for n in 0u64..1000 {
+        store.write(COUNTER, &n.to_le_bytes()).unwrap();
+    }
+
+    // Values survive a remount
+    let mut store = Store::mount(store.release(), 0, SECTORS).unwrap();
+    assert_eq!(read_counter(&mut store), Some(999));
+    let mut buf = [0; 32];
+    let len = store.read(NAME, &mut buf).unwrap().unwrap();
+    assert_eq!(&buf[..len], b"reliable-serial");
+
+    store.clear().unwrap();
+    assert_eq!(read_counter(&mut store), None);
+    let mut store = Store::mount(store.release(), 0, SECTORS).unwrap();
+    assert_eq!(read_counter(&mut store), None);
+    assert_eq!(store.read(NAME, &mut buf).unwrap(), None);
+}
+
+/// Cuts the power after every number of flash operations while incrementing the counter, and
+/// checks that the counter holds either the previous or the next value after the power returns
+fn power_loss() {
+    for ops in 0..200 {
+        let mut store = Store::mount(Flash::new(), 0, SECTORS).unwrap();
+        store.write(NAME, b"reliable-serial").unwrap();
+        for n in 0u64..20 {
+            store.write(COUNTER, &n.to_le_bytes()).unwrap();
+        }
+
+        let mut flash = store.release();
+        flash.fail_after(ops);
+        let mut last = 19;
+        // Mounting an existing store does not write, so it succeeds regardless of the power loss
+        let mut store = Store::mount(flash, 0, SECTORS).unwrap();
+        for n in 20u64..60 {
+            if store.write(COUNTER, &n.to_le_bytes()).is_err() {
+                break;
+            }
+            last = n;
+        }
+        let mut flash = store.release();
+
+        flash.restore_power();
+        let mut store = Store::mount(flash, 0, SECTORS).unwrap();
+        let counter = read_counter(&mut store)
+            .unwrap_or_else(|| panic!("no counter after {ops} operations, last {last}"));
+        assert!(
+            counter == last || counter == last + 1,
+            "counter was {counter} after losing power at {last} after {ops} operations"
+        );
+        let mut buf = [0; 32];
+        let len = store.read(NAME, &mut buf).unwrap().unwrap();
+        assert_eq!(&buf[..len], b"reliable-serial");
+
+        // The store is writable again
+        store.write(COUNTER, &100u64.to_le_bytes()).unwrap();
+        assert_eq!(read_counter(&mut store), Some(100));
+    }
+}
+
+fn read_counter(store: &mut Store<Flash>) -> Option<u64> {
+    let mut buf = [0; 8];
+    store
+        .read(COUNTER, &mut buf)
+        .unwrap()
+        .map(|_| u64::from_le_bytes(buf))
+}
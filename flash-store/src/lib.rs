@@ -0,0 +1,32 @@
+//! Wear-levelled key/value store on NOR flash
+//!
+//! The store occupies a number of consecutive flash sectors, of which one is active at a time.
+//! Writing a value appends a record to the active sector, so that a value that is written often
+//! does not wear out any particular location. When the active sector is full, the latest value of
+//! each key is copied to the next sector in turn, which then becomes the active one.
+//!
+//! ## Layout
+//!
+//! | Item   | Contents                                                        |
+//! | :-     | :-                                                              |
+//! | Sector | `generation: u32`, `magic: u32`, records                        |
+//! | Record | `key: u16`, `len: u16`, `crc: u32`, data padded to eight bytes  |
+//!
+//! All integers are little endian. The CRC-32 covers the key, the length and the data of a record.
+//! A record of length zero removes the key.
+//!
+//! ## Power loss
+//!
+//! A record that was torn by a power loss fails its CRC and is ignored, so the key keeps its
+//! previous value. The header of a sector is written only after the records have been copied into
+//! it, so a sector that was being filled when power was lost is never mistaken for the active one.
+#![no_std]
+// The storage format must be documented thoroughly
+#![deny(missing_docs)]
+
+mod mem;
+mod store;
+
+pub use embedded_storage;
+pub use mem::{MemFlash, MemFlashError};
+pub use store::{Error, Key, Store};
@@ -0,0 +1,158 @@
+//! NOR flash in RAM for exercising the store on the host
+use embedded_storage::nor_flash::{
+    ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash, check_erase, check_read,
+    check_write,
+};
+
+/// Errors of [MemFlash]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MemFlashError {
+    /// The arguments are not aligned to the read, write or erase size
+    NotAligned,
+    /// The arguments are out of bounds
+    OutOfBounds,
+    /// A byte was written twice without erasing it in between
+    NotErased,
+    /// The operation was interrupted by a simulated power loss, see [MemFlash::fail_after]
+    PowerLoss,
+}
+
+impl NorFlashError for MemFlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        match self {
+            MemFlashError::NotAligned => NorFlashErrorKind::NotAligned,
+            MemFlashError::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            MemFlashError::NotErased | MemFlashError::PowerLoss => NorFlashErrorKind::Other,
+        }
+    }
+}
+
+impl From<NorFlashErrorKind> for MemFlashError {
+    fn from(kind: NorFlashErrorKind) -> Self {
+        match kind {
+            NorFlashErrorKind::NotAligned => MemFlashError::NotAligned,
+            _ => MemFlashError::OutOfBounds,
+        }
+    }
+}
+
+/// NOR flash backed by an array, with the read, write and erase sizes of the ESP32-C3 flash by
+/// default
+///
+/// Like real NOR flash, erasing sets bytes to `0xFF` and a byte may only be written once between
+/// erases. Power loss can be simulated with [MemFlash::fail_after].
+///
+/// # Type arguments
+///
+/// * `SIZE` - Capacity in bytes
+/// * `SECTOR` - Erase size in bytes
+pub struct MemFlash<const SIZE: usize, const SECTOR: usize = 4096> {
+    data: [u8; SIZE],
+    /// Number of writes and erases that succeed before power is lost, `None` if power is not lost
+    ops_left: Option<usize>,
+}
+
+impl<const SIZE: usize, const SECTOR: usize> MemFlash<SIZE, SECTOR> {
+    /// Creates an erased flash
+    pub const fn new() -> Self {
+        Self {
+            data: [0xFF; SIZE],
+            ops_left: None,
+        }
+    }
+
+    /// Contents of the flash
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Simulates losing power after `ops` writes or erases. The write that is interrupted only
+    /// writes the first half of its bytes, and the interrupted erase only erases the first half
+    /// of its range. All operations fail after the power loss until [MemFlash::restore_power].
+    pub fn fail_after(&mut self, ops: usize) {
+        self.ops_left = Some(ops);
+    }
+
+    /// Lets all operations succeed again
+    pub fn restore_power(&mut self) {
+        self.ops_left = None;
+    }
+
+    /// Returns whether the next operation runs to completion, consuming one operation
+    fn consume_op(&mut self) -> Result<bool, MemFlashError> {
+        match &mut self.ops_left {
+            None => Ok(true),
+            Some(0) => Err(MemFlashError::PowerLoss),
+            Some(1) => {
+                self.ops_left = Some(0);
+                Ok(false)
+            }
+            Some(n) => {
+                *n -= 1;
+                Ok(true)
+            }
+        }
+    }
+}
+
+impl<const SIZE: usize, const SECTOR: usize> Default for MemFlash<SIZE, SECTOR> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const SIZE: usize, const SECTOR: usize> ErrorType for MemFlash<SIZE, SECTOR> {
+    type Error = MemFlashError;
+}
+
+impl<const SIZE: usize, const SECTOR: usize> ReadNorFlash for MemFlash<SIZE, SECTOR> {
+    const READ_SIZE: usize = 4;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        check_read(self, offset, bytes.len())?;
+        let offset = offset as usize;
+        bytes.copy_from_slice(&self.data[offset..offset + bytes.len()]);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        SIZE
+    }
+}
+
+impl<const SIZE: usize, const SECTOR: usize> NorFlash for MemFlash<SIZE, SECTOR> {
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = SECTOR;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+        check_erase(self, from, to)?;
+        let (from, to) = (from as usize, to as usize);
+        if self.consume_op()? {
+            self.data[from..to].fill(0xFF);
+            Ok(())
+        } else {
+            self.data[from..from + (to - from) / 2].fill(0xFF);
+            Err(MemFlashError::PowerLoss)
+        }
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        check_write(self, offset, bytes.len())?;
+        let range = offset as usize..offset as usize + bytes.len();
+        if self.data[range.clone()].iter().any(|&b| b != 0xFF) {
+            return Err(MemFlashError::NotErased);
+        }
+        let complete = self.consume_op()?;
+        let len = if complete {
+            bytes.len()
+        } else {
+            bytes.len() / 2
+        };
+        self.data[range][..len].copy_from_slice(&bytes[..len]);
+        if complete {
+            Ok(())
+        } else {
+            Err(MemFlashError::PowerLoss)
+        }
+    }
+}
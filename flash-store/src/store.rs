@@ -0,0 +1,404 @@
+//! Log-structured key/value store
+use checksum::{CRC_32, Crc};
+use embedded_storage::nor_flash::NorFlash;
+
+/// Identifier of a value in the store. [u16::MAX] is reserved.
+pub type Key = u16;
+
+/// Marks the header of a sector that holds records
+const MAGIC: u32 = 0x4B56_5331;
+/// Headers and records are aligned to this many bytes. Must be a multiple of the read and write
+/// sizes of the flash.
+const ALIGN: u32 = 8;
+const SECTOR_HEADER_LEN: u32 = 8;
+const RECORD_HEADER_LEN: u32 = 8;
+/// Key of an erased record header, which marks the end of the records in a sector
+const ERASED_KEY: Key = u16::MAX;
+/// Size of the buffer for moving data to and from flash. Must be a multiple of [ALIGN].
+const CHUNK_LEN: usize = 32;
+
+/// Errors of [Store]
+#[derive(Debug, PartialEq)]
+pub enum Error<E> {
+    /// The flash returned an error
+    Flash(E),
+    /// The latest values of all keys together do not fit in one sector
+    Full,
+    /// The value does not fit in the buffer or in a sector
+    TooLarge,
+    /// The key is reserved
+    InvalidKey,
+}
+
+#[derive(Clone, Copy)]
+struct RecordHeader {
+    key: Key,
+    len: u16,
+    crc: u32,
+}
+
+impl RecordHeader {
+    fn new(key: Key, value: &[u8]) -> Self {
+        let len = value.len() as u16;
+        let mut crc = Crc::new(&CRC_32);
+        crc.update(&key.to_le_bytes());
+        crc.update(&len.to_le_bytes());
+        crc.update(value);
+        Self {
+            key,
+            len,
+            crc: crc.finish(),
+        }
+    }
+
+    fn from_bytes(b: [u8; RECORD_HEADER_LEN as usize]) -> Self {
+        Self {
+            key: Key::from_le_bytes([b[0], b[1]]),
+            len: u16::from_le_bytes([b[2], b[3]]),
+            crc: u32::from_le_bytes([b[4], b[5], b[6], b[7]]),
+        }
+    }
+
+    fn to_bytes(self) -> [u8; RECORD_HEADER_LEN as usize] {
+        let mut b = [0; RECORD_HEADER_LEN as usize];
+        b[0..2].copy_from_slice(&self.key.to_le_bytes());
+        b[2..4].copy_from_slice(&self.len.to_le_bytes());
+        b[4..8].copy_from_slice(&self.crc.to_le_bytes());
+        b
+    }
+
+    /// Length of the record in flash, including the header and padding
+    fn stored_len(&self) -> u32 {
+        RECORD_HEADER_LEN + padded(self.len as usize)
+    }
+}
+
+/// Rounds `len` up to a multiple of [ALIGN]
+fn padded(len: usize) -> u32 {
+    (len as u32).div_ceil(ALIGN) * ALIGN
+}
+
+/// Key/value store that spreads writes over a range of flash sectors
+///
+/// Only the active sector is read, so reads and writes take time proportional to the number of
+/// records in one sector.
+pub struct Store<F> {
+    flash: F,
+    /// Offset of the first sector of the store
+    start: u32,
+    sector_count: u32,
+    /// Index of the active sector
+    active: u32,
+    /// Generation of the active sector, incremented each time the records move to another sector
+    generation: u32,
+    /// Offset of the next record from the start of the active sector
+    write_pos: u32,
+}
+
+impl<F: NorFlash> Store<F> {
+    const SECTOR_LEN: u32 = F::ERASE_SIZE as u32;
+
+    /// Opens the store in `sector_count` sectors starting at `start`, or creates an empty store if
+    /// there is none
+    ///
+    /// # Panics
+    ///
+    /// If there are fewer than two sectors or the flash cannot be read and written in units of
+    /// [ALIGN] bytes.
+    pub fn mount(flash: F, start: u32, sector_count: u32) -> Result<Self, Error<F::Error>> {
+        assert!(sector_count >= 2, "the store needs at least two sectors");
+        assert!(
+            (ALIGN as usize).is_multiple_of(F::READ_SIZE)
+                && (ALIGN as usize).is_multiple_of(F::WRITE_SIZE),
+            "unsupported flash read or write size"
+        );
+        let mut store = Self {
+            flash,
+            start,
+            sector_count,
+            active: 0,
+            generation: 0,
+            write_pos: SECTOR_HEADER_LEN,
+        };
+
+        let mut latest = None;
+        for sector in 0..sector_count {
+            if let Some(generation) = store.read_generation(sector)?
+                && latest.is_none_or(|(_, latest)| generation > latest)
+            {
+                latest = Some((sector, generation));
+            }
+        }
+        match latest {
+            Some((sector, generation)) => {
+                store.active = sector;
+                store.generation = generation;
+                store.write_pos = store.find_end()?;
+            }
+            None => {
+                store.erase(0)?;
+                store.commit(0, 0)?;
+                store.active = 0;
+            }
+        }
+        Ok(store)
+    }
+
+    /// Gives the flash back
+    pub fn release(self) -> F {
+        self.flash
+    }
+
+    /// Reads the value of `key` into `buf`. Returns the length of the value or `None` if there is
+    /// no value.
+    pub fn read(&mut self, key: Key, buf: &mut [u8]) -> Result<Option<usize>, Error<F::Error>> {
+        let Some((pos, header)) = self.find(key)? else {
+            return Ok(None);
+        };
+        let len = header.len as usize;
+        if len == 0 {
+            return Ok(None);
+        }
+        if buf.len() < len {
+            return Err(Error::TooLarge);
+        }
+        let data = self.offset(self.active) + pos + RECORD_HEADER_LEN;
+        let mut chunk = [0; CHUNK_LEN];
+        for (idx, part) in buf[..len].chunks_mut(CHUNK_LEN).enumerate() {
+            let stored = padded(part.len()) as usize;
+            self.read_flash(data + (idx * CHUNK_LEN) as u32, &mut chunk[..stored])?;
+            part.copy_from_slice(&chunk[..part.len()]);
+        }
+        Ok(Some(len))
+    }
+
+    /// Sets the value of `key`
+    pub fn write(&mut self, key: Key, value: &[u8]) -> Result<(), Error<F::Error>> {
+        if key == ERASED_KEY {
+            return Err(Error::InvalidKey);
+        }
+        let max_len = (Self::SECTOR_LEN - SECTOR_HEADER_LEN - RECORD_HEADER_LEN) as usize;
+        if value.len() > max_len.min(u16::MAX as usize) {
+            return Err(Error::TooLarge);
+        }
+        if self.write_pos + RECORD_HEADER_LEN + padded(value.len()) > Self::SECTOR_LEN {
+            if value.is_empty() {
+                // Leaving out the previous value removes the key
+                return self.compact(|k| k != key);
+            }
+            // The previous value is moved too, as it must survive a power loss before the new
+            // value has been written
+            self.compact(|_| true)?;
+            if self.write_pos + RECORD_HEADER_LEN + padded(value.len()) > Self::SECTOR_LEN {
+                return Err(Error::Full);
+            }
+        }
+        self.append(key, value)
+    }
+
+    /// Removes the value of `key`
+    pub fn remove(&mut self, key: Key) -> Result<(), Error<F::Error>> {
+        match self.find(key)? {
+            Some((_, header)) if header.len > 0 => self.write(key, &[]),
+            _ => Ok(()),
+        }
+    }
+
+    /// Removes all values
+    pub fn clear(&mut self) -> Result<(), Error<F::Error>> {
+        self.compact(|_| false)
+    }
+
+    /// Returns the position and header of the latest intact record of `key` in the active sector
+    fn find(&mut self, key: Key) -> Result<Option<(u32, RecordHeader)>, Error<F::Error>> {
+        let mut found = None;
+        let mut pos = SECTOR_HEADER_LEN;
+        while let Some(header) = self.read_header(self.active, pos)? {
+            if header.key == key && self.is_intact(self.active, pos, header)? {
+                found = Some((pos, header));
+            }
+            pos += header.stored_len();
+        }
+        Ok(found)
+    }
+
+    /// Returns the position after the last record in the active sector
+    fn find_end(&mut self) -> Result<u32, Error<F::Error>> {
+        let mut pos = SECTOR_HEADER_LEN;
+        while let Some(header) = self.read_header(self.active, pos)? {
+            pos += header.stored_len();
+        }
+        let mut raw = [0; RECORD_HEADER_LEN as usize];
+        if pos + RECORD_HEADER_LEN <= Self::SECTOR_LEN {
+            self.read_flash(self.offset(self.active) + pos, &mut raw)?;
+            if raw.iter().any(|&b| b != 0xFF) {
+                // A torn header with a length beyond the sector, nothing can be appended after it
+                return Ok(Self::SECTOR_LEN);
+            }
+        }
+        Ok(pos)
+    }
+
+    /// Moves the latest values of the keys for which `keep` returns `true` to the next sector,
+    /// which becomes the active one
+    fn compact(&mut self, keep: impl Fn(Key) -> bool) -> Result<(), Error<F::Error>> {
+        let target = (self.active + 1) % self.sector_count;
+        self.erase(target)?;
+
+        let mut dst = SECTOR_HEADER_LEN;
+        let mut pos = SECTOR_HEADER_LEN;
+        while let Some(header) = self.read_header(self.active, pos)? {
+            let is_latest = header.len > 0
+                && keep(header.key)
+                && self
+                    .find(header.key)?
+                    .is_some_and(|(latest, _)| latest == pos);
+            if is_latest {
+                if dst + header.stored_len() > Self::SECTOR_LEN {
+                    // The active sector stays as it was
+                    return Err(Error::Full);
+                }
+                self.copy_record(pos, target, dst, header.stored_len())?;
+                dst += header.stored_len();
+            }
+            pos += header.stored_len();
+        }
+
+        // The target becomes valid only once the records are in place
+        self.commit(target, self.generation.wrapping_add(1))?;
+        self.active = target;
+        self.generation = self.generation.wrapping_add(1);
+        self.write_pos = dst;
+        Ok(())
+    }
+
+    fn append(&mut self, key: Key, value: &[u8]) -> Result<(), Error<F::Error>> {
+        let header = RecordHeader::new(key, value);
+        let base = self.offset(self.active) + self.write_pos;
+        let result = self.write_record(base, header, value);
+        self.write_pos = match result {
+            Ok(()) => self.write_pos + header.stored_len(),
+            // The header of a failed record cannot be trusted to skip over it, so nothing more
+            // may be appended to this sector. The next write moves the records to another sector.
+            Err(_) => Self::SECTOR_LEN,
+        };
+        result
+    }
+
+    fn write_record(
+        &mut self,
+        base: u32,
+        header: RecordHeader,
+        value: &[u8],
+    ) -> Result<(), Error<F::Error>> {
+        self.write_flash(base, &header.to_bytes())?;
+        let mut chunk = [0xFF; CHUNK_LEN];
+        for (idx, part) in value.chunks(CHUNK_LEN).enumerate() {
+            chunk.fill(0xFF);
+            chunk[..part.len()].copy_from_slice(part);
+            let stored = padded(part.len()) as usize;
+            let at = base + RECORD_HEADER_LEN + (idx * CHUNK_LEN) as u32;
+            self.write_flash(at, &chunk[..stored])?;
+        }
+        Ok(())
+    }
+
+    /// Copies `len` bytes of a record at `pos` of the active sector to `dst` of `target`
+    fn copy_record(
+        &mut self,
+        pos: u32,
+        target: u32,
+        dst: u32,
+        len: u32,
+    ) -> Result<(), Error<F::Error>> {
+        let (src, dst) = (self.offset(self.active) + pos, self.offset(target) + dst);
+        let mut chunk = [0; CHUNK_LEN];
+        let mut done = 0;
+        while done < len {
+            let n = (len - done).min(CHUNK_LEN as u32);
+            self.read_flash(src + done, &mut chunk[..n as usize])?;
+            self.write_flash(dst + done, &chunk[..n as usize])?;
+            done += n;
+        }
+        Ok(())
+    }
+
+    /// Returns the header of the record at `pos` of `sector`, or `None` if there are no more
+    /// records
+    fn read_header(
+        &mut self,
+        sector: u32,
+        pos: u32,
+    ) -> Result<Option<RecordHeader>, Error<F::Error>> {
+        if pos + RECORD_HEADER_LEN > Self::SECTOR_LEN {
+            return Ok(None);
+        }
+        let mut raw = [0; RECORD_HEADER_LEN as usize];
+        self.read_flash(self.offset(sector) + pos, &mut raw)?;
+        let header = RecordHeader::from_bytes(raw);
+        if header.key == ERASED_KEY || pos + header.stored_len() > Self::SECTOR_LEN {
+            return Ok(None);
+        }
+        Ok(Some(header))
+    }
+
+    /// Whether the data of a record matches its CRC
+    fn is_intact(
+        &mut self,
+        sector: u32,
+        pos: u32,
+        header: RecordHeader,
+    ) -> Result<bool, Error<F::Error>> {
+        let mut crc = Crc::new(&CRC_32);
+        crc.update(&header.key.to_le_bytes());
+        crc.update(&header.len.to_le_bytes());
+        let data = self.offset(sector) + pos + RECORD_HEADER_LEN;
+        let mut chunk = [0; CHUNK_LEN];
+        let mut done = 0;
+        while done < header.len as usize {
+            let n = (header.len as usize - done).min(CHUNK_LEN);
+            self.read_flash(data + done as u32, &mut chunk[..padded(n) as usize])?;
+            crc.update(&chunk[..n]);
+            done += n;
+        }
+        Ok(crc.finish() == header.crc)
+    }
+
+    /// Returns the generation of `sector`, or `None` if the sector does not hold records
+    fn read_generation(&mut self, sector: u32) -> Result<Option<u32>, Error<F::Error>> {
+        let mut raw = [0; SECTOR_HEADER_LEN as usize];
+        self.read_flash(self.offset(sector), &mut raw)?;
+        let generation = u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]);
+        let magic = u32::from_le_bytes([raw[4], raw[5], raw[6], raw[7]]);
+        Ok((magic == MAGIC && generation != u32::MAX).then_some(generation))
+    }
+
+    /// Writes the header of `sector`, making it the candidate for the active sector
+    fn commit(&mut self, sector: u32, generation: u32) -> Result<(), Error<F::Error>> {
+        let mut raw = [0; SECTOR_HEADER_LEN as usize];
+        // The magic comes last, so that a header that was torn by a power loss is not valid
+        raw[0..4].copy_from_slice(&generation.to_le_bytes());
+        raw[4..8].copy_from_slice(&MAGIC.to_le_bytes());
+        self.write_flash(self.offset(sector), &raw)
+    }
+
+    fn erase(&mut self, sector: u32) -> Result<(), Error<F::Error>> {
+        let from = self.offset(sector);
+        self.flash
+            .erase(from, from + Self::SECTOR_LEN)
+            .map_err(Error::Flash)
+    }
+
+    fn read_flash(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Error<F::Error>> {
+        self.flash.read(offset, bytes).map_err(Error::Flash)
+    }
+
+    fn write_flash(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Error<F::Error>> {
+        self.flash.write(offset, bytes).map_err(Error::Flash)
+    }
+
+    /// Offset of the start of `sector`
+    fn offset(&self, sector: u32) -> u32 {
+        self.start + sector * Self::SECTOR_LEN
+    }
+}
@@ -0,0 +1,67 @@
+//! Operator console on the BOOT button (GPIO9)
+//!
+//! | Press                          | Action                                         |
+//! | :-                             | :-                                             |
+//! | Short                          | [Funct::Increment]                             |
+//! | Long, at least [LONG_PRESS_MS] | [Funct::EnableBlink] or [Funct::DisableBlink]  |
+//!
+//! Presses go through the same command queue as commands from the host, and are reported to the
+//! host with [the_protocol_serde::Event::ButtonPressed].
+use the_protocol_serde::{Funct, Press};
+
+/// Edges closer than this to the previous accepted edge are contact bounce and ignored
+pub const DEBOUNCE_MS: u64 = 30;
+/// Presses at least this long are [Press::Long]
+pub const LONG_PRESS_MS: u64 = 800;
+/// Blink period set by a long press
+pub const BLINK_PERIOD_MS: u64 = 500;
+
+/// Turns the edges of the button into presses
+pub struct Debouncer {
+    /// Time of the last accepted edge
+    last_edge_ms: Option<u64>,
+    /// Time at which the button was pressed down, `None` while it is up
+    pressed_at: Option<u64>,
+}
+
+impl Debouncer {
+    pub const fn new() -> Self {
+        Self {
+            last_edge_ms: None,
+            pressed_at: None,
+        }
+    }
+
+    /// Feeds the state of the button after an edge and returns the press that completed with it
+    pub fn edge(&mut self, is_down: bool, now_ms: u64) -> Option<Press> {
+        if let Some(last) = self.last_edge_ms {
+            if now_ms - last < DEBOUNCE_MS {
+                return None;
+            }
+        }
+        self.last_edge_ms = Some(now_ms);
+
+        if is_down {
+            self.pressed_at = Some(now_ms);
+            return None;
+        }
+        // A release without a press is a bounce that settled after the press was ignored
+        let held_ms = now_ms - self.pressed_at.take()?;
+        Some(if held_ms >= LONG_PRESS_MS {
+            Press::Long
+        } else {
+            Press::Short
+        })
+    }
+}
+
+/// Action for a press, given whether the LED is currently blinking
+pub fn funct(press: Press, blinking: bool) -> Funct {
+    match press {
+        Press::Short => Funct::Increment,
+        Press::Long if blinking => Funct::DisableBlink,
+        Press::Long => Funct::EnableBlink {
+            period_ms: BLINK_PERIOD_MS,
+        },
+    }
+}
@@ -0,0 +1,51 @@
+//! Counters for device health diagnostics
+use esp_hal::rtc_cntl::SocResetReason;
+use portable_atomic::{AtomicU32, Ordering};
+use the_protocol_serde::ResetReason;
+
+/// Number of frames received, including corrupted ones
+pub static FRAMES_RECEIVED: AtomicU32 = AtomicU32::new(0);
+/// Number of frames rejected as corrupted
+pub static CORRUPTED_FRAMES: AtomicU32 = AtomicU32::new(0);
+/// Number of partial frames discarded after an idle gap
+pub static STALE_FRAMES: AtomicU32 = AtomicU32::new(0);
+/// Number of UART RX FIFO overflows
+pub static UART_OVERRUNS: AtomicU32 = AtomicU32::new(0);
+/// Number of commands rejected because the command queue was full
+pub static BUSY_REJECTIONS: AtomicU32 = AtomicU32::new(0);
+/// Number of replies dropped because the reply queue was full
+pub static DROPPED_REPLIES: AtomicU32 = AtomicU32::new(0);
+/// Number of replies that could not be written to UART
+pub static WRITE_ERRORS: AtomicU32 = AtomicU32::new(0);
+/// Number of link frames sent again because the host did not acknowledge them in time
+pub static RETRANSMISSIONS: AtomicU32 = AtomicU32::new(0);
+/// Number of frames dropped because they were not authenticated, failed verification or were
+/// replays
+pub static AUTH_FAILURES: AtomicU32 = AtomicU32::new(0);
+
+/// Increments a diagnostics counter
+pub fn count(counter: &AtomicU32) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Reads the reason for the most recent reset from hardware
+pub fn reset_reason() -> ResetReason {
+    match esp_hal::system::reset_reason() {
+        Some(SocResetReason::ChipPowerOn) => ResetReason::PowerOn,
+        Some(SocResetReason::CoreSw | SocResetReason::Cpu0Sw) => ResetReason::Software,
+        Some(
+            SocResetReason::CoreMwdt0
+            | SocResetReason::CoreMwdt1
+            | SocResetReason::CoreRtcWdt
+            | SocResetReason::Cpu0Mwdt0
+            | SocResetReason::Cpu0Mwdt1
+            | SocResetReason::Cpu0RtcWdt
+            | SocResetReason::SysRtcWdt
+            | SocResetReason::SysSuperWdt,
+        ) => ResetReason::Watchdog,
+        Some(SocResetReason::SysBrownOut) => ResetReason::BrownOut,
+        Some(SocResetReason::CoreDeepSleep) => ResetReason::DeepSleep,
+        Some(other) => ResetReason::Other(other as u8),
+        None => ResetReason::Unknown,
+    }
+}
@@ -7,54 +7,212 @@
 //! | IO21/TX   | RX    |
 //! | IO20/RX   | TX    |
 //!
-//! Baud: 115_200 BPS
+//! The BOOT button on IO9 is an operator console, see [console].
+//!
+//! Baud: 115_200 BPS after a reset, renegotiated with [CommandV2::SetBaudRate]
 #![no_std]
 #![no_main]
 
-use the_protocol_serde::Request;  
-use the_protocol_serde::Codec;  
-
+mod console;
+mod diagnostics;
+mod persist;
+mod rgb;
+mod schedule;
+mod security;
 mod serial;
+mod supervisor;
 
 // Bring in a panic handler
 use panic_rtt_target as _;
 
-use the_protocol::{Command, Funct, Response, RejectReason, Payload};
-use the_protocol_serde::Codec;
+use the_protocol::{Funct, RejectReason, Response, SDateTime};
+use the_protocol_serde::{
+    admit,
+    format::{DefaultFormat, Format},
+    link::{self, Link, Packet},
+    to_utc, Batch, BatchMode, BatchResponse, BlinkPattern, BlinkShape, Codec, CommandV2,
+    DeserializeError, DeviceMessage, Diagnostics, Event, FirmwareInfo, FrameReceiver, JobId,
+    LedState, Limits, PayloadV2, Press, Received, Recurrence, Reply, Request, ResponseV2,
+    RgbEffect, Version, BAUD_FALLBACK_MS, COMMAND_QUEUE_LEN, DEFAULT_BAUD_RATE, PROTOCOL_REVISION,
+};
+
+/// Parses a decimal version component at compile time
+const fn parse_version(s: &str) -> u16 {
+    let bytes = s.as_bytes();
+    let mut value = 0;
+    let mut idx = 0;
+    while idx < bytes.len() {
+        value = value * 10 + (bytes[idx] - b'0') as u16;
+        idx += 1;
+    }
+    value
+}
+
+/// Returned for [CommandV2::FirmwareInfo]
+const FIRMWARE_INFO: FirmwareInfo = FirmwareInfo {
+    major: parse_version(env!("CARGO_PKG_VERSION_MAJOR")),
+    minor: parse_version(env!("CARGO_PKG_VERSION_MINOR")),
+    patch: parse_version(env!("CARGO_PKG_VERSION_PATCH")),
+    protocol: PROTOCOL_REVISION,
+};
+
+/// Ranges of command arguments accepted by the device, returned for [CommandV2::Limits]
+const LIMITS: Limits = Limits {
+    // Shorter periods would keep the CPU busy toggling the LED
+    min_blink_period_ms: 10,
+    max_blink_period_ms: 60 * 60 * 1000,
+    min_blink_step_ms: 10,
+    max_blink_step_ms: 60 * 60 * 1000,
+    // Shorter periods would skip most hues or levels at the frame rate of the effects engine
+    min_rgb_period_ms: 200,
+    max_rgb_period_ms: 60 * 60 * 1000,
+    max_rgb_brightness: rgb::MAX_BRIGHTNESS,
+    // The largest reply takes 200 ms at the slowest rate, well within the timeouts of the link
+    min_baud_rate: 38_400,
+    max_baud_rate: 2_000_000,
+};
 
 #[rtic::app(device = esp32c3, dispatchers=[FROM_CPU_INTR0, FROM_CPU_INTR1, FROM_CPU_INTR2])]
 mod app {
     use super::*;
-    use crate::serial;
+    use crate::console::{self, Debouncer};
+    use crate::diagnostics::{self, count};
+    use crate::persist::{self, Persistence};
+    use crate::rgb::{self, RgbMode};
+    use crate::schedule::{self, Schedule};
+    use crate::security::{self, Security};
+    use crate::serial::{self, BaudRate, Frame, Transmitter, FRAME_QUEUE_LEN};
+    use crate::supervisor;
 
     use esp_hal::{
+        peripherals::TIMG0,
         rmt::{ConstChannelAccess, Rmt},
+        rng::Trng,
         time,
-        uart::{self, Uart, UartRx, UartTx},
-        gpio::{Output, OutputConfig},
+        timer::timg::{MwdtStage, TimerGroup, Wdt},
+        uart::{self, RxError, Uart, UartRx},
+        gpio::{self, Input, InputConfig, Level, Output, OutputConfig, Pull},
         Blocking,
     };
-    use esp_hal_smartled::{smart_led_buffer, SmartLedsAdapter};
+    use esp_hal_smartled::{buffer_size, smart_led_buffer, SmartLedsAdapter};
+    use rgb_effects::{Engine, FRAME_INTERVAL_MS};
     use rtic_monotonics::esp32c3::prelude::*;
+    use rtic_sync::{
+        channel::{Receiver, Sender},
+        make_channel, make_signal,
+        signal::{SignalReader, SignalWriter},
+    };
     use rtt_target::{rprintln, rtt_init_print};
+    use smart_leds::SmartLedsWrite;
+    use portable_atomic::Ordering;
+    use the_protocol::chrono::{TimeDelta, Timelike};
 
     // Register SysTimer as the monotonic timer for this platform
     esp32c3_systimer_monotonic!(Mono);
 
+    /// Gap between received bytes after which a partial frame is discarded. The host writes a
+    /// frame in one go, so bytes of one frame arrive well within this.
+    const FRAME_IDLE_TIMEOUT_MS: u64 = 50;
+
+    /// Interval at which the schedule is checked for jobs that are due
+    const SCHEDULE_POLL_MS: u64 = 10;
+
+    /// Capacity of the queue of replies and events waiting to be encoded into frames
+    const REPLY_QUEUE_LEN: usize = 4;
+    /// Capacity of the queue of button presses waiting to be executed. Presses made while the
+    /// queue is full are ignored.
+    const PRESS_QUEUE_LEN: usize = 2;
+
+    /// Replies in flight on the sliding-window link before the host must acknowledge
+    const LINK_WINDOW: usize = 4;
+    /// Time after which replies that the host has not acknowledged are sent again, until the
+    /// round-trip time has been measured, and the shortest and longest such time
+    const LINK_TIMEOUT_MS: u64 = 200;
+    const LINK_MIN_TIMEOUT_MS: u64 = 50;
+    const LINK_MAX_TIMEOUT_MS: u64 = 1_000;
+    /// Time to wait for room in the window, after which the host is considered gone and the
+    /// session ends. Part of [PROCESS_DEADLINE_MS], as processing waits for the replies.
+    const LINK_GIVE_UP_MS: u64 = 1_000;
+    /// Time to wait for the reply to [CommandV2::SetBaudRate] to be written, and acknowledged
+    /// during a session, before switching the baud rate anyway
+    const BAUD_DRAIN_MS: u64 = 500;
+
+    /// Time without feeding after which the watchdog resets the device
+    const WATCHDOG_TIMEOUT_MS: u64 = 3_000;
+    /// Interval at which the supervisor checks the tasks and feeds the watchdog
+    const SUPERVISOR_PERIOD_MS: u64 = 500;
+    /// Time allowed for executing a command and writing its replies, besides waiting on the host:
+    /// the largest reply takes 200 ms at the slowest baud rate
+    const PROCESS_SLACK_MS: u64 = 1_000;
+    /// Time allowed for processing a command, including waiting for room for its replies: the
+    /// reply and the two events that may follow it, `Rebooted` and `ClockUnset`, may each wait
+    /// [LINK_GIVE_UP_MS] for a slow host, and a baud rate switch waits up to [BAUD_DRAIN_MS].
+    /// 3 * 1000 + 500 + 1000 = 4500 ms.
+    const PROCESS_DEADLINE_MS: u64 = 3 * LINK_GIVE_UP_MS + BAUD_DRAIN_MS + PROCESS_SLACK_MS;
+    /// Time allowed for `blink_led` to switch the LED after a step of the pattern has passed
+    const BLINK_SLACK_MS: u64 = 500;
+
+    /// Time without UART activity after which changed state is saved to flash. Writing to flash
+    /// masks interrupts, so bytes that arrive meanwhile are lost.
+    const SAVE_IDLE_MS: u64 = 100;
+    /// Longest time that changed state waits for the UART to go idle before it is saved anyway
+    const SAVE_MAX_DELAY_MS: u64 = 5_000;
+
+    /// Interval at which the time of day is checked for a change of colour of the RGB led
+    const TIME_OF_DAY_POLL_MS: u64 = 1_000;
+
+    /// Chain of RGB leds on GPIO2
+    type RgbLed =
+        SmartLedsAdapter<ConstChannelAccess<esp_hal::rmt::Tx, 0>, { buffer_size(rgb::LEDS) }>;
+
+    /// A command and the protocol revision to reply with. The revision is `None` for commands
+    /// issued by the device itself, e.g., by the schedule, which are not replied to.
+    type Work = (CommandV2, Option<Version>);
+
+    /// Longest frame from the host, a [Request] in a link frame, authenticated or encrypted with
+    /// those features
+    const RX_FRAME_LEN: usize =
+        security::max_frame_len(link::max_frame_len(Request::MAX_SERIALIZED_LEN));
+
+    /// End of the sliding-window link of the device, which keeps the replies in flight
+    type DeviceLink = Link<LINK_WINDOW, { DeviceMessage::MAX_SERIALIZED_LEN }>;
+
     #[local]
     struct Local {
         /// UART RX receives bytes which are framed into COBS packets
         uart_rx: UartRx<'static, Blocking>,
         /// [the_protocol_serde::Response]'s are sent back over UART TX
-        _uart_tx: UartTx<'static, Blocking>,
-        /// RGB led for showing the time of day
-        _rgb_led: SmartLedsAdapter<ConstChannelAccess<esp_hal::rmt::Tx, 0>, 25>,
+        transmitter: Transmitter,
+        /// RGB leds for showing the time of day and effects
+        rgb_led: RgbLed,
         
-        // TODO: add missing local resources here as needed TODO: aggregation
-        // buffer for commands which are received byte by byte
-        cmd_buf: [u8; Command::MAX_SERIALIZED_LEN],
-        /// Current length of cmd_buf.
-        cmd_len: usize,
+        // TODO: add missing local resources here as needed
+        /// Aggregates commands which are received byte by byte, as plain or link frames
+        receiver: FrameReceiver<RX_FRAME_LEN>,
+        /// Queue of received commands, processed by `process_command`
+        commands: Sender<'static, Work, COMMAND_QUEUE_LEN>,
+        /// Queue of replies and events, encoded by `send_response`
+        replies: Sender<'static, DeviceMessage, REPLY_QUEUE_LEN>,
+        /// Wakes up `send_response` when the host acknowledges replies
+        window_open: SignalWriter<'static, ()>,
+        /// Wakes up `run_link` when a link frame arrives
+        link_changed: SignalWriter<'static, ()>,
+        /// Wakes up `save_nonce_mark` with a nonce mark to save
+        nonce_mark: SignalWriter<'static, u64>,
+        /// Wakes up `save_state` when a command may have changed the saved state
+        state_changed: SignalWriter<'static, ()>,
+        /// Wakes up `blink_led` when a command changes the blink period or pattern
+        blink_changed: SignalWriter<'static, ()>,
+        /// Wakes up `run_rgb` when a command changes what the RGB led shows
+        rgb_changed: SignalWriter<'static, ()>,
+        /// Resets the device unless fed by `supervise`
+        watchdog: Wdt<TIMG0<'static>>,
+        /// BOOT button of the operator console
+        button: Input<'static>,
+        /// Turns the edges of `button` into presses
+        debouncer: Debouncer,
+        /// Queue of button presses, executed by `run_console`
+        presses: Sender<'static, Press, PRESS_QUEUE_LEN>,
     }
 
     #[shared]
@@ -62,10 +220,31 @@ mod app {
         // TODO: add missing shared resources here as needed
         /// Blink period in milliseconds. 0 = disabled.
         led_interval_ms: u64,
+        /// Pattern that replaces the blink period while set
+        blink_pattern: Option<BlinkPattern>,
+        /// What the RGB led shows
+        rgb_mode: RgbMode,
+        /// Brightness of the RGB led, at most [rgb::MAX_BRIGHTNESS]
+        rgb_brightness: u8,
         /// Global counter for C2 / C9.
         counter: u64,
         /// LED output pin.
         led_pin: Output<'static>,
+        /// Reference time set by the host and the instant at which it was set. `None` if the time
+        /// has not been set.
+        date_time: Option<(SDateTime, <Mono as Monotonic>::Instant)>,
+        /// Jobs scheduled by the host
+        schedule: Schedule,
+        /// Whether the host has subscribed to [Event]s
+        events_subscribed: bool,
+        /// Sliding-window link, see [link]
+        link: DeviceLink,
+        /// Whether the host talks over the link. The latest frame from the host decides.
+        in_session: bool,
+        /// Baud rate of UART0
+        baud: BaudRate,
+        /// Saves the state of the device and the nonce mark to flash
+        persistence: Persistence,
     }
 
     #[init]
@@ -85,16 +264,30 @@ mod app {
 
         let peripherals = esp_hal::init(esp_hal::Config::default());
 
+        // Reset the device if the supervisor stops feeding the watchdog. The default action of the
+        // first stage is a system reset, which is reported as a watchdog reset after the boot.
+        let mut watchdog = TimerGroup::new(peripherals.TIMG0).wdt;
+        watchdog.set_timeout(
+            MwdtStage::Stage0,
+            time::Duration::from_millis(WATCHDOG_TIMEOUT_MS),
+        );
+        watchdog.enable();
+
+        // Pick up where the device left off before the reset
+        let (mut persistence, state) = Persistence::restore();
+
         let rmt = Rmt::new(peripherals.RMT, time::Rate::from_mhz(80u32)).unwrap();
         // We use one of the RMT channels to instantiate a `SmartLedsAdapter` which can be used
         // directly with all `smart_led` implementations
-        let rmt_buffer = smart_led_buffer!(1);
+        let rmt_buffer = smart_led_buffer!(rgb::LEDS);
         let rgb_led = SmartLedsAdapter::new(rmt.channel0, peripherals.GPIO2, rmt_buffer);
 
         let (tx, rx) = (peripherals.GPIO21, peripherals.GPIO20);
         let mut serial = Uart::<'static>::new(
             peripherals.UART0,
-            uart::Config::default().with_rx(uart::RxConfig::default().with_fifo_full_threshold(1)),
+            uart::Config::default()
+                .with_baudrate(DEFAULT_BAUD_RATE)
+                .with_rx(uart::RxConfig::default().with_fifo_full_threshold(1)),
         )
         .unwrap()
         .with_rx(rx)
@@ -102,28 +295,102 @@ mod app {
         serial.listen(uart::UartInterrupt::RxFifoFull);
 
         let (uart_rx, uart_tx) = serial.split();
+        // The TRNG takes the ADC for the entropy of the handshakes, see [Security::new]
+        let rng = Trng::new(peripherals.RNG, peripherals.ADC1);
+        let security = Security::new(rng, persistence.nonce_mark());
+        // Accept frames only once a mark above their nonces is saved, see [Security::nonce_mark]
+        if let Some(mark) = security.nonce_mark() {
+            if persistence.save_nonce_mark(mark) {
+                security::nonce_mark_saved(mark);
+            }
+        }
 
         // LED on GPIO7
         let cfg = OutputConfig::default();
         let led_pin = Output::new(peripherals.GPIO7, esp_hal::gpio::Level::Low, cfg);
 
-        // Start the async blink loop task
-        blink_led::spawn().ok();
+        // BOOT button on GPIO9, low while pressed
+        let mut button = Input::new(
+            peripherals.GPIO9,
+            InputConfig::default().with_pull(Pull::Up),
+        );
+        button.listen(gpio::Event::AnyEdge);
+
+        // Commands flow from `on_uart`, `run_console` and `run_schedule` to `process_command`, replies from
+        // `on_uart` and `process_command` to `send_response`, and encoded frames from
+        // `send_response` and `run_link` back to the transmitter in `on_uart`
+        let (commands, command_rx) = make_channel!(Work, COMMAND_QUEUE_LEN);
+        let (replies, reply_rx) = make_channel!(DeviceMessage, REPLY_QUEUE_LEN);
+        let (frames, frame_rx) = make_channel!(Frame, FRAME_QUEUE_LEN);
+        process_command::spawn(command_rx, replies.clone()).ok();
+        // Acknowledgements from the host open the window of `send_response`, and any link frame
+        // may call for an acknowledgement or a retransmission by `run_link`
+        let (window_open, window_rx) = make_signal!(());
+        let (link_changed, link_rx) = make_signal!(());
+        send_response::spawn(reply_rx, frames.clone(), window_rx, link_changed.clone()).ok();
+        run_link::spawn(frames, link_rx).ok();
+
+        // Start saving the state and the nonce mark to flash
+        let (state_changed, state_rx) = make_signal!(());
+        save_state::spawn(state_rx).ok();
+        let (nonce_mark, nonce_mark_rx) = make_signal!(u64);
+        save_nonce_mark::spawn(nonce_mark_rx).ok();
+
+        // Start the LED driver
+        let (blink_changed, blink_rx) = make_signal!(());
+        blink_led::spawn(blink_rx).ok();
+        // Start the RGB effects engine
+        let (rgb_changed, rgb_rx) = make_signal!(());
+        run_rgb::spawn(rgb_rx).ok();
+        // Start the async schedule loop task
+        run_schedule::spawn(commands.clone(), replies.clone()).ok();
+        // Start the operator console
+        let (presses, press_rx) = make_channel!(Press, PRESS_QUEUE_LEN);
+        run_console::spawn(press_rx, commands.clone(), replies.clone()).ok();
+        // Start the watchdog supervisor
+        supervise::spawn().ok();
 
         rprintln!("`init`: exit");
 
         (
             Shared {
-                led_interval_ms: 0,
-                counter: 0,
+                led_interval_ms: state.led_interval_ms,
+                blink_pattern: None,
+                rgb_mode: RgbMode::Effect(RgbEffect::Off),
+                rgb_brightness: rgb::MAX_BRIGHTNESS,
+                counter: state.counter,
                 led_pin,
+                date_time: None,
+                schedule: state.schedule,
+                events_subscribed: false,
+                link: Link::new(link::Config {
+                    window: LINK_WINDOW,
+                    timeout_ms: LINK_TIMEOUT_MS,
+                    min_timeout_ms: LINK_MIN_TIMEOUT_MS,
+                    max_timeout_ms: LINK_MAX_TIMEOUT_MS,
+                    nak: true,
+                }),
+                in_session: false,
+                baud: BaudRate::new(),
+                persistence,
             },
             Local {
-                _uart_tx: uart_tx,
+                transmitter: Transmitter::new(uart_tx, frame_rx, security),
                 uart_rx,
-                _rgb_led: rgb_led,
-                cmd_buf: [0; Command::MAX_SERIALIZED_LEN],
-                cmd_len: 0,
+                rgb_led,
+                receiver: FrameReceiver::new(FRAME_IDLE_TIMEOUT_MS),
+                commands,
+                replies,
+                window_open,
+                link_changed,
+                nonce_mark,
+                state_changed,
+                blink_changed,
+                rgb_changed,
+                watchdog,
+                button,
+                debouncer: Debouncer::new(),
+                presses,
             },
         )
     }
@@ -142,10 +409,29 @@ mod app {
         }
     }
 
-    /// On UART0, aggregate incoming byte(s) to a buffer
-    #[task(binds = UART0, priority = 3, local = [ uart_rx, cmd_buf, cmd_len ], shared = [])]
-    fn receive_byte(cx: receive_byte::Context) {
-        rprintln!("`receive_byte`: enter");
+    /// On UART0, aggregate incoming byte(s) to a buffer and refill the TX FIFO with outgoing
+    /// frames
+    #[task(
+        binds = UART0,
+        priority = 3,
+        local = [
+            uart_rx,
+            transmitter,
+            receiver,
+            commands,
+            replies,
+            window_open,
+            link_changed,
+            nonce_mark,
+        ],
+        shared = [link, in_session, baud]
+    )]
+    fn on_uart(mut cx: on_uart::Context) {
+        rprintln!("`on_uart`: enter");
+        supervisor::rx_interrupt();
+
+        // RX and TX share the interrupt
+        cx.local.transmitter.on_interrupt();
 
         // Unpend the interrupt. This is necessary to prevent the interrupt from
         // re-firing after this task completes.
@@ -153,143 +439,934 @@ mod app {
 
         let rx = cx.local.uart_rx;
         let unit_buf = &mut [0; 1];
-        while let Result::Ok(1) = rx.read_buffered(unit_buf) {
-            let byte = unit_buf[0];
-
-            // TODO: aggregate bytes, construct a command
+        loop {
+            let byte = match rx.read_buffered(unit_buf) {
+                Ok(1) => unit_buf[0],
+                Ok(_) => break,
+                Err(e) => {
+                    if e == RxError::FifoOverflowed {
+                        count(&diagnostics::UART_OVERRUNS);
+                    }
+                    break;
+                }
+            };
 
-            if *cx.local.cmd_len < cx.local.cmd_buf.len() {
-                cx.local.cmd_buf[*cx.local.cmd_len] = byte;
-                *cx.local.cmd_len += 1;
-            } else {
-                // Buffer overflow -> corrupted frame
-                send_response::spawn(Response::Rejected(RejectReason::CorruptedFrame)).ok();
-                *cx.local.cmd_len = 0;
-                return;
+            let now = Mono::now().duration_since_epoch().to_millis();
+            if cx.local.receiver.expire(now) {
+                // The host stopped sending mid-frame, drop the leftovers rather than prepend
+                // them to this frame
+                rprintln!("discarded stale partial frame");
+                count(&diagnostics::STALE_FRAMES);
             }
 
-            if serial::is_termination_byte(byte) {
-                // Copy frame into an independent buffer for in-place deserialization
-                let mut frame = [0u8; Command::MAX_SERIALIZED_LEN];
-                let len = *cx.local.cmd_len;
-                frame[..len].copy_from_slice(&cx.local.cmd_buf[..len]);
-
-                // Reset original buffer
-                *cx.local.cmd_len = 0;
-                cx.local.cmd_buf.fill(0);
+            let frame = match cx.local.receiver.push(byte, now) {
+                Received::Pending => {
+                    rprintln!("received byte: {}", byte);
+                    continue;
+                }
+                Received::Frame(frame) => frame,
+                Received::Overflow => {
+                    // Every request fits the buffer by construction, so the bytes were not a
+                    // request -> corrupted frame. The receiver drops the rest of the frame, so it
+                    // is answered once, and the bytes after it are read on.
+                    count(&diagnostics::CORRUPTED_FRAMES);
+                    reject_corrupted(&mut cx);
+                    continue;
+                }
+            };
 
-                // Decode from independent frame
-                match Command::deserialize_in_place(&mut frame[..len]) {
-                    Ok(cmd) => {
-                        process_command::spawn(cmd).ok();
+            count(&diagnostics::FRAMES_RECEIVED);
+            let opened = cx.local.transmitter.open(frame);
+            // Keep the saved nonce mark ahead of the frames
+            if let Some(mark) = cx.local.transmitter.nonce_mark() {
+                cx.local.nonce_mark.write(mark);
+            }
+            let frame = match opened {
+                Ok(Some(frame)) => frame,
+                // A handshake, which the transmitter answers
+                Ok(None) => continue,
+                Err(DeserializeError::Unauthenticated) => {
+                    rprintln!("rejected unauthenticated frame");
+                    count(&diagnostics::AUTH_FAILURES);
+                    reject_unauthenticated(&mut cx);
+                    continue;
+                }
+                Err(_) => {
+                    count(&diagnostics::CORRUPTED_FRAMES);
+                    reject_corrupted(&mut cx);
+                    continue;
+                }
+            };
+            // Decode the frame in place. The frame may carry either a legacy or an extended
+            // command, on its own or in a link frame.
+            let (bytes, plain) = match link::unpack(frame) {
+                // A link frame with a damaged tag reads as a plain frame, so the session only
+                // ends once the frame turns out to carry a command
+                Ok(Packet::Plain(bytes)) => (bytes, true),
+                Ok(Packet::Link(header, payload)) => {
+                    cx.shared.baud.lock(|b| b.confirm());
+                    cx.shared.in_session.lock(|s| *s = true);
+                    let delivered = cx
+                        .shared
+                        .link
+                        .lock(|link| link.receive(header, payload, now));
+                    cx.local.window_open.write(());
+                    cx.local.link_changed.write(());
+                    match delivered {
+                        Some(bytes) => (bytes, false),
+                        // Acknowledgements, duplicates and frames out of order carry no command
+                        None => continue,
                     }
-                    Err(_) => {
-                        send_response::spawn(Response::Rejected(RejectReason::CorruptedFrame)).ok();
+                }
+                Err(_) => {
+                    count(&diagnostics::CORRUPTED_FRAMES);
+                    reject_corrupted(&mut cx);
+                    continue;
+                }
+            };
+            match DefaultFormat::from_slice::<Request>(bytes) {
+                Ok(req) => {
+                    if plain {
+                        // A plain command ends the session, the host waits for a plain reply
+                        cx.shared.in_session.lock(|s| *s = false);
+                    }
+                    // A valid frame confirms a new baud rate
+                    cx.shared.baud.lock(|b| b.confirm());
+                    // Reject rather than drop the command when processing is falling behind
+                    let commands = &mut cx.local.commands;
+                    if let Some(busy) = admit(req, |cmd, ver| commands.try_send((cmd, Some(ver)))) {
+                        count(&diagnostics::BUSY_REJECTIONS);
+                        try_reply(cx.local.replies, busy);
                     }
                 }
-                rprintln!("received termination byte ({})", byte);
-            } else {
-                rprintln!("received byte: {}", byte);
+                Err(_) => {
+                    count(&diagnostics::CORRUPTED_FRAMES);
+                    reject_corrupted(&mut cx);
+                }
             }
+            rprintln!("received termination byte ({})", byte);
+        }
+
+        rprintln!("on_uart: exit");
+    }
+
+    /// Answers a corrupted frame. While the host talks over the link, `run_link` asks for the
+    /// frame again with a NAK instead, as the host would take a reply for the reply to a command.
+    fn reject_corrupted(cx: &mut on_uart::Context) {
+        if cx.shared.in_session.lock(|s| *s) {
+            cx.shared.link.lock(|link| link.reject());
+            cx.local.link_changed.write(());
+            return;
+        }
+        // The revision of a corrupted frame is unknown, so reply with the legacy encoding which is
+        // understood by all hosts
+        try_reply(
+            cx.local.replies,
+            Reply::Legacy(Response::Rejected(RejectReason::CorruptedFrame)),
+        );
+    }
+
+    /// Answers a frame that failed verification or was a replay, e.g., one sent before a reset
+    /// with a nonce below the saved mark. The host sends the command again with a new nonce. While
+    /// the host talks over the link, `run_link` asks for the frame again with a NAK instead, which
+    /// has the same effect.
+    fn reject_unauthenticated(cx: &mut on_uart::Context) {
+        if cx.shared.in_session.lock(|s| *s) {
+            cx.shared.link.lock(|link| link.reject());
+            cx.local.link_changed.write(());
+            return;
         }
+        // The revision of the frame is unknown, but hosts that authenticate frames understand the
+        // extended encoding
+        try_reply(cx.local.replies, Reply::V2(ResponseV2::Unauthenticated));
+    }
 
-        rprintln!("receive_byte: exit");
+    /// Queues a reply without waiting for room in the queue, as `on_uart` cannot wait. The
+    /// reply is dropped if the queue is full.
+    fn try_reply(replies: &mut Sender<'static, DeviceMessage, REPLY_QUEUE_LEN>, reply: Reply) {
+        if replies.try_send(reply.into()).is_err() {
+            count(&diagnostics::DROPPED_REPLIES);
+        }
+    }
+
+    // ======================= OPERATOR CONSOLE =========================
+    /// On an edge of the BOOT button, queue the press that it completes
+    #[task(binds = GPIO, priority = 3, local = [button, debouncer, presses])]
+    fn on_button(cx: on_button::Context) {
+        cx.local.button.clear_interrupt();
+        let now = Mono::now().duration_since_epoch().to_millis();
+        if let Some(press) = cx.local.debouncer.edge(cx.local.button.is_low(), now) {
+            rprintln!("button: {:?} press", press);
+            cx.local.presses.try_send(press).ok();
+        }
+    }
+
+    /// Executes button presses through the command queue and reports them to the host
+    #[task(shared = [led_interval_ms, blink_pattern, events_subscribed], priority = 1)]
+    async fn run_console(
+        mut cx: run_console::Context,
+        mut presses: Receiver<'static, Press, PRESS_QUEUE_LEN>,
+        mut commands: Sender<'static, Work, COMMAND_QUEUE_LEN>,
+        mut replies: Sender<'static, DeviceMessage, REPLY_QUEUE_LEN>,
+    ) {
+        while let Ok(press) = presses.recv().await {
+            let blinking = cx.shared.led_interval_ms.lock(|v| *v != 0)
+                || cx.shared.blink_pattern.lock(|p| p.is_some());
+            let funct = console::funct(press, blinking);
+            // Wait for room in the queue rather than drop the press
+            commands
+                .send((CommandV2::Immediate(funct.clone()), None))
+                .await
+                .ok();
+            if cx.shared.events_subscribed.lock(|v| *v) {
+                let event = Event::ButtonPressed { press, funct };
+                replies.send(event.into()).await.ok();
+            }
+        }
     }
 
     // ======================= SEND RESPONSE ============================
-    #[task(local = [_uart_tx], priority = 2)]
-    async fn send_response(cx: send_response::Context, resp: Response) {
-        // Using the serial helper which writes the serialized response
-        // to UART.
-        serial::send_response(resp, cx.local._uart_tx);
+    /// Encodes replies and events into plain frames, or into data frames of the link while the
+    /// host talks over it
+    #[task(shared = [link, in_session], priority = 2)]
+    async fn send_response(
+        mut cx: send_response::Context,
+        mut replies: Receiver<'static, DeviceMessage, REPLY_QUEUE_LEN>,
+        mut frames: Sender<'static, Frame, FRAME_QUEUE_LEN>,
+        mut window_open: SignalReader<'static, ()>,
+        mut link_changed: SignalWriter<'static, ()>,
+    ) {
+        while let Ok(msg) = replies.recv().await {
+            let frame = if cx.shared.in_session.lock(|s| *s) {
+                link_frame(&mut cx, msg, &mut window_open).await
+            } else {
+                Frame::encode(msg)
+            };
+            // Wait for room in the frame queue, which fills while the transmitter is busy
+            if frames.send(frame).await.is_err() {
+                rprintln!("transmitter is gone");
+                count(&diagnostics::WRITE_ERRORS);
+                continue;
+            }
+            // Start the retransmission timer of `run_link`
+            link_changed.write(());
+            serial::start_transmit();
+        }
+    }
+
+    /// Puts a reply into the window of the link and encodes it into a data frame. Waits for the
+    /// host to acknowledge earlier replies while the window is full, and ends the session if the
+    /// host does not within [LINK_GIVE_UP_MS].
+    async fn link_frame(
+        cx: &mut send_response::Context<'_>,
+        msg: DeviceMessage,
+        window_open: &mut SignalReader<'static, ()>,
+    ) -> Frame {
+        let give_up = Mono::now() + LINK_GIVE_UP_MS.millis();
+        while !cx.shared.link.lock(|link| link.can_send()) {
+            if Mono::timeout_at(give_up, window_open.wait()).await.is_err() {
+                rprintln!("host stopped acknowledging, ending the session");
+                count(&diagnostics::DROPPED_REPLIES);
+                cx.shared.link.lock(|link| link.reset());
+                cx.shared.in_session.lock(|s| *s = false);
+                return Frame::encode(msg);
+            }
+        }
+        let now = Mono::now().duration_since_epoch().to_millis();
+        cx.shared.link.lock(|link| {
+            let seq = link
+                .send(&msg, now)
+                // There is no way to recover from this, nor should it ever fail
+                .expect("unable to serialize response");
+            Frame::encode_with(|buf| link.frame(seq, buf))
+        })
     }
 
+    /// Sends the frames of the link other than replies: acknowledgements and NAKs of the frames
+    /// from the host, and the replies that the host has asked for or not acknowledged in time,
+    /// again
+    #[task(shared = [link], priority = 2)]
+    async fn run_link(
+        mut cx: run_link::Context,
+        mut frames: Sender<'static, Frame, FRAME_QUEUE_LEN>,
+        mut changed: SignalReader<'static, ()>,
+    ) {
+        loop {
+            let now = Mono::now().duration_since_epoch().to_millis();
+            match cx.shared.link.lock(|link| link.deadline()) {
+                Some(deadline) => {
+                    let wait = deadline.saturating_sub(now);
+                    Mono::timeout_after(wait.millis(), changed.wait())
+                        .await
+                        .ok();
+                }
+                None => changed.wait().await,
+            }
+
+            let now = Mono::now().duration_since_epoch().to_millis();
+            if cx.shared.link.lock(|link| link.poll(now)) {
+                for seq in cx.shared.link.lock(|link| link.unacked()) {
+                    // The host may acknowledge the frame in the meantime
+                    let frame = cx.shared.link.lock(|link| {
+                        link.unacked()
+                            .any(|s| s == seq)
+                            .then(|| Frame::encode_with(|buf| link.frame(seq, buf)))
+                    });
+                    let Some(frame) = frame else {
+                        continue;
+                    };
+                    count(&diagnostics::RETRANSMISSIONS);
+                    if frames.send(frame).await.is_err() {
+                        count(&diagnostics::WRITE_ERRORS);
+                    }
+                    serial::start_transmit();
+                }
+            }
+            // Data frames carry the acknowledgement too, but the host may have nothing to send
+            let ack = cx.shared.link.lock(|link| {
+                link.needs_ack()
+                    .then(|| Frame::encode_with(|buf| link.ack_frame(buf)))
+            });
+            if let Some(frame) = ack {
+                if frames.send(frame).await.is_err() {
+                    count(&diagnostics::WRITE_ERRORS);
+                }
+                serial::start_transmit();
+            }
+        }
+    }
 
     // ======================= PROCESS COMMAND ==========================
-    #[task(priority = 2)]
-    async fn process_command(_: process_command::Context, cmd: Command) {
-        match cmd {
-            Command::Reset => {
-                rprintln!("Command recieved - Reset");
-                reset::spawn().ok();
+    #[task(
+        priority = 2,
+        local = [state_changed, blink_changed, rgb_changed],
+        shared = [
+            led_interval_ms,
+            blink_pattern,
+            rgb_mode,
+            rgb_brightness,
+            counter,
+            led_pin,
+            date_time,
+            schedule,
+            events_subscribed,
+            link,
+            in_session,
+            baud
+        ]
+    )]
+    async fn process_command(
+        mut cx: process_command::Context,
+        mut commands: Receiver<'static, Work, COMMAND_QUEUE_LEN>,
+        mut replies: Sender<'static, DeviceMessage, REPLY_QUEUE_LEN>,
+    ) {
+        // The host is told about the boot once, after it first subscribes to events
+        let mut reboot_reported = false;
+        loop {
+            supervisor::PROCESS.idle();
+            let Ok((cmd, ver)) = commands.recv().await else {
+                break;
+            };
+            let now = Mono::now().duration_since_epoch().to_millis();
+            supervisor::PROCESS.expect(now, PROCESS_DEADLINE_MS);
+
+            let had_clock = cx.shared.date_time.lock(|v| v.is_some());
+            let new_baud = match cmd {
+                CommandV2::SetBaudRate(rate) => Some(rate),
+                _ => None,
+            };
+            let blink = blink_state(&mut cx.shared);
+            let rgb = rgb_state(&mut cx.shared);
+            let resp = execute(&mut cx.shared, cmd, ver);
+            let new_baud = new_baud.filter(|_| matches!(resp, ResponseV2::Ok(_)));
+            if blink_state(&mut cx.shared) != blink {
+                cx.local.blink_changed.write(());
+            }
+            if rgb_state(&mut cx.shared) != rgb {
+                cx.local.rgb_changed.write(());
             }
-            Command::Counter => {
-                get_counter::spawn().ok();
+            cx.local.state_changed.write(());
+            if let Some(ver) = ver {
+                // Wait for room in the reply queue, which holds back the processing of further
+                // commands until the replies have been written
+                replies.send(Reply::new(ver, resp).into()).await.ok();
             }
-            Command::Immediate(f) => {
-                process_funct::spawn(f).ok();
+            if let Some(rate) = new_baud {
+                switch_baud_rate(&mut cx.shared, &replies, rate).await;
             }
-            _ => {
-                send_response::spawn(Response::Rejected(RejectReason::NotImplemented)).ok();
+
+            if !cx.shared.events_subscribed.lock(|v| *v) {
+                continue;
+            }
+            if !reboot_reported {
+                reboot_reported = true;
+                let reason = diagnostics::reset_reason();
+                replies.send(Event::Rebooted { reason }.into()).await.ok();
+            }
+            if had_clock && cx.shared.date_time.lock(|v| v.is_none()) {
+                replies.send(Event::ClockUnset.into()).await.ok();
             }
         }
     }
 
+    /// Shared resources available to command processing
+    type Resources<'a> = process_command::SharedResources<'a>;
 
-        // ======================= FUNCT HANDLER ============================
-    #[task(priority = 2, shared = [led_interval_ms, counter])]
-    async fn process_funct(mut _cx: process_funct::Context, f: Funct) {
-        match f {
-            Funct::Increment => {
-                increment_counter::spawn().ok();
+    /// Switches to the baud rate once the replies queued so far have been written, and
+    /// acknowledged by the host during a session, so that the host receives the acknowledgement of
+    /// the switch at the current rate
+    async fn switch_baud_rate(
+        res: &mut Resources<'_>,
+        replies: &Sender<'static, DeviceMessage, REPLY_QUEUE_LEN>,
+        rate: u32,
+    ) {
+        let give_up = Mono::now() + BAUD_DRAIN_MS.millis();
+        let drained = |res: &mut Resources| {
+            replies.is_empty()
+                && !serial::is_transmitting()
+                && (!res.in_session.lock(|s| *s) || res.link.lock(|link| link.is_idle()))
+        };
+        while !drained(res) && Mono::now() < give_up {
+            Mono::delay(1.millis()).await;
+        }
+        rprintln!("switching to {} baud", rate);
+        res.baud.lock(|b| b.switch(rate));
+        if fall_back_baud_rate::spawn().is_err() {
+            // Never keep a rate that nothing would fall back from. The host falls back too when
+            // it gets no reply at the new rate.
+            res.baud.lock(|b| b.fall_back());
+        }
+    }
+
+    /// Returns to the previous baud rate unless a valid frame arrives at the new one within
+    /// [BAUD_FALLBACK_MS]. Ends soon after the rate is confirmed, so that the next switch can
+    /// spawn it again.
+    #[task(shared = [baud], priority = 1)]
+    async fn fall_back_baud_rate(mut cx: fall_back_baud_rate::Context) {
+        let deadline = Mono::now() + BAUD_FALLBACK_MS.millis();
+        while Mono::now() < deadline {
+            if !cx.shared.baud.lock(|b| b.is_pending()) {
+                return;
             }
-            Funct::EnableBlink { period_ms } => {
-                set_led_interval::spawn(period_ms).ok();
+            Mono::delay(10.millis()).await;
+        }
+        if cx.shared.baud.lock(|b| b.fall_back()) {
+            rprintln!("no valid frame at the new baud rate, falling back");
+        }
+    }
+
+    /// Blink period and pattern, which `blink_led` is told about when they change
+    fn blink_state(res: &mut Resources) -> (u64, Option<BlinkPattern>) {
+        (
+            res.led_interval_ms.lock(|v| *v),
+            res.blink_pattern.lock(|p| *p),
+        )
+    }
+
+    /// Mode and brightness of the RGB led, which `run_rgb` is told about when they change
+    fn rgb_state(res: &mut Resources) -> (RgbMode, u8) {
+        (res.rgb_mode.lock(|m| *m), res.rgb_brightness.lock(|b| *b))
+    }
+
+    /// Executes a command and returns the response for the host
+    ///
+    /// Legacy commands are processed as their extended counterpart. `ver` is the revision of the
+    /// request, or `None` if the command was issued by the device itself.
+    fn execute(res: &mut Resources, cmd: CommandV2, ver: Option<Version>) -> ResponseV2 {
+        // Validate the arguments before anything is executed. The commands of a batch are
+        // validated one by one as `execute_batch` executes them.
+        if !LIMITS.allows(&cmd) {
+            return ResponseV2::Rejected(RejectReason::IllegalCommand);
+        }
+        match cmd {
+            CommandV2::Reset => {
+                rprintln!("Command recieved - Reset");
+                reset(res)
             }
-            Funct::DisableBlink => {
-                set_led_interval::spawn(0).ok();
+            CommandV2::Counter => get_counter(res),
+            CommandV2::SetDateTime(dt) => set_date_time(res, dt),
+            CommandV2::Immediate(f) => execute_funct(res, f),
+            // The legacy schedule command has no return value
+            CommandV2::Schedule(f, at) => match schedule_job(res, f, at) {
+                ResponseV2::Ok(_) if ver != Some(Version::V2) => ResponseV2::Ok(None),
+                resp => resp,
+            },
+            CommandV2::ScheduleRecurring(f, recurrence) => schedule_recurring(res, f, recurrence),
+            CommandV2::CancelJob(id) => cancel_job(res, id),
+            CommandV2::ClearSchedule => clear_schedule(res),
+            CommandV2::ListSchedule { page } => list_schedule(res, page),
+            CommandV2::LedState => get_led_state(res),
+            CommandV2::DateTime => get_date_time(res),
+            CommandV2::SetCounter(v) => set_counter(res, v),
+            CommandV2::FirmwareInfo => ResponseV2::Ok(Some(PayloadV2::FirmwareInfo(FIRMWARE_INFO))),
+            CommandV2::Diagnostics => get_diagnostics(res),
+            CommandV2::Batch(batch) => execute_batch(res, batch),
+            CommandV2::SubscribeEvents(on) => subscribe_events(res, on),
+            CommandV2::SetBlinkPattern(pattern) => set_blink_pattern(res, pattern),
+            CommandV2::SetRgbEffect(effect) => set_rgb_effect(res, effect),
+            CommandV2::SetRgbBrightness(brightness) => set_rgb_brightness(res, brightness),
+            CommandV2::Limits => ResponseV2::Ok(Some(PayloadV2::Limits(LIMITS))),
+            // Switched by `process_command` after the reply, one switch at a time
+            CommandV2::SetBaudRate(_) if res.baud.lock(|b| b.is_pending()) => {
+                ResponseV2::Rejected(RejectReason::IllegalCommand)
             }
-            _ => {
-                send_response::spawn(Response::Rejected(RejectReason::NotImplemented)).ok();
+            CommandV2::SetBaudRate(_) => ResponseV2::Ok(None),
+            CommandV2::LinkStats => {
+                ResponseV2::Ok(Some(PayloadV2::LinkStats(res.link.lock(|l| l.stats()))))
+            }
+        }
+    }
+
+    // ========================== V2: Batch ============================
+    /// State that is restored when an atomic batch is rolled back
+    struct Snapshot {
+        led_interval_ms: u64,
+        blink_pattern: Option<BlinkPattern>,
+        /// Level of the LED, e.g., turned off by a reset in the batch
+        led_on: bool,
+        rgb_mode: RgbMode,
+        rgb_brightness: u8,
+        counter: u64,
+        date_time: Option<(SDateTime, <Mono as Monotonic>::Instant)>,
+        schedule: Schedule,
+    }
+
+    fn execute_batch(res: &mut Resources, batch: Batch) -> ResponseV2 {
+        let snapshot = (batch.mode == BatchMode::Atomic).then(|| Snapshot {
+            led_interval_ms: res.led_interval_ms.lock(|v| *v),
+            blink_pattern: res.blink_pattern.lock(|p| *p),
+            led_on: res.led_pin.lock(|p| p.is_set_high()),
+            rgb_mode: res.rgb_mode.lock(|m| *m),
+            rgb_brightness: res.rgb_brightness.lock(|b| *b),
+            counter: res.counter.lock(|c| *c),
+            date_time: res.date_time.lock(|v| v.clone()),
+            schedule: res.schedule.lock(|s| s.clone()),
+        });
+
+        let mut resp = BatchResponse::new();
+        for cmd in batch.iter() {
+            let cmd_resp = execute(res, cmd.clone().into(), Some(Version::Legacy));
+            let rejected = !cmd_resp.is_ok();
+            resp.push(cmd_resp);
+            match &snapshot {
+                // Roll back the effects of the preceding commands of an atomic batch
+                Some(snapshot) if rejected => {
+                    res.led_interval_ms.lock(|v| *v = snapshot.led_interval_ms);
+                    res.blink_pattern.lock(|p| *p = snapshot.blink_pattern);
+                    res.led_pin
+                        .lock(|p| p.set_level(Level::from(snapshot.led_on)));
+                    res.rgb_mode.lock(|m| *m = snapshot.rgb_mode);
+                    res.rgb_brightness.lock(|b| *b = snapshot.rgb_brightness);
+                    res.counter.lock(|c| *c = snapshot.counter);
+                    res.date_time.lock(|v| *v = snapshot.date_time.clone());
+                    res.schedule.lock(|s| *s = snapshot.schedule.clone());
+                    resp.committed = false;
+                    break;
+                }
+                _ => {}
             }
         }
+        ResponseV2::Ok(Some(PayloadV2::Batch(resp)))
+    }
+
+    // ======================= FUNCT HANDLER ============================
+    fn execute_funct(res: &mut Resources, f: Funct) -> ResponseV2 {
+        match f {
+            Funct::Increment => increment_counter(res),
+            Funct::EnableBlink { period_ms } => set_led_interval(res, period_ms),
+            Funct::DisableBlink => set_led_interval(res, 0),
+            Funct::EnableRgb => set_rgb_mode(res, RgbMode::TimeOfDay),
+            Funct::DisableRgb => set_rgb_mode(res, RgbMode::Effect(RgbEffect::Off)),
+        }
     }
 
     // =========================== C2: Increment =======================
-    #[task(priority = 2, shared = [counter])]
-    async fn increment_counter(mut cx: increment_counter::Context) {
-        cx.shared.counter.lock(|c| *c += 1);
-        send_response::spawn(Response::Ok(None)).ok();
+    fn increment_counter(res: &mut Resources) -> ResponseV2 {
+        res.counter.lock(|c| *c += 1);
+        ResponseV2::Ok(None)
     }
 
     // =========================== C9: Counter =========================
-    #[task(priority = 2, shared = [counter])]
-    async fn get_counter(mut cx: get_counter::Context) {
-        let v = cx.shared.counter.lock(|c| *c);
-        send_response::spawn(Response::Ok(Some(Payload::Counter(v)))).ok();
+    fn get_counter(res: &mut Resources) -> ResponseV2 {
+        let v = res.counter.lock(|c| *c);
+        ResponseV2::Ok(Some(PayloadV2::Counter(v)))
+    }
+
+    // ========================= V2: SetCounter ========================
+    fn set_counter(res: &mut Resources, v: u64) -> ResponseV2 {
+        res.counter.lock(|c| *c = v);
+        ResponseV2::Ok(None)
     }
 
     // =========================== C3/C4: Blink =======================
-    #[task(priority = 2, shared = [led_interval_ms])]
-    async fn set_led_interval(mut cx: set_led_interval::Context, ms: u64) {
-        cx.shared.led_interval_ms.lock(|v| *v = ms);
-        send_response::spawn(Response::Ok(None)).ok();
+    fn set_led_interval(res: &mut Resources, ms: u64) -> ResponseV2 {
+        res.led_interval_ms.lock(|v| *v = ms);
+        res.blink_pattern.lock(|p| *p = None);
+        ResponseV2::Ok(None)
     }
 
-    // =========================== C1: Reset ===========================
-    #[task(priority = 2, shared = [led_interval_ms, counter, led_pin])]
-    async fn reset(mut cx: reset::Context) {
-        cx.shared.led_interval_ms.lock(|v| *v = 0);
-        cx.shared.counter.lock(|c| *c = 0);
-        cx.shared.led_pin.lock(|p| p.set_low());
+    // ====================== V2: SetBlinkPattern ======================
+    fn set_blink_pattern(res: &mut Resources, pattern: BlinkPattern) -> ResponseV2 {
+        res.led_interval_ms.lock(|v| *v = 0);
+        res.blink_pattern.lock(|p| *p = Some(pattern));
+        ResponseV2::Ok(None)
+    }
+
+    // =========================== C5/C6: Rgb ==========================
+    fn set_rgb_mode(res: &mut Resources, mode: RgbMode) -> ResponseV2 {
+        res.rgb_mode.lock(|m| *m = mode);
+        ResponseV2::Ok(None)
+    }
+
+    // ======================== V2: SetRgbEffect =======================
+    fn set_rgb_effect(res: &mut Resources, effect: RgbEffect) -> ResponseV2 {
+        set_rgb_mode(res, RgbMode::Effect(effect))
+    }
 
-        send_response::spawn(Response::Ok(None)).ok();
+    // ====================== V2: SetRgbBrightness =====================
+    fn set_rgb_brightness(res: &mut Resources, brightness: u8) -> ResponseV2 {
+        res.rgb_brightness.lock(|b| *b = brightness);
+        ResponseV2::Ok(None)
     }
 
-    // ====================== BLINK LED LOOP ===========================
-   
-    #[task(shared = [led_interval_ms, led_pin], priority = 1)]
-    async fn blink_led(mut cx: blink_led::Context) {
+    // ========================= V2: LedState ==========================
+    fn get_led_state(res: &mut Resources) -> ResponseV2 {
+        let state = LedState {
+            blink_period_ms: res.led_interval_ms.lock(|v| *v),
+            is_on: res.led_pin.lock(|p| p.is_set_high()),
+        };
+        ResponseV2::Ok(Some(PayloadV2::LedState(state)))
+    }
+
+    // ========================= C8: SetDateTime =======================
+    fn set_date_time(res: &mut Resources, dt: Option<SDateTime>) -> ResponseV2 {
+        // A date-time that does not exist on the calendar was rejected by the limits
+        res.date_time.lock(|v| *v = dt.map(|dt| (dt, Mono::now())));
+        ResponseV2::Ok(None)
+    }
+
+    /// Advances the reference time set by the host by the time elapsed since it was set. Returns
+    /// `None` if the time is unset, or out of the range of dates.
+    fn current_time(
+        date_time: &Option<(SDateTime, <Mono as Monotonic>::Instant)>,
+    ) -> Option<SDateTime> {
+        let (dt, set_at) = date_time.as_ref()?;
+        let elapsed = (Mono::now() - *set_at).to_millis() as i64;
+        to_utc(dt)?
+            .checked_add_signed(TimeDelta::milliseconds(elapsed))
+            .map(SDateTime::from)
+    }
+
+    // ========================= V2: DateTime ==========================
+    fn get_date_time(res: &mut Resources) -> ResponseV2 {
+        let now = res.date_time.lock(|v| current_time(v));
+        ResponseV2::Ok(Some(PayloadV2::DateTime(now)))
+    }
+
+    // ========================= C7: Schedule ==========================
+    fn schedule_job(res: &mut Resources, f: Funct, at: SDateTime) -> ResponseV2 {
+        // Scheduling is only possible once the host has provided a reference time
+        if res.date_time.lock(|v| v.is_none()) {
+            return ResponseV2::Rejected(RejectReason::IllegalCommand);
+        }
+        match res.schedule.lock(|s| s.insert(f, at, None)) {
+            Ok(id) => ResponseV2::Ok(Some(PayloadV2::JobId(id))),
+            Err(_) => {
+                rprintln!("schedule is full");
+                ResponseV2::Rejected(RejectReason::IllegalCommand)
+            }
+        }
+    }
+
+    // ===================== V2: ScheduleRecurring =====================
+    fn schedule_recurring(res: &mut Resources, f: Funct, recurrence: Recurrence) -> ResponseV2 {
+        let first = res
+            .date_time
+            .lock(|v| current_time(v))
+            .and_then(|now| recurrence.first(&now));
+        // Rejected if the time is not set or there are no future occurrences
+        let Some(at) = first else {
+            return ResponseV2::Rejected(RejectReason::IllegalCommand);
+        };
+        match res.schedule.lock(|s| s.insert(f, at, Some(recurrence))) {
+            Ok(id) => ResponseV2::Ok(Some(PayloadV2::JobId(id))),
+            Err(_) => {
+                rprintln!("schedule is full");
+                ResponseV2::Rejected(RejectReason::IllegalCommand)
+            }
+        }
+    }
+
+    // ========================= V2: CancelJob =========================
+    fn cancel_job(res: &mut Resources, id: JobId) -> ResponseV2 {
+        if res.schedule.lock(|s| s.cancel(id)) {
+            ResponseV2::Ok(None)
+        } else {
+            ResponseV2::Rejected(RejectReason::IllegalCommand)
+        }
+    }
+
+    // ======================= V2: ClearSchedule =======================
+    fn clear_schedule(res: &mut Resources) -> ResponseV2 {
+        res.schedule.lock(|s| s.clear());
+        ResponseV2::Ok(None)
+    }
+
+    // ======================= V2: ListSchedule ========================
+    fn list_schedule(res: &mut Resources, page: u8) -> ResponseV2 {
+        let page = res.schedule.lock(|s| s.page(page));
+        ResponseV2::Ok(Some(PayloadV2::SchedulePage(page)))
+    }
+
+    // ====================== V2: SubscribeEvents ======================
+    fn subscribe_events(res: &mut Resources, on: bool) -> ResponseV2 {
+        res.events_subscribed.lock(|v| *v = on);
+        ResponseV2::Ok(None)
+    }
+
+    // ======================= V2: Diagnostics =========================
+    fn get_diagnostics(res: &mut Resources) -> ResponseV2 {
+        let load = |counter: &portable_atomic::AtomicU32| counter.load(Ordering::Relaxed);
+        let diag = Diagnostics {
+            uptime_ms: Mono::now().duration_since_epoch().to_millis(),
+            free_schedule_slots: (schedule::CAPACITY - res.schedule.lock(|s| s.len())) as u16,
+            frames_received: load(&diagnostics::FRAMES_RECEIVED),
+            corrupted_frames: load(&diagnostics::CORRUPTED_FRAMES),
+            stale_frames: load(&diagnostics::STALE_FRAMES),
+            uart_overruns: load(&diagnostics::UART_OVERRUNS),
+            busy_rejections: load(&diagnostics::BUSY_REJECTIONS),
+            dropped_replies: load(&diagnostics::DROPPED_REPLIES),
+            write_errors: load(&diagnostics::WRITE_ERRORS),
+            auth_failures: load(&diagnostics::AUTH_FAILURES),
+            reset_reason: diagnostics::reset_reason(),
+        };
+        ResponseV2::Ok(Some(PayloadV2::Diagnostics(diag)))
+    }
+
+    // =========================== C1: Reset ===========================
+    fn reset(res: &mut Resources) -> ResponseV2 {
+        res.led_interval_ms.lock(|v| *v = 0);
+        res.blink_pattern.lock(|p| *p = None);
+        res.rgb_mode.lock(|m| *m = RgbMode::Effect(RgbEffect::Off));
+        res.rgb_brightness.lock(|b| *b = rgb::MAX_BRIGHTNESS);
+        res.counter.lock(|c| *c = 0);
+        res.led_pin.lock(|p| p.set_low());
+        res.date_time.lock(|v| *v = None);
+        res.schedule.lock(|s| s.clear());
+        ResponseV2::Ok(None)
+    }
+
+    // ========================== LED DRIVER ===========================
+
+    /// Plays the blink pattern, or toggles the LED at the blink period, until either changes
+    ///
+    /// Each step ends at a fixed offset from the start of the pattern rather than from the time the
+    /// previous step was handled, so the timing is accurate to the tick of [Mono] and does not
+    /// drift. The task sleeps while the LED is off, until `changed` wakes it up.
+    #[task(shared = [led_interval_ms, blink_pattern, led_pin], priority = 1)]
+    async fn blink_led(mut cx: blink_led::Context, mut changed: SignalReader<'static, ()>) {
         loop {
-            let ms = cx.shared.led_interval_ms.lock(|v| *v);
+            let period_ms = cx.shared.led_interval_ms.lock(|v| *v);
+            let pattern = cx.shared.blink_pattern.lock(|p| *p).or_else(|| {
+                // The legacy period is a pattern with equal times on and off. The limits keep the
+                // period well within `u32`, except for a period restored from flash.
+                let ms = u32::try_from(period_ms).unwrap_or(u32::MAX);
+                (ms != 0).then(|| {
+                    BlinkPattern::forever(BlinkShape::Duty {
+                        on_ms: ms,
+                        off_ms: ms,
+                    })
+                })
+            });
 
-            if ms == 0 {
+            let mut at = Mono::now();
+            let mut interrupted = false;
+            for step in pattern.iter().flat_map(|p| p.steps()) {
+                let level = Level::from(step.on);
+                cx.shared.led_pin.lock(|p| p.set_level(level));
+                at += (step.ms as u64).millis();
+                let at_ms = at.duration_since_epoch().to_millis();
+                supervisor::BLINK.expect(at_ms, BLINK_SLACK_MS);
+                if Mono::timeout_at(at, changed.wait()).await.is_ok() {
+                    interrupted = true;
+                    break;
+                }
+            }
+
+            if !interrupted {
+                // Disabled, or the pattern has played to the end
                 cx.shared.led_pin.lock(|p| p.set_low());
-                Mono::delay(200.millis()).await;
+                supervisor::BLINK.idle();
+                changed.wait().await;
+            }
+        }
+    }
+
+    // ========================== RGB EFFECTS ==========================
+
+    /// Renders the effect of the RGB led into frames at a fixed frame rate while it is animated
+    ///
+    /// The task sleeps while the frame does not change, until `changed` wakes it up. The time of
+    /// day is checked every [TIME_OF_DAY_POLL_MS] while it is shown.
+    #[task(local = [rgb_led], shared = [rgb_mode, rgb_brightness, date_time], priority = 1)]
+    async fn run_rgb(mut cx: run_rgb::Context, mut changed: SignalReader<'static, ()>) {
+        let mut engine = Engine::<{ rgb::LEDS }>::new(rgb::MAX_BRIGHTNESS);
+        let mut next_frame = Mono::now();
+        loop {
+            let now = Mono::now();
+            let now_ms = now.duration_since_epoch().to_millis();
+            let mode = cx.shared.rgb_mode.lock(|m| *m);
+            let effect = match mode {
+                RgbMode::Effect(effect) => effect,
+                RgbMode::TimeOfDay => match cx
+                    .shared
+                    .date_time
+                    .lock(|v| current_time(v))
+                    .and_then(|now| to_utc(&now))
+                {
+                    Some(now) => RgbEffect::Fade {
+                        to: rgb::time_of_day_color(now.hour()),
+                        duration_ms: rgb::TIME_OF_DAY_FADE_MS,
+                    },
+                    None => RgbEffect::Off,
+                },
+            };
+            if effect != engine.effect() {
+                engine.set_effect(effect, now_ms);
+            }
+            // The brightness was checked against the limit when it was set
+            engine.set_brightness(cx.shared.rgb_brightness.lock(|b| *b));
+
+            let frame = engine.render(now_ms);
+            if let Err(e) = cx.local.rgb_led.write(frame) {
+                rprintln!("unable to write RGB led: {:?}", e);
+            }
+
+            let wake = if engine.is_animated(now_ms) {
+                // Keep a fixed frame rate, but skip the frames that are already late
+                next_frame = (next_frame + FRAME_INTERVAL_MS.millis()).max(now);
+                Some(next_frame)
+            } else if mode == RgbMode::TimeOfDay {
+                Some(now + TIME_OF_DAY_POLL_MS.millis())
+            } else {
+                None
+            };
+            match wake {
+                Some(at) => {
+                    Mono::timeout_at(at, changed.wait()).await.ok();
+                }
+                None => {
+                    changed.wait().await;
+                    next_frame = Mono::now();
+                }
+            }
+        }
+    }
+
+    // ====================== SCHEDULE LOOP ============================
+
+    #[task(shared = [date_time, schedule, events_subscribed], priority = 1)]
+    async fn run_schedule(
+        mut cx: run_schedule::Context,
+        mut commands: Sender<'static, Work, COMMAND_QUEUE_LEN>,
+        mut replies: Sender<'static, DeviceMessage, REPLY_QUEUE_LEN>,
+    ) {
+        loop {
+            // Jobs cannot fire while the time is unset
+            if let Some(now) = cx.shared.date_time.lock(|v| current_time(v)) {
+                while let Some(job) = cx.shared.schedule.lock(|s| s.pop_due(&now)) {
+                    rprintln!("job {} fired", job.id);
+                    // Wait for room in the queue rather than skip the job
+                    commands
+                        .send((CommandV2::Immediate(job.funct), None))
+                        .await
+                        .ok();
+                    if cx.shared.events_subscribed.lock(|v| *v) {
+                        let event = Event::JobFired {
+                            id: job.id,
+                            at: job.at,
+                        };
+                        replies.send(event.into()).await.ok();
+                    }
+                }
+            }
+            Mono::delay(SCHEDULE_POLL_MS.millis()).await;
+        }
+    }
+
+    // ========================== PERSISTENCE ==========================
+
+    /// Saves the state to flash once the UART has gone idle after commands changed it
+    ///
+    /// Commands that arrive in a burst are saved together. Only the values that differ from flash
+    /// are written, see [Persistence::save]. Changes to the schedule made by `run_schedule` are
+    /// picked up too, as it sends every job that fires through `process_command`.
+    #[task(shared = [counter, led_interval_ms, schedule, persistence], priority = 1)]
+    async fn save_state(mut cx: save_state::Context, mut changed: SignalReader<'static, ()>) {
+        loop {
+            changed.wait().await;
+            let deadline = Mono::now() + SAVE_MAX_DELAY_MS.millis();
+            loop {
+                let interrupts = supervisor::RX_INTERRUPTS.load(Ordering::Relaxed);
+                Mono::delay(SAVE_IDLE_MS.millis()).await;
+                let idle = supervisor::RX_INTERRUPTS.load(Ordering::Relaxed) == interrupts;
+                if idle || Mono::now() >= deadline {
+                    break;
+                }
+            }
+
+            let state = persist::State {
+                counter: cx.shared.counter.lock(|c| *c),
+                led_interval_ms: cx.shared.led_interval_ms.lock(|v| *v),
+                schedule: cx.shared.schedule.lock(|s| s.clone()),
+            };
+            cx.shared.persistence.lock(|p| p.save(&state));
+        }
+    }
+
+    /// Saves the nonce marks of `on_uart` to flash, see [Security::nonce_mark]
+    ///
+    /// Marks are saved right away rather than once the UART has gone idle, as frames above the
+    /// saved mark are rejected. A new mark is only needed once per hour of the nonces of the host.
+    #[task(shared = [persistence], priority = 1)]
+    async fn save_nonce_mark(
+        mut cx: save_nonce_mark::Context,
+        mut marks: SignalReader<'static, u64>,
+    ) {
+        loop {
+            let mark = marks.wait().await;
+            if cx.shared.persistence.lock(|p| p.save_nonce_mark(mark)) {
+                security::nonce_mark_saved(mark);
+            }
+        }
+    }
+
+    // ====================== WATCHDOG SUPERVISOR ======================
+
+    /// Feeds the watchdog as long as the receive, process and blink tasks are making progress
+    ///
+    /// Runs at the lowest priority, so the watchdog also resets the device if a higher priority
+    /// task keeps the CPU busy.
+    #[task(local = [watchdog], priority = 1)]
+    async fn supervise(cx: supervise::Context) {
+        let mut receive = supervisor::Receive::new();
+        loop {
+            let now = Mono::now().duration_since_epoch().to_millis();
+            let rx_stuck = receive.is_stuck(serial::rx_fifo_is_empty());
+            let process_stuck = supervisor::PROCESS.is_missed(now);
+            let blink_stuck = supervisor::BLINK.is_missed(now);
+            if rx_stuck || process_stuck || blink_stuck {
+                // Let the watchdog expire; the host learns the reset reason after the reboot
+                rprintln!(
+                    "stalled: receive {}, process {}, blink {}",
+                    rx_stuck,
+                    process_stuck,
+                    blink_stuck
+                );
             } else {
-                cx.shared.led_pin.lock(|p| p.toggle());
-                Mono::delay(ms.millis()).await; // Toggle every specified period
+                cx.local.watchdog.feed();
             }
+            Mono::delay(SUPERVISOR_PERIOD_MS.millis()).await;
         }
     }
 }
@@ -0,0 +1,238 @@
+//! Device state kept in flash across resets
+//!
+//! Saving is only available with the `persistence` feature. Without it, the device starts from
+//! the default state after every reset.
+use crate::schedule::Schedule;
+
+/// State that is saved
+#[derive(Clone, PartialEq)]
+pub struct State {
+    pub counter: u64,
+    pub led_interval_ms: u64,
+    pub schedule: Schedule,
+}
+
+impl State {
+    /// State of the device after [the_protocol::Command::Reset]
+    pub const fn new() -> Self {
+        Self {
+            counter: 0,
+            led_interval_ms: 0,
+            schedule: Schedule::new(),
+        }
+    }
+}
+
+/// Saves the state, and the nonce mark of authenticated frames, to flash
+pub struct Persistence {
+    /// `None` if the flash could not be mounted
+    #[cfg(feature = "persistence")]
+    flash: Option<flash::FlashState>,
+}
+
+impl Persistence {
+    /// Returns the saved state, or the default state if there is none
+    ///
+    /// Errors are logged rather than returned, as the device is usable without persistence.
+    #[cfg(feature = "persistence")]
+    pub fn restore() -> (Self, State) {
+        match flash::FlashState::restore() {
+            Some((flash, state)) => (Self { flash: Some(flash) }, state),
+            None => (Self { flash: None }, State::new()),
+        }
+    }
+
+    #[cfg(not(feature = "persistence"))]
+    pub fn restore() -> (Self, State) {
+        (Self {}, State::new())
+    }
+
+    /// Writes the parts of `state` that differ from the saved state. Saving the default state
+    /// clears the flash, but for the nonce mark.
+    #[cfg(feature = "persistence")]
+    pub fn save(&mut self, state: &State) {
+        if let Some(flash) = &mut self.flash {
+            flash.save(state);
+        }
+    }
+
+    #[cfg(not(feature = "persistence"))]
+    pub fn save(&mut self, _state: &State) {}
+
+    /// Returns the nonce mark saved with [Persistence::save_nonce_mark], or 0 if there is none
+    #[cfg(feature = "persistence")]
+    pub fn nonce_mark(&self) -> u64 {
+        self.flash.as_ref().map_or(0, |flash| flash.nonce_mark())
+    }
+
+    #[cfg(not(feature = "persistence"))]
+    pub fn nonce_mark(&self) -> u64 {
+        0
+    }
+
+    /// Writes the mark above the nonces of authenticated frames, see [crate::security]. The mark
+    /// outlives [the_protocol::Command::Reset]. Returns whether the mark was written.
+    #[cfg(feature = "persistence")]
+    pub fn save_nonce_mark(&mut self, mark: u64) -> bool {
+        self.flash
+            .as_mut()
+            .is_some_and(|flash| flash.save_nonce_mark(mark))
+    }
+
+    #[cfg(not(feature = "persistence"))]
+    pub fn save_nonce_mark(&mut self, _mark: u64) -> bool {
+        false
+    }
+}
+
+#[cfg(feature = "persistence")]
+mod flash {
+    use esp_storage::{FlashStorage, FlashStorageError};
+    use flash_store::{Key, Store};
+    use rtt_target::rprintln;
+    use the_protocol_serde::{Codec, JobId, ScheduledJob};
+
+    use super::State;
+    use crate::schedule::Schedule;
+
+    /// Start of the flash region of the store: the `nvs` partition of the default partition
+    /// table, which is not used otherwise by this firmware
+    const FLASH_START: u32 = 0x9000;
+    /// Number of 4 KiB sectors in the flash region
+    const FLASH_SECTORS: u32 = 6;
+
+    const KEY_COUNTER: Key = 0;
+    const KEY_LED_INTERVAL: Key = 1;
+    const KEY_NEXT_JOB_ID: Key = 2;
+    const KEY_NONCE_MARK: Key = 3;
+    /// Each slot of the schedule is saved under its own key, starting from this one
+    const KEY_JOBS: Key = 0x100;
+
+    type Error = flash_store::Error<FlashStorageError>;
+
+    pub struct FlashState {
+        store: Store<FlashStorage>,
+        /// State as it is in flash
+        saved: State,
+        /// Nonce mark as it is in flash
+        nonce_mark: u64,
+    }
+
+    impl FlashState {
+        pub fn restore() -> Option<(Self, State)> {
+            let mut store = match Store::mount(FlashStorage::new(), FLASH_START, FLASH_SECTORS) {
+                Ok(store) => store,
+                Err(e) => {
+                    rprintln!("unable to mount flash: {:?}", e);
+                    return None;
+                }
+            };
+            let state = read_state(&mut store);
+            let saved = state.clone();
+            let nonce_mark = read_u64(&mut store, KEY_NONCE_MARK);
+            let flash = Self {
+                store,
+                saved,
+                nonce_mark,
+            };
+            Some((flash, state))
+        }
+
+        pub fn save(&mut self, state: &State) {
+            if *state == self.saved {
+                return;
+            }
+            match write_state(&mut self.store, &self.saved, state, self.nonce_mark) {
+                Ok(()) => self.saved = state.clone(),
+                // The write is retried with the next save
+                Err(e) => rprintln!("unable to save state: {:?}", e),
+            }
+        }
+
+        pub fn nonce_mark(&self) -> u64 {
+            self.nonce_mark
+        }
+
+        pub fn save_nonce_mark(&mut self, mark: u64) -> bool {
+            match self.store.write(KEY_NONCE_MARK, &mark.to_le_bytes()) {
+                Ok(()) => {
+                    self.nonce_mark = mark;
+                    true
+                }
+                Err(e) => {
+                    rprintln!("unable to save nonce mark: {:?}", e);
+                    false
+                }
+            }
+        }
+    }
+
+    fn read_u64(store: &mut Store<FlashStorage>, key: Key) -> u64 {
+        let mut buf = [0; 8];
+        match store.read(key, &mut buf) {
+            Ok(Some(8)) => u64::from_le_bytes(buf),
+            _ => 0,
+        }
+    }
+
+    fn read_state(store: &mut Store<FlashStorage>) -> State {
+        let counter = read_u64(store, KEY_COUNTER);
+        let led_interval_ms = read_u64(store, KEY_LED_INTERVAL);
+        let next_id = read_u64(store, KEY_NEXT_JOB_ID) as JobId;
+
+        let jobs = core::array::from_fn(|slot| {
+            let mut buf = [0; ScheduledJob::MAX_SERIALIZED_LEN];
+            match store.read(KEY_JOBS + slot as Key, &mut buf) {
+                // A job that cannot be decoded, e.g., after a firmware update, is dropped
+                Ok(Some(len)) => ScheduledJob::deserialize_in_place(&mut buf[..len]).ok(),
+                _ => None,
+            }
+        });
+        State {
+            counter,
+            led_interval_ms,
+            schedule: Schedule::from_parts(jobs, next_id),
+        }
+    }
+
+    fn write_state(
+        store: &mut Store<FlashStorage>,
+        saved: &State,
+        state: &State,
+        nonce_mark: u64,
+    ) -> Result<(), Error> {
+        // Clearing would lose the nonce mark, the values are written one by one instead
+        if *state == State::new() && nonce_mark == 0 {
+            return store.clear();
+        }
+        if state.counter != saved.counter {
+            store.write(KEY_COUNTER, &state.counter.to_le_bytes())?;
+        }
+        if state.led_interval_ms != saved.led_interval_ms {
+            store.write(KEY_LED_INTERVAL, &state.led_interval_ms.to_le_bytes())?;
+        }
+        if state.schedule.next_id() != saved.schedule.next_id() {
+            let next_id = state.schedule.next_id() as u64;
+            store.write(KEY_NEXT_JOB_ID, &next_id.to_le_bytes())?;
+        }
+        let slots = state.schedule.slots().iter().zip(saved.schedule.slots());
+        for (slot, (job, saved_job)) in slots.enumerate() {
+            if job == saved_job {
+                continue;
+            }
+            let key = KEY_JOBS + slot as Key;
+            match job {
+                Some(job) => {
+                    let mut buf = [0; ScheduledJob::MAX_SERIALIZED_LEN];
+                    let frame = job
+                        .serialize(&mut buf)
+                        // There is no way to recover from this, nor should it ever fail
+                        .expect("unable to serialize job");
+                    store.write(key, frame)?;
+                }
+                None => store.remove(key)?,
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,36 @@
+//! What the RGB led shows
+use the_protocol_serde::{parse_capacity, Color, RgbEffect};
+
+/// Number of leds in the chain on GPIO2. Set with the `RGB_LEDS` environment variable at build
+/// time, 1 by default. Sizes the RMT buffer and the frames of the effects engine.
+pub const LEDS: usize = parse_capacity(option_env!("RGB_LEDS"), 1);
+/// Brightness limit of the leds, which keeps them comfortable to look at and the current drawn
+/// by the chain low
+pub const MAX_BRIGHTNESS: u8 = 64;
+/// Duration of the fade to the colour of the next part of the day
+pub const TIME_OF_DAY_FADE_MS: u32 = 1000;
+
+/// Content of the RGB led
+#[derive(Clone, Copy, PartialEq)]
+pub enum RgbMode {
+    /// The colour of the time of day, set with [the_protocol::Funct::EnableRgb]. The led is off
+    /// while the time is unset.
+    TimeOfDay,
+    /// An effect set by the host. [the_protocol::Funct::DisableRgb] sets [RgbEffect::Off].
+    Effect(RgbEffect),
+}
+
+/// Colour of the part of the day that `hour` belongs to in UTC
+pub fn time_of_day_color(hour: u32) -> Color {
+    let (r, g, b) = match hour {
+        // Dawn, Aureolin
+        3..=8 => (0xF8, 0xF3, 0x2B),
+        // Noon, Ice blue
+        9..=14 => (0x9C, 0xFF, 0xFA),
+        // Evening, Indigo dye
+        15..=20 => (0x05, 0x3C, 0x5E),
+        // Night, Dark purple
+        _ => (0x31, 0x08, 0x1F),
+    };
+    Color::Rgb { r, g, b }
+}
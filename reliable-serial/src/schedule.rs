@@ -0,0 +1,129 @@
+//! Queue of jobs scheduled by the host
+use the_protocol::{Funct, SDateTime};
+use the_protocol_serde::{JobId, Recurrence, SchedulePage, ScheduledJob, SCHEDULE_PAGE_LEN};
+
+/// Maximum number of jobs that can be scheduled at the same time
+pub const CAPACITY: usize = 16;
+
+/// Returned when a job is scheduled while all slots are taken
+#[derive(Debug)]
+pub struct ScheduleFull;
+
+/// Fixed-capacity schedule which assigns an identifier to each job
+#[derive(Clone, PartialEq)]
+pub struct Schedule {
+    jobs: [Option<ScheduledJob>; CAPACITY],
+    /// Identifier for the next scheduled job
+    next_id: JobId,
+}
+
+impl Schedule {
+    pub const fn new() -> Self {
+        Self {
+            jobs: [const { None }; CAPACITY],
+            next_id: 0,
+        }
+    }
+
+    /// Reconstructs a schedule from its slots and the identifier for the next scheduled job
+    pub fn from_parts(jobs: [Option<ScheduledJob>; CAPACITY], next_id: JobId) -> Self {
+        Self { jobs, next_id }
+    }
+
+    /// Slots of the schedule, `None` for a free slot
+    pub fn slots(&self) -> &[Option<ScheduledJob>; CAPACITY] {
+        &self.jobs
+    }
+
+    /// Identifier for the next scheduled job
+    pub fn next_id(&self) -> JobId {
+        self.next_id
+    }
+
+    /// Adds a job to the schedule and returns its identifier
+    ///
+    /// A job with a [Recurrence] fires first `at` and is then rescheduled each time it fires.
+    pub fn insert(
+        &mut self,
+        funct: Funct,
+        at: SDateTime,
+        recurrence: Option<Recurrence>,
+    ) -> Result<JobId, ScheduleFull> {
+        let slot = self
+            .jobs
+            .iter_mut()
+            .find(|job| job.is_none())
+            .ok_or(ScheduleFull)?;
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        *slot = Some(ScheduledJob {
+            id,
+            funct,
+            at,
+            recurrence,
+        });
+        Ok(id)
+    }
+
+    /// Removes the job with the identifier. Returns `false` if there was no such job.
+    pub fn cancel(&mut self, id: JobId) -> bool {
+        match self
+            .jobs
+            .iter_mut()
+            .find(|job| job.as_ref().is_some_and(|job| job.id == id))
+        {
+            Some(job) => {
+                *job = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes all jobs
+    pub fn clear(&mut self) {
+        self.jobs.fill(None);
+    }
+
+    /// Number of jobs in the schedule
+    pub fn len(&self) -> usize {
+        self.jobs.iter().flatten().count()
+    }
+
+    /// Returns the earliest job that should have fired by `now`
+    ///
+    /// The job is removed from the schedule, unless its recurrence has an occurrence after `now`,
+    /// in which case the job stays in the schedule for that occurrence.
+    pub fn pop_due(&mut self, now: &SDateTime) -> Option<ScheduledJob> {
+        let idx = (0..CAPACITY)
+            .filter(|&idx| self.jobs[idx].as_ref().is_some_and(|job| job.at <= *now))
+            .min_by(|&a, &b| {
+                let (a, b) = (&self.jobs[a], &self.jobs[b]);
+                a.as_ref()
+                    .map(|job| &job.at)
+                    .partial_cmp(&b.as_ref().map(|job| &job.at))
+                    .unwrap_or(core::cmp::Ordering::Equal)
+            })?;
+        let job = self.jobs[idx].take()?;
+        let next = job.recurrence.as_ref().and_then(|r| r.after(now));
+        if let Some(at) = next {
+            self.jobs[idx] = Some(ScheduledJob { at, ..job.clone() });
+        }
+        Some(job)
+    }
+
+    /// Returns the specified page of the schedule, with jobs in no particular order
+    pub fn page(&self, page: u8) -> SchedulePage {
+        let mut jobs = self
+            .jobs
+            .iter()
+            .flatten()
+            .skip(page as usize * SCHEDULE_PAGE_LEN)
+            .cloned();
+        SchedulePage {
+            page,
+            page_count: self.len().div_ceil(SCHEDULE_PAGE_LEN) as u8,
+            jobs: core::array::from_fn(|_| jobs.next()),
+        }
+    }
+}
@@ -0,0 +1,241 @@
+//! Authentication or encryption of the frames exchanged with the host
+//!
+//! With the `auth` feature, frames are authenticated with the key in `AUTH_KEY`, see
+//! [the_protocol_serde::auth]. With the `encryption` feature, frames are encrypted with a key of
+//! the session that the host starts with a handshake, derived from the key in `ENCRYPTION_KEY`, see
+//! [the_protocol_serde::encryption]. Without either, frames are sent and received as they are.
+//!
+//! With the `auth` feature, a mark above the nonces of the frames is kept in flash, see
+//! [Security::nonce_mark]. After a reset, the device accepts only nonces above the mark, so that
+//! frames recorded before the reset cannot be replayed.
+#![allow(unused)]
+use esp_hal::rng::Trng;
+#[cfg(feature = "auth")]
+use portable_atomic::{AtomicU64, Ordering};
+#[cfg(feature = "auth")]
+use the_protocol_serde::auth::{self, Authenticator, Direction};
+#[cfg(feature = "encryption")]
+use the_protocol_serde::encryption::{self, Hello, Random, Responder, Session};
+use the_protocol_serde::DeserializeError;
+
+#[cfg(all(feature = "auth", feature = "encryption"))]
+compile_error!("the `auth` and `encryption` features are alternatives, enable either");
+
+/// Key shared with the host, given as 32 hex digits in `AUTH_KEY` at build time
+#[cfg(feature = "auth")]
+const AUTH_KEY: auth::Key = parse_key(env!("AUTH_KEY"));
+
+/// Key shared with the host, given as 64 hex digits in `ENCRYPTION_KEY` at build time
+#[cfg(feature = "encryption")]
+const ENCRYPTION_KEY: encryption::Key = parse_key(env!("ENCRYPTION_KEY"));
+
+/// Nonces that may be accepted or sealed above the highest one so far: an hour of the nonces of the
+/// host, which count microseconds. A new mark is saved once half of them are used, so that frames
+/// keep passing while it is written.
+#[cfg(feature = "auth")]
+const NONCE_RESERVE: u64 = 60 * 60 * 1_000_000;
+
+/// Nonce mark in flash, which the nonces of the frames accepted must not pass
+#[cfg(feature = "auth")]
+static SAVED_MARK: AtomicU64 = AtomicU64::new(0);
+
+/// Records that `mark`, returned by [Security::nonce_mark], was saved in flash
+pub fn nonce_mark_saved(mark: u64) {
+    #[cfg(feature = "auth")]
+    SAVED_MARK.fetch_max(mark, Ordering::Relaxed);
+}
+
+/// Parses a key of hex digits at compile time
+const fn parse_key<const N: usize>(s: &str) -> [u8; N] {
+    const fn digit(c: u8) -> u8 {
+        match c {
+            b'0'..=b'9' => c - b'0',
+            b'a'..=b'f' => c - b'a' + 10,
+            b'A'..=b'F' => c - b'A' + 10,
+            _ => panic!("the key must consist of hex digits"),
+        }
+    }
+    let bytes = s.as_bytes();
+    assert!(
+        bytes.len() == 2 * N,
+        "the key has the wrong number of hex digits"
+    );
+    let mut key = [0; N];
+    let mut idx = 0;
+    while idx < N {
+        key[idx] = (digit(bytes[2 * idx]) << 4) | digit(bytes[2 * idx + 1]);
+        idx += 1;
+    }
+    key
+}
+
+/// Length of the frame that carries a frame of at most `frame_len` bytes on the line
+#[cfg(not(any(feature = "auth", feature = "encryption")))]
+pub const fn max_frame_len(frame_len: usize) -> usize {
+    frame_len
+}
+
+/// Length of the authenticated frame that carries a frame of at most `frame_len` bytes
+#[cfg(feature = "auth")]
+pub const fn max_frame_len(frame_len: usize) -> usize {
+    auth::max_frame_len(frame_len)
+}
+
+/// Length of the encrypted frame that carries a frame of at most `frame_len` bytes
+#[cfg(feature = "encryption")]
+pub const fn max_frame_len(frame_len: usize) -> usize {
+    encryption::max_frame_len(frame_len)
+}
+
+/// Seals the frames of the device and opens those of the host
+pub struct Security {
+    #[cfg(feature = "auth")]
+    authenticator: Authenticator,
+    #[cfg(feature = "encryption")]
+    responder: Responder,
+    /// Source of the random values of the handshakes
+    #[cfg(feature = "encryption")]
+    rng: Trng<'static>,
+    /// Session of the latest handshake, `None` until the host starts one
+    #[cfg(feature = "encryption")]
+    session: Option<Session>,
+    /// Answer to the latest hello of the host, yet to be written
+    #[cfg(feature = "encryption")]
+    answer: Option<Hello>,
+}
+
+impl Security {
+    /// Creates the security layer. `rng` is used for the handshakes of the `encryption` feature,
+    /// and `nonce_mark`, the mark saved in flash before the reset, by the `auth` feature.
+    ///
+    /// The random value of the device must not repeat: a replayed hello of the host answered with
+    /// a repeated value would start a session with the key of an earlier one, in which the frames
+    /// recorded in that session pass again. The TRNG keeps the noise of the ADC mixed into the
+    /// RNG for as long as it lives, so the values are truly random rather than pseudo-random.
+    pub fn new(rng: Trng<'static>, nonce_mark: u64) -> Self {
+        #[cfg(feature = "auth")]
+        SAVED_MARK.store(nonce_mark, Ordering::Relaxed);
+        Self {
+            #[cfg(feature = "auth")]
+            authenticator: Authenticator::resume(AUTH_KEY, Direction::ToHost, nonce_mark),
+            #[cfg(feature = "encryption")]
+            responder: Responder::new(ENCRYPTION_KEY),
+            #[cfg(feature = "encryption")]
+            rng,
+            #[cfg(feature = "encryption")]
+            session: None,
+            #[cfg(feature = "encryption")]
+            answer: None,
+        }
+    }
+}
+
+#[cfg(not(any(feature = "auth", feature = "encryption")))]
+impl Security {
+    /// Wraps the COBS packet in `buf[..len]` into a frame for the line in place. Returns the frame,
+    /// or `None` if it cannot be sent yet.
+    pub fn seal<'a>(&mut self, buf: &'a mut [u8], len: usize) -> Option<&'a mut [u8]> {
+        Some(&mut buf[..len])
+    }
+
+    /// Unwraps the COBS packet carried by the frame `frame` from the line in place. Returns the
+    /// packet, or `None` if the frame carries none.
+    ///
+    /// # Errors
+    ///
+    /// * [DeserializeError::Unauthenticated] if the frame fails verification or is a replay
+    pub fn open<'a>(
+        &mut self,
+        frame: &'a mut [u8],
+    ) -> Result<Option<&'a mut [u8]>, DeserializeError> {
+        Ok(Some(frame))
+    }
+
+    /// Returns the nonce mark to save in flash, if any
+    pub fn nonce_mark(&self) -> Option<u64> {
+        None
+    }
+}
+
+#[cfg(feature = "auth")]
+impl Security {
+    /// Wraps the COBS packet in `buf[..len]` into an authenticated frame in place
+    pub fn seal<'a>(&mut self, buf: &'a mut [u8], len: usize) -> Option<&'a mut [u8]> {
+        let frame = self
+            .authenticator
+            .seal(buf, len)
+            // The buffer fits the largest frame by construction
+            .expect("unable to authenticate frame");
+        Some(frame)
+    }
+
+    /// Verifies the authenticated frame `frame` and unwraps the COBS packet it carries in place.
+    /// Frames with nonces above the saved mark are rejected until a higher mark is saved.
+    pub fn open<'a>(
+        &mut self,
+        frame: &'a mut [u8],
+    ) -> Result<Option<&'a mut [u8]>, DeserializeError> {
+        let packet = self.authenticator.open(frame)?;
+        // The frame could be replayed after a reset that comes before the mark is saved
+        if self.authenticator.high_water() > SAVED_MARK.load(Ordering::Relaxed) {
+            return Err(DeserializeError::Unauthenticated);
+        }
+        Ok(Some(packet))
+    }
+
+    /// Returns the nonce mark to save in flash once the nonces come within half of
+    /// [NONCE_RESERVE] of the saved mark. Call [nonce_mark_saved] once it is saved.
+    pub fn nonce_mark(&self) -> Option<u64> {
+        let high_water = self.authenticator.high_water();
+        let saved = SAVED_MARK.load(Ordering::Relaxed);
+        (high_water.saturating_add(NONCE_RESERVE / 2) > saved)
+            .then(|| high_water.saturating_add(NONCE_RESERVE))
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl Security {
+    /// Encrypts the COBS packet in `buf[..len]` into an encrypted frame in place. Returns `None`
+    /// until the host starts a session.
+    pub fn seal<'a>(&mut self, buf: &'a mut [u8], len: usize) -> Option<&'a mut [u8]> {
+        let frame = self
+            .session
+            .as_mut()?
+            .seal(buf, len)
+            // The buffer fits the largest frame by construction
+            .expect("unable to encrypt frame");
+        Some(frame)
+    }
+
+    /// Decrypts the encrypted frame `frame` and unwraps the COBS packet it carries in place.
+    /// Returns `None` for a hello of the host, which starts a new session and is answered with
+    /// [Security::answer].
+    pub fn open<'a>(
+        &mut self,
+        frame: &'a mut [u8],
+    ) -> Result<Option<&'a mut [u8]>, DeserializeError> {
+        if encryption::is_hello(frame) {
+            let mut random = Random::default();
+            self.rng.read(&mut random);
+            let (session, answer) = self.responder.accept(frame, random)?;
+            self.session = Some(session);
+            self.answer = Some(answer);
+            return Ok(None);
+        }
+        match self.session.as_mut() {
+            Some(session) => session.open(frame).map(Some),
+            None => Err(DeserializeError::Unauthenticated),
+        }
+    }
+
+    /// Takes the answer to the latest hello of the host, which is written ahead of the frames in
+    /// the queue and goes out as it is
+    pub fn answer(&mut self) -> Option<Hello> {
+        self.answer.take()
+    }
+
+    /// Returns the nonce mark to save in flash, if any. A handshake starts every session afresh.
+    pub fn nonce_mark(&self) -> Option<u64> {
+        None
+    }
+}
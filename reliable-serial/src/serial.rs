@@ -1,28 +1,280 @@
 //! Methods for controlling the serial port
 #![allow(unused)]
 use esp_hal::{uart::UartTx, Blocking};
-use the_protocol_serde::{corncobs::ZERO, Codec, Response};
+use rtic_sync::channel::Receiver;
+use rtt_target::rprintln;
+use the_protocol_serde::{
+    link, Codec, DeserializeError, DeviceMessage, SerializeError, DEFAULT_BAUD_RATE,
+};
 
-/// Sends a [the_protocol::Response] over provided UART
-pub fn send_response(resp: Response, tx: &mut UartTx<'static, Blocking>) {
-    let mut out_buf = [0u8; Response::MAX_SERIALIZED_LEN];
-    uart_write(
-        resp.serialize(&mut out_buf)
+use crate::diagnostics::{self, count};
+use crate::security::{self, Security};
+
+/// Capacity of the queue of encoded frames waiting to be written to UART
+pub const FRAME_QUEUE_LEN: usize = 2;
+
+/// Longest frame sent to the host, a [DeviceMessage] in a link frame, authenticated or encrypted
+/// with those features
+const FRAME_LEN: usize =
+    security::max_frame_len(link::max_frame_len(DeviceMessage::MAX_SERIALIZED_LEN));
+
+/// An encoded [DeviceMessage] or link frame, ready to be written to UART
+pub struct Frame {
+    buf: [u8; FRAME_LEN],
+    len: usize,
+}
+
+impl Frame {
+    /// Encodes a reply or an event into a plain frame
+    pub fn encode(msg: DeviceMessage) -> Self {
+        Self::encode_with(|buf| msg.serialize(buf))
+    }
+
+    /// Encodes a frame of the sliding-window link with `f`, e.g., [link::Link::frame]
+    pub fn encode_with(
+        f: impl FnOnce(&mut [u8; FRAME_LEN]) -> Result<&mut [u8], SerializeError>,
+    ) -> Self {
+        let mut buf = [0u8; FRAME_LEN];
+        let len = f(&mut buf)
             // There is no way to recover from this, nor should it ever fail
-            .expect("unable to serialize response"),
-        tx,
-    );
+            .expect("unable to serialize response")
+            .len();
+        Self { buf, len }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+
+    /// Wraps the frame into a frame for the line, see [Security::seal]. Returns whether the
+    /// frame can be sent.
+    fn seal(&mut self, security: &mut Security) -> bool {
+        match security.seal(&mut self.buf, self.len) {
+            Some(frame) => {
+                self.len = frame.len();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Interrupt-driven writer of queued frames
+///
+/// The TX FIFO is refilled from the UART interrupt whenever it runs low, so that no task has to
+/// wait for the bytes to drain. The TX FIFO empty interrupt is enabled with [start_transmit] once
+/// a frame has been queued, and disabled again by the transmitter once the queue runs dry.
+///
+/// Frames are authenticated or encrypted as they are taken from the queue, so that they go out in
+/// the order of their nonces, see [security].
+pub struct Transmitter {
+    uart_tx: UartTx<'static, Blocking>,
+    frames: Receiver<'static, Frame, FRAME_QUEUE_LEN>,
+    /// Frame currently being written and the number of bytes of it already written
+    current: Option<(Frame, usize)>,
+    /// Seals the frames sent and opens those received
+    security: Security,
 }
 
-/// Writes a buffer of bytes over provided UART
-pub fn uart_write(buf: &[u8], uart_tx: &mut UartTx<'static, Blocking>) {
-    // Write as long as more than 0 bytes remain to be written
-    let mut rem = buf.len();
-    while rem > 0 {
-        rem -= uart_tx.write(buf).unwrap();
+impl Transmitter {
+    pub fn new(
+        uart_tx: UartTx<'static, Blocking>,
+        frames: Receiver<'static, Frame, FRAME_QUEUE_LEN>,
+        security: Security,
+    ) -> Self {
+        Self {
+            uart_tx,
+            frames,
+            current: None,
+            security,
+        }
+    }
+
+    /// Unwraps the packet carried by a frame from the host, see [Security::open]. An answer to a
+    /// handshake is written right away.
+    pub fn open<'a>(
+        &mut self,
+        frame: &'a mut [u8],
+    ) -> Result<Option<&'a mut [u8]>, DeserializeError> {
+        let packet = self.security.open(frame)?;
+        if packet.is_none() {
+            start_transmit();
+        }
+        Ok(packet)
+    }
+
+    /// Returns the nonce mark to save in flash, see [Security::nonce_mark]
+    pub fn nonce_mark(&self) -> Option<u64> {
+        self.security.nonce_mark()
+    }
+
+    /// Writes as many queued bytes as fit in the TX FIFO. Called from the UART interrupt.
+    pub fn on_interrupt(&mut self) {
+        unpend_txfifo_empty_int();
+        loop {
+            #[cfg(feature = "encryption")]
+            if self.current.is_none() {
+                if let Some(answer) = self.security.answer() {
+                    self.current = Some((Frame::encode_with(|buf| answer.encode(buf)), 0));
+                }
+            }
+            if self.current.is_none() {
+                match self.frames.try_recv() {
+                    Ok(mut frame) => {
+                        if !frame.seal(&mut self.security) {
+                            // No session to encrypt the frame with, the host cannot read it
+                            rprintln!("dropped frame without a session");
+                            continue;
+                        }
+                        self.current = Some((frame, 0));
+                    }
+                    Err(_) => {
+                        // Nothing more to send, stop refilling until the next frame is queued
+                        listen_txfifo_empty(false);
+                        return;
+                    }
+                }
+            }
+            let Some((frame, pos)) = &mut self.current else {
+                return;
+            };
+            let rem = &frame.as_bytes()[*pos..];
+            if rem.is_empty() {
+                self.current = None;
+                continue;
+            }
+            if uart_fifo_is_full() {
+                // Continue when the FIFO has drained below the threshold
+                return;
+            }
+            match self.uart_tx.write(rem) {
+                Ok(written) => *pos += written,
+                Err(e) => {
+                    // The rest of the frame is dropped; the host will time out on the reply
+                    rprintln!("unable to write frame: {:?}", e);
+                    count(&diagnostics::WRITE_ERRORS);
+                    self.current = None;
+                }
+            }
+        }
     }
 }
 
+/// Baud rate of UART0, and the rate to return to unless the host confirms a new one, see
+/// [the_protocol_serde::BAUD_FALLBACK_MS]
+pub struct BaudRate {
+    current: u32,
+    fallback: Option<u32>,
+}
+
+impl BaudRate {
+    /// The rate after a reset, which UART0 is configured with in `init`
+    pub const fn new() -> Self {
+        Self {
+            current: DEFAULT_BAUD_RATE,
+            fallback: None,
+        }
+    }
+
+    /// Whether the latest switch is yet to be confirmed
+    pub fn is_pending(&self) -> bool {
+        self.fallback.is_some()
+    }
+
+    /// Switches UART0 to `rate`, to fall back to the current rate unless confirmed
+    pub fn switch(&mut self, rate: u32) {
+        self.fallback = Some(self.current);
+        self.current = rate;
+        set_baud_rate(rate);
+    }
+
+    /// Keeps the new rate, as a valid frame has arrived at it
+    pub fn confirm(&mut self) {
+        self.fallback = None;
+    }
+
+    /// Returns to the previous rate unless the new one has been confirmed. Returns whether it did.
+    pub fn fall_back(&mut self) -> bool {
+        let Some(rate) = self.fallback.take() else {
+            return false;
+        };
+        self.current = rate;
+        set_baud_rate(rate);
+        true
+    }
+}
+
+/// Programs the baud rate of UART0
+///
+/// This is a workaround for esp-hal 1.0.0-rc.0, where only [esp_hal::uart::Uart::apply_config]
+/// changes the baud rate and it is gone once the UART is split into halves. The divider is
+/// computed like esp-hal does for the APB clock, which the default configuration selects.
+fn set_baud_rate(rate: u32) {
+    /// Frequency of the APB clock
+    const CLOCK_HZ: u32 = 80_000_000;
+    /// Largest integral part of the baud rate divider
+    const MAX_DIV: u32 = 0xFFF - 1;
+    // The clock is divided down first for slow rates, and then by a divider with 4 fractional bits
+    let clk_div = CLOCK_HZ.div_ceil(MAX_DIV).div_ceil(rate);
+    let divider = (CLOCK_HZ << 4) / (rate * clk_div);
+    let uart0 = unsafe { esp32c3::UART0::steal() };
+    uart0
+        .clk_conf()
+        .modify(|_, w| unsafe { w.sclk_div_num().bits(clk_div as u8 - 1) });
+    uart0.clkdiv().write(|w| unsafe {
+        w.clkdiv()
+            .bits((divider >> 4) as u16)
+            .frag()
+            .bits((divider & 0xF) as u8)
+    });
+    // Latch the configuration into the UART core
+    uart0.id().modify(|_, w| w.reg_update().set_bit());
+    while uart0.id().read().reg_update().bit_is_set() {}
+}
+
+/// Whether frames are being written, i.e., the transmitter is refilling the TX FIFO or the FIFO
+/// has yet to drain onto the line
+pub(crate) fn is_transmitting() -> bool {
+    let uart0 = unsafe { esp32c3::UART0::steal() };
+    uart0.int_ena().read().txfifo_empty().bit_is_set()
+        || uart0.status().read().txfifo_cnt().bits() > 0
+        || uart0.fsm_status().read().st_utx_out().bits() != 0
+}
+
+/// Enables the TX FIFO empty interrupt so that queued frames get written to UART
+///
+/// The interrupt handler may disable the interrupt concurrently, but only when the frame queue is
+/// empty, so a frame must be queued before this is called.
+pub(crate) fn start_transmit() {
+    listen_txfifo_empty(true);
+}
+
+fn listen_txfifo_empty(enable: bool) {
+    let uart0 = unsafe { esp32c3::UART0::steal() };
+    uart0.int_ena().modify(|_, w| w.txfifo_empty().bit(enable));
+}
+
+/// Clears the interrupt for TX FIFO empty. The interrupt re-fires if the FIFO is still below the
+/// threshold.
+fn unpend_txfifo_empty_int() {
+    let uart0 = unsafe { esp32c3::UART0::steal() };
+    uart0.int_clr().write(|w| w.txfifo_empty().bit(true));
+}
+
+/// Whether the TX FIFO has no room for another byte, in which case [UartTx::write] would block
+fn uart_fifo_is_full() -> bool {
+    /// Size of the TX FIFO of UART0 in bytes
+    const UART_FIFO_SIZE: u16 = 128;
+    let uart0 = unsafe { esp32c3::UART0::steal() };
+    u16::from(uart0.status().read().txfifo_cnt().bits()) >= UART_FIFO_SIZE
+}
+
+/// Whether the RX FIFO holds bytes that the UART interrupt has yet to read
+pub(crate) fn rx_fifo_is_empty() -> bool {
+    let uart0 = unsafe { esp32c3::UART0::steal() };
+    uart0.status().read().rxfifo_cnt().bits() == 0
+}
+
 /// Clears the interrupt for RX FIFO full, preventing the interrupt from re-firing
 ///
 /// This is a workaround for esp-hal 0.20.1 <= 1.0.0-rc.0+ which are missing the
@@ -40,7 +292,3 @@ pub(crate) fn unpend_at_cmd_char_det_int() {
     let uart0 = unsafe { esp32c3::UART0::steal() };
     uart0.int_clr().write(|w| w.at_cmd_char_det().bit(true));
 }
-
-pub(crate) fn is_termination_byte(byte: u8) -> bool {
-    byte == ZERO
-}
@@ -0,0 +1,71 @@
+//! Progress tracking of the tasks that are checked before feeding the watchdog
+//!
+//! Each supervised task announces the time by which it will report progress again. A task that
+//! is waiting for work has no such deadline. The supervisor only feeds the watchdog while every
+//! task keeps its deadline, so a task that hangs resets the device.
+use portable_atomic::{AtomicU32, AtomicU64, Ordering};
+
+/// Deadline of `process_command` while it is working on a command
+pub static PROCESS: Deadline = Deadline::new();
+/// Deadline of the next step of the pattern played by `blink_led`
+pub static BLINK: Deadline = Deadline::new();
+/// Number of times the UART interrupt has run, see [Receive]
+pub static RX_INTERRUPTS: AtomicU32 = AtomicU32::new(0);
+
+/// Time in milliseconds of uptime by which a task reports progress again
+pub struct Deadline(AtomicU64);
+
+impl Deadline {
+    /// The task is waiting for work
+    const IDLE: u64 = u64::MAX;
+
+    pub const fn new() -> Self {
+        Self(AtomicU64::new(Self::IDLE))
+    }
+
+    /// Expects the task to report again within `within_ms` from `now_ms`
+    pub fn expect(&self, now_ms: u64, within_ms: u64) {
+        self.0
+            .store(now_ms.saturating_add(within_ms), Ordering::Relaxed);
+    }
+
+    /// Lifts the deadline while the task waits for work
+    pub fn idle(&self) {
+        self.0.store(Self::IDLE, Ordering::Relaxed);
+    }
+
+    /// Whether the task has failed to report by its deadline
+    pub fn is_missed(&self, now_ms: u64) -> bool {
+        now_ms > self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Checks that the UART interrupt keeps up with received bytes
+///
+/// The interrupt runs only when bytes arrive, so it is considered stuck if the RX FIFO holds bytes
+/// at two consecutive checks without the interrupt having run in between.
+pub struct Receive {
+    /// Value of [RX_INTERRUPTS] at the previous check if the RX FIFO held bytes then
+    pending_since: Option<u32>,
+}
+
+impl Receive {
+    pub const fn new() -> Self {
+        Self {
+            pending_since: None,
+        }
+    }
+
+    /// Returns whether the interrupt is stuck, given whether the RX FIFO is currently empty
+    pub fn is_stuck(&mut self, rx_fifo_empty: bool) -> bool {
+        let interrupts = RX_INTERRUPTS.load(Ordering::Relaxed);
+        let stuck = !rx_fifo_empty && self.pending_since == Some(interrupts);
+        self.pending_since = (!rx_fifo_empty).then_some(interrupts);
+        stuck
+    }
+}
+
+/// Records a run of the UART interrupt
+pub fn rx_interrupt() {
+    RX_INTERRUPTS.fetch_add(1, Ordering::Relaxed);
+}
@@ -0,0 +1,113 @@
+//! Renders every effect on a chain of three leds and checks the frames
+//!
+//! ```sh
+//! cargo run --example render
+//! ```
+use rgb_effects::{Engine, FRAME_INTERVAL_MS, RGB8, to_rgb};
+use the_protocol_serde::{Color, RgbEffect};
+
+const LEDS: usize = 3;
+const RED: Color = Color::Rgb { r: 255, g: 0, b: 0 };
+const BLUE: Color = Color::Hsv {
+    h: 170,
+    s: 255,
+    v: 255,
+};
+
+fn main() {
+    static_and_brightness();
+    fade();
+    breathe();
+    rainbow();
+    println!("ok");
+}
+
+fn static_and_brightness() {
+    let mut engine = Engine::<LEDS>::new(128);
+    assert_eq!(engine.render(0), [RGB8::default(); LEDS]);
+    assert!(!engine.is_animated(0));
+
+    engine.set_effect(RgbEffect::Static(RED), 0);
+    assert_eq!(engine.render(0), [RGB8::new(128, 0, 0); LEDS]);
+    assert!(!engine.is_animated(0));
+
+    assert!(!engine.set_brightness(129));
+    assert_eq!(engine.brightness(), 128);
+    assert!(engine.set_brightness(51));
+    assert_eq!(engine.render(0), [RGB8::new(51, 0, 0); LEDS]);
+}
+
+fn fade() {
+    let mut engine = Engine::<LEDS>::new(255);
+    engine.set_effect(RgbEffect::Static(RED), 0);
+    engine.render(0);
+
+    let fade = RgbEffect::Fade {
+        to: BLUE,
+        duration_ms: 1000,
+    };
+    engine.set_effect(fade, 100);
+    assert_eq!(engine.render(100), [RGB8::new(255, 0, 0); LEDS]);
+    assert!(engine.is_animated(600));
+    let halfway = engine.render(600)[0];
+    assert!(halfway.r > 100 && halfway.r < 155, "{halfway:?}");
+    assert!(halfway.b > 100 && halfway.b < 155, "{halfway:?}");
+    assert!(!engine.is_animated(1100));
+    assert_eq!(engine.render(1100), [to_rgb(BLUE); LEDS]);
+
+    // A fade of no duration jumps to the colour
+    engine.set_effect(
+        RgbEffect::Fade {
+            to: RED,
+            duration_ms: 0,
+        },
+        2000,
+    );
+    assert_eq!(engine.render(2000), [RGB8::new(255, 0, 0); LEDS]);
+}
+
+fn breathe() {
+    let mut engine = Engine::<LEDS>::new(255);
+    let breathe = RgbEffect::Breathe {
+        color: RED,
+        period_ms: 2000,
+    };
+    engine.set_effect(breathe, 0);
+    assert!(engine.is_animated(0));
+    assert_eq!(engine.render(0)[0], RGB8::new(0, 0, 0));
+    assert_eq!(engine.render(1000)[0], RGB8::new(255, 0, 0));
+    assert_eq!(engine.render(2000)[0], RGB8::new(0, 0, 0));
+
+    // The level rises and falls steadily from frame to frame
+    let mut prev = 0;
+    for t in (0..=1000).step_by(FRAME_INTERVAL_MS as usize) {
+        let r = engine.render(t)[0].r;
+        assert!(r >= prev);
+        prev = r;
+    }
+    for t in (1000..=2000).step_by(FRAME_INTERVAL_MS as usize) {
+        let r = engine.render(t)[0].r;
+        assert!(r <= prev);
+        prev = r;
+    }
+}
+
+fn rainbow() {
+    let mut engine = Engine::<LEDS>::new(255);
+    engine.set_effect(RgbEffect::Rainbow { period_ms: 3000 }, 0);
+    let frame = engine.render(0);
+    // The hues are spread along the chain: red, green and blue
+    assert_eq!(frame[0], RGB8::new(255, 0, 0));
+    assert!(
+        frame[1].g > frame[1].r && frame[1].g > frame[1].b,
+        "{frame:?}"
+    );
+    assert!(
+        frame[2].b > frame[2].r && frame[2].b > frame[2].g,
+        "{frame:?}"
+    );
+    // After a third of the cycle the hues have moved one led along
+    let later = engine.render(1000);
+    assert!(later[0].g > later[0].r, "{later:?}");
+    assert_eq!(engine.render(3000), frame);
+}
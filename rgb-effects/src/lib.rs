@@ -0,0 +1,167 @@
+//! Effects engine for chains of RGB leds
+//!
+//! The [Engine] renders an [RgbEffect] into frames, one colour per led of the chain, as a function
+//! of time. The device renders a frame every [FRAME_INTERVAL_MS] while the effect is animated and
+//! writes it to the leds, e.g., with `SmartLedsAdapter`. A static effect is rendered once.
+//!
+//! Frames are scaled by the brightness of the engine, which cannot exceed the maximum brightness
+//! that the engine was created with, e.g., to limit the current drawn by a long chain.
+#![no_std]
+// The engine is a public API for the firmware and host tools alike
+#![deny(missing_docs)]
+
+pub use smart_leds::RGB8;
+use smart_leds::hsv::{Hsv, hsv2rgb};
+use the_protocol_serde::{Color, RgbEffect};
+
+/// Interval between the frames of an animated effect, 50 frames per second
+pub const FRAME_INTERVAL_MS: u64 = 20;
+
+const OFF: RGB8 = RGB8 { r: 0, g: 0, b: 0 };
+
+/// Renders an [RgbEffect] on a chain of `N` leds
+pub struct Engine<const N: usize> {
+    effect: RgbEffect,
+    /// Time at which the effect was set in milliseconds
+    started_ms: u64,
+    /// Frame at the time the effect was set, from which a fade starts
+    from: [RGB8; N],
+    /// Frame rendered last, before scaling by the brightness
+    current: [RGB8; N],
+    brightness: u8,
+    max_brightness: u8,
+}
+
+impl<const N: usize> Engine<N> {
+    /// Creates an engine with the leds off and the brightness at `max_brightness`
+    pub const fn new(max_brightness: u8) -> Self {
+        Self {
+            effect: RgbEffect::Off,
+            started_ms: 0,
+            from: [OFF; N],
+            current: [OFF; N],
+            brightness: max_brightness,
+            max_brightness,
+        }
+    }
+
+    /// The effect being rendered
+    pub fn effect(&self) -> RgbEffect {
+        self.effect
+    }
+
+    /// Starts rendering `effect` at `now_ms`. A fade starts from the frame rendered last.
+    pub fn set_effect(&mut self, effect: RgbEffect, now_ms: u64) {
+        self.from = self.current;
+        self.effect = effect;
+        self.started_ms = now_ms;
+    }
+
+    /// Brightness that scales every frame, 0--255
+    pub fn brightness(&self) -> u8 {
+        self.brightness
+    }
+
+    /// Maximum brightness of the leds
+    pub fn max_brightness(&self) -> u8 {
+        self.max_brightness
+    }
+
+    /// Sets the brightness. Returns `false` and keeps the brightness if it exceeds the maximum.
+    pub fn set_brightness(&mut self, brightness: u8) -> bool {
+        if brightness > self.max_brightness {
+            return false;
+        }
+        self.brightness = brightness;
+        true
+    }
+
+    /// Whether the frames change over time at `now_ms`, i.e., another frame needs to be rendered
+    /// after [FRAME_INTERVAL_MS]
+    pub fn is_animated(&self, now_ms: u64) -> bool {
+        match self.effect {
+            RgbEffect::Breathe { .. } | RgbEffect::Rainbow { .. } => true,
+            RgbEffect::Fade { duration_ms, .. } => self.elapsed_ms(now_ms) < duration_ms as u64,
+            RgbEffect::Off | RgbEffect::Static(_) => false,
+        }
+    }
+
+    /// Renders the frame at `now_ms`, scaled by the brightness
+    pub fn render(&mut self, now_ms: u64) -> [RGB8; N] {
+        let elapsed = self.elapsed_ms(now_ms);
+        self.current = match self.effect {
+            RgbEffect::Off => [OFF; N],
+            RgbEffect::Static(color) => [to_rgb(color); N],
+            RgbEffect::Breathe { color, period_ms } => {
+                let period = period_ms.max(1) as u64;
+                let phase = elapsed % period;
+                // Triangle wave from 0 up to 255 at half the period and back down
+                let level = if 2 * phase < period {
+                    2 * phase * 255 / period
+                } else {
+                    2 * (period - phase) * 255 / period
+                };
+                [scale(to_rgb(color), level.min(255) as u8); N]
+            }
+            RgbEffect::Rainbow { period_ms } => {
+                let period = period_ms.max(1) as u64;
+                let hue = ((elapsed % period) * 256 / period) as usize;
+                core::array::from_fn(|i| {
+                    let hue = (hue + i * 256 / N) as u8;
+                    hsv2rgb(Hsv {
+                        hue,
+                        sat: 255,
+                        val: 255,
+                    })
+                })
+            }
+            RgbEffect::Fade { to, duration_ms } => {
+                let to = to_rgb(to);
+                let duration = duration_ms as u64;
+                if elapsed >= duration {
+                    [to; N]
+                } else {
+                    let t = (elapsed * 255 / duration) as u8;
+                    core::array::from_fn(|i| lerp(self.from[i], to, t))
+                }
+            }
+        };
+        self.current.map(|c| scale(c, self.brightness))
+    }
+
+    fn elapsed_ms(&self, now_ms: u64) -> u64 {
+        now_ms.saturating_sub(self.started_ms)
+    }
+}
+
+/// Converts a colour to its red, green and blue components
+pub fn to_rgb(color: Color) -> RGB8 {
+    match color {
+        Color::Rgb { r, g, b } => RGB8 { r, g, b },
+        Color::Hsv { h, s, v } => hsv2rgb(Hsv {
+            hue: h,
+            sat: s,
+            val: v,
+        }),
+    }
+}
+
+/// Scales each component of `c` by `level / 255`
+fn scale(c: RGB8, level: u8) -> RGB8 {
+    let scale = |x: u8| (x as u16 * level as u16 / 255) as u8;
+    RGB8 {
+        r: scale(c.r),
+        g: scale(c.g),
+        b: scale(c.b),
+    }
+}
+
+/// Interpolates from `a` at `t = 0` to `b` at `t = 255`
+fn lerp(a: RGB8, b: RGB8, t: u8) -> RGB8 {
+    let lerp = |a: u8, b: u8| (a as i32 + (b as i32 - a as i32) * t as i32 / 255) as u8;
+    RGB8 {
+        r: lerp(a.r, b.r),
+        g: lerp(a.g, b.g),
+        b: lerp(a.b, b.b),
+    }
+}
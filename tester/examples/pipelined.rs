@@ -0,0 +1,46 @@
+//! Sends the same commands one at a time and over a session of the sliding-window transport, and
+//! compares the time taken
+//!
+//! ```sh
+//! COM_PATH=/dev/ttyUSB0 cargo run --release --example pipelined
+//! ```
+use std::time::Instant;
+
+use tester::{exchange_v2, link_stats, open, Session, DEFAULT_WINDOW};
+use the_protocol_serde::{CommandV2, PayloadV2, ResponseV2};
+
+const COMMANDS: u64 = 100;
+
+fn main() {
+    let mut port = open().unwrap();
+    let cmds: Vec<_> = (0..COMMANDS).map(CommandV2::SetCounter).collect();
+
+    let start = Instant::now();
+    for cmd in &cmds {
+        exchange_v2(cmd, &mut port, None).unwrap();
+    }
+    let stop_and_wait = start.elapsed();
+
+    let start = Instant::now();
+    let mut session = Session::start(&mut port, DEFAULT_WINDOW).unwrap();
+    let replies = session.exchange_all(&cmds).unwrap();
+    let windowed = start.elapsed();
+    let stats = session.stats();
+    drop(session);
+
+    for (n, resp) in replies.iter().enumerate() {
+        if *resp != ResponseV2::Ok(None) {
+            println!("WARN: unexpected response to command {n}: {resp:?}");
+        }
+    }
+    // The commands were executed in order. A plain frame ends the session on the device.
+    let last = Some(PayloadV2::Counter(COMMANDS - 1));
+    if exchange_v2(&CommandV2::Counter, &mut port, None).unwrap() != ResponseV2::Ok(last) {
+        println!("WARN: counter value was incorrect");
+    }
+
+    println!("{COMMANDS} commands one at a time: {stop_and_wait:?}");
+    println!("{COMMANDS} commands with a window of {DEFAULT_WINDOW}: {windowed:?}");
+    println!("host statistics one at a time: {:?}", link_stats());
+    println!("host statistics of the session: {stats:?}");
+}
@@ -0,0 +1,279 @@
+//! Interactive prompt for sending extended commands to the device
+//!
+//! The serial terminal can be specified using the `COM_PATH` environment
+//! variable, e.g.,
+//!
+//! ```sh
+//! export COM_PATH=/dev/ttyUSB0
+//! ```
+use std::{
+    io::{self, BufRead, Write},
+    time::Duration,
+};
+
+use tester::{
+    change_baud_rate, exchange_v2, link_stats, open, parse_key, poll_events, start_encryption,
+    subscribe,
+};
+use the_protocol::chrono::{self, Utc};
+use the_protocol_serde::{
+    BlinkPattern, BlinkShape, Color, CommandV2, Funct, MorseText, PayloadV2, Recurrence,
+    ResponseV2, RgbEffect,
+};
+
+const HELP: &str = "\
+commands:
+  reset                     reset the device
+  counter                   read the counter
+  set-counter <n>           set the counter
+  time set|unset|get        set the device time to host time, unset it or read it
+  led                       read the led state
+  pattern <n>|forever <shape>
+                            play a blink pattern <n> times or until replaced
+  effect <effect>           play an effect on the RGB led
+  brightness <0-255>        set the brightness of the RGB led
+  info                      read the firmware info
+  diag                      read the device diagnostics
+  limits                    read the ranges of command arguments accepted by the device
+  link                      read the link statistics of the device
+  stats                     print the link statistics of the host
+  baud <rate>               switch the device and the host to the baud rate
+  key <hex>                 start a session of encrypted frames with the 64-digit key
+  events on|off             subscribe or unsubscribe to device events
+  listen <secs>             print the events received within <secs> seconds
+  now <funct>               actuate functionality immediately
+  schedule <secs> <funct>   schedule functionality to start in <secs> seconds
+  every <secs> <funct>      schedule functionality to start every <secs> seconds from now
+  daily <hh:mm> <funct>     schedule functionality to start daily at <hh:mm> UTC
+  repeat <n> <secs> <funct> schedule functionality to start <n> times, <secs> seconds apart
+  cancel <id>               cancel a scheduled job
+  clear                     cancel all scheduled jobs
+  list                      list the scheduled jobs
+  help                      show this message
+  quit                      exit
+
+functionality (<funct>):
+  inc | blink <ms> | noblink | rgb | norgb
+
+blink patterns (<shape>):
+  duty <on ms> <off ms> | burst <flashes> <on ms> <off ms> <pause ms> | morse <unit ms> <text>
+
+RGB effects (<effect>, <color>):
+  off | static <color> | breathe <ms> <color> | rainbow <ms> | fade <ms> <color>
+  rgb <r> <g> <b> | hsv <h> <s> <v>";
+
+fn main() {
+    let mut port = open().unwrap();
+    subscribe(|event| println!("event: {event:?}"));
+
+    println!("{HELP}");
+    let stdin = io::stdin();
+    loop {
+        print!("> ");
+        io::stdout().flush().unwrap();
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap() == 0 {
+            break;
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            [] => continue,
+            ["quit"] => break,
+            ["help"] => println!("{HELP}"),
+            ["list"] => list_schedule(&mut port),
+            ["stats"] => println!("{:?}", link_stats()),
+            ["baud", rate] => match rate.parse() {
+                Ok(rate) => match change_baud_rate(&mut port, rate) {
+                    Ok(()) => println!("switched to {rate} baud"),
+                    Err(e) => println!("baud rate unchanged: {e:?}"),
+                },
+                Err(_) => println!("unrecognized command, try `help`"),
+            },
+            ["key", key] => match parse_key(key) {
+                Some(key) => match start_encryption(&mut port, key) {
+                    Ok(()) => println!("encrypting frames"),
+                    Err(e) => println!("handshake failed: {e:?}"),
+                },
+                None => println!("the key must be 64 hex digits"),
+            },
+            ["listen", secs] => match secs.parse() {
+                Ok(secs) => {
+                    let n = poll_events(&mut port, Duration::from_secs(secs));
+                    println!("{n} events");
+                }
+                Err(_) => println!("unrecognized command, try `help`"),
+            },
+            words => match parse_command(words) {
+                Some(cmd) => match exchange_v2(&cmd, &mut port, None) {
+                    Ok(resp) => println!("{resp:?}"),
+                    Err(e) => println!("no response: {e:?}"),
+                },
+                None => println!("unrecognized command, try `help`"),
+            },
+        }
+    }
+}
+
+fn parse_command(words: &[&str]) -> Option<CommandV2> {
+    let cmd = match words {
+        ["reset"] => CommandV2::Reset,
+        ["counter"] => CommandV2::Counter,
+        ["set-counter", n] => CommandV2::SetCounter(n.parse().ok()?),
+        ["time", "set"] => CommandV2::SetDateTime(Some(Utc::now().into())),
+        ["time", "unset"] => CommandV2::SetDateTime(None),
+        ["time", "get"] => CommandV2::DateTime,
+        ["led"] => CommandV2::LedState,
+        ["info"] => CommandV2::FirmwareInfo,
+        ["diag"] => CommandV2::Diagnostics,
+        ["limits"] => CommandV2::Limits,
+        ["link"] => CommandV2::LinkStats,
+        ["events", "on"] => CommandV2::SubscribeEvents(true),
+        ["events", "off"] => CommandV2::SubscribeEvents(false),
+        ["now", funct @ ..] => CommandV2::Immediate(parse_funct(funct)?),
+        ["schedule", secs, funct @ ..] => {
+            let at = Utc::now() + chrono::Duration::seconds(secs.parse().ok()?);
+            CommandV2::Schedule(parse_funct(funct)?, at.into())
+        }
+        ["every", secs, funct @ ..] => {
+            let recurrence = Recurrence::Periodic {
+                start: Utc::now().into(),
+                period_s: secs.parse().ok()?,
+            };
+            CommandV2::ScheduleRecurring(parse_funct(funct)?, recurrence)
+        }
+        ["daily", time, funct @ ..] => {
+            let (hour, minute) = time.split_once(':')?;
+            let recurrence = Recurrence::Daily {
+                hour: hour.parse().ok()?,
+                minute: minute.parse().ok()?,
+            };
+            CommandV2::ScheduleRecurring(parse_funct(funct)?, recurrence)
+        }
+        ["repeat", n, secs, funct @ ..] => {
+            let recurrence = Recurrence::Repeat {
+                start: Utc::now().into(),
+                interval_s: secs.parse().ok()?,
+                count: n.parse().ok()?,
+            };
+            CommandV2::ScheduleRecurring(parse_funct(funct)?, recurrence)
+        }
+        ["pattern", times, shape @ ..] => {
+            let repetitions = match *times {
+                "forever" => None,
+                n => Some(n.parse().ok()?),
+            };
+            CommandV2::SetBlinkPattern(BlinkPattern {
+                shape: parse_shape(shape)?,
+                repetitions,
+            })
+        }
+        ["effect", effect @ ..] => CommandV2::SetRgbEffect(parse_effect(effect)?),
+        ["brightness", b] => CommandV2::SetRgbBrightness(b.parse().ok()?),
+        ["cancel", id] => CommandV2::CancelJob(id.parse().ok()?),
+        ["clear"] => CommandV2::ClearSchedule,
+        _ => return None,
+    };
+    Some(cmd)
+}
+
+fn parse_funct(words: &[&str]) -> Option<Funct> {
+    let funct = match words {
+        ["inc"] => Funct::Increment,
+        ["blink", ms] => Funct::EnableBlink {
+            period_ms: ms.parse().ok()?,
+        },
+        ["noblink"] => Funct::DisableBlink,
+        ["rgb"] => Funct::EnableRgb,
+        ["norgb"] => Funct::DisableRgb,
+        _ => return None,
+    };
+    Some(funct)
+}
+
+fn parse_shape(words: &[&str]) -> Option<BlinkShape> {
+    let shape = match words {
+        ["duty", on, off] => BlinkShape::Duty {
+            on_ms: on.parse().ok()?,
+            off_ms: off.parse().ok()?,
+        },
+        ["burst", flashes, on, off, pause] => BlinkShape::Burst {
+            flashes: flashes.parse().ok()?,
+            on_ms: on.parse().ok()?,
+            off_ms: off.parse().ok()?,
+            pause_ms: pause.parse().ok()?,
+        },
+        ["morse", unit, text @ ..] => BlinkShape::Morse {
+            unit_ms: unit.parse().ok()?,
+            text: MorseText::new(&text.join(" "))?,
+        },
+        _ => return None,
+    };
+    Some(shape)
+}
+
+fn parse_effect(words: &[&str]) -> Option<RgbEffect> {
+    let effect = match words {
+        ["off"] => RgbEffect::Off,
+        ["static", color @ ..] => RgbEffect::Static(parse_color(color)?),
+        ["breathe", ms, color @ ..] => RgbEffect::Breathe {
+            color: parse_color(color)?,
+            period_ms: ms.parse().ok()?,
+        },
+        ["rainbow", ms] => RgbEffect::Rainbow {
+            period_ms: ms.parse().ok()?,
+        },
+        ["fade", ms, color @ ..] => RgbEffect::Fade {
+            to: parse_color(color)?,
+            duration_ms: ms.parse().ok()?,
+        },
+        _ => return None,
+    };
+    Some(effect)
+}
+
+fn parse_color(words: &[&str]) -> Option<Color> {
+    let color = match words {
+        ["rgb", r, g, b] => Color::Rgb {
+            r: r.parse().ok()?,
+            g: g.parse().ok()?,
+            b: b.parse().ok()?,
+        },
+        ["hsv", h, s, v] => Color::Hsv {
+            h: h.parse().ok()?,
+            s: s.parse().ok()?,
+            v: v.parse().ok()?,
+        },
+        _ => return None,
+    };
+    Some(color)
+}
+
+/// Retrieves and prints every page of the schedule
+fn list_schedule(port: &mut serial2::SerialPort) {
+    let mut page = 0;
+    loop {
+        let cmd = CommandV2::ListSchedule { page };
+        match exchange_v2(&cmd, port, None) {
+            Ok(ResponseV2::Ok(Some(PayloadV2::SchedulePage(p)))) => {
+                for job in p.iter() {
+                    println!("  #{}: {:?} at {:?}", job.id, job.funct, job.at);
+                    if let Some(recurrence) = &job.recurrence {
+                        println!("      recurring {recurrence:?}");
+                    }
+                }
+                if p.is_last() {
+                    break;
+                }
+            }
+            Ok(resp) => {
+                println!("unexpected response: {resp:?}");
+                break;
+            }
+            Err(e) => {
+                println!("no response: {e:?}");
+                break;
+            }
+        }
+        page += 1;
+    }
+}
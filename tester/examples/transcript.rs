@@ -0,0 +1,99 @@
+//! Records a scenario of commands against a device, or compares a device against a recording
+//!
+//! Record the scenario against a device known to be good, e.g., one flashed with the reference
+//! firmware, then compare another device against the recording:
+//!
+//! ```sh
+//! COM_PATH=/dev/ttyUSB0 cargo run --release --example transcript -- record reference.json
+//! COM_PATH=/dev/ttyUSB0 cargo run --release --example transcript -- compare reference.json
+//! ```
+//!
+//! `compare` takes the tolerance for the latencies in milliseconds as an optional third argument,
+//! and saves the actual transcript next to the expected one for inspection.
+use std::{env, process, time::Duration};
+
+use tester::{open, Step, Transcript};
+use the_protocol::chrono::{self, Utc};
+use the_protocol::{Command, Funct};
+
+/// Latencies of the two devices may differ by this much by default
+const DEFAULT_TOLERANCE_MS: u64 = 20;
+
+const USAGE: &str = "usage: transcript record <file> | compare <file> [tolerance-ms]";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let (mode, path) = match args.as_slice() {
+        [mode, path, ..] => (mode.as_str(), path.as_str()),
+        _ => exit(USAGE),
+    };
+
+    match mode {
+        "record" => {
+            let transcript = Transcript::record(&scenario(), &mut open().unwrap());
+            transcript.save(path).unwrap();
+            println!("Recorded {} commands to {path}", transcript.entries.len());
+        }
+        "compare" => {
+            let tolerance = match args.get(2).map(|ms| ms.parse()) {
+                Some(Ok(ms)) => Duration::from_millis(ms),
+                Some(Err(_)) => exit(USAGE),
+                None => Duration::from_millis(DEFAULT_TOLERANCE_MS),
+            };
+            let expected = Transcript::load(path).unwrap();
+            let actual = Transcript::record(&scenario(), &mut open().unwrap());
+            let actual_path = format!("{path}.actual");
+            actual.save(&actual_path).unwrap();
+
+            let differences = expected.compare(&actual, tolerance);
+            for difference in &differences {
+                println!("{difference}");
+            }
+            if !differences.is_empty() {
+                exit(&format!(
+                    "{} differences from {path}, see {actual_path}",
+                    differences.len()
+                ));
+            }
+            println!("ok");
+        }
+        _ => exit(USAGE),
+    }
+}
+
+/// Exercises every legacy command. The device is reset first so that the counter starts from the
+/// same value on every run.
+fn scenario() -> Vec<Step> {
+    vec![
+        Step::Exchange(Command::Reset),
+        Step::Sleep(Duration::from_secs(1)),
+        Step::Exchange(Command::Counter),
+        Step::Exchange(Command::Immediate(Funct::Increment)),
+        Step::Exchange(Command::Counter),
+        Step::Exchange(Command::Immediate(Funct::EnableBlink { period_ms: 300 })),
+        Step::Sleep(Duration::from_secs(1)),
+        Step::Exchange(Command::Immediate(Funct::DisableBlink)),
+        // Scheduling is illegal while the date-time is unset
+        Step::Exchange(Command::SetDateTime(None)),
+        Step::ExchangeBuilt(blink_in_two_seconds),
+        Step::ExchangeBuilt(|| Command::SetDateTime(Some(Utc::now().into()))),
+        Step::ExchangeBuilt(blink_in_two_seconds),
+        Step::Sleep(Duration::from_secs(3)),
+        Step::Exchange(Command::Immediate(Funct::DisableBlink)),
+        Step::Exchange(Command::Immediate(Funct::EnableRgb)),
+        Step::Sleep(Duration::from_secs(2)),
+        Step::Exchange(Command::Immediate(Funct::DisableRgb)),
+    ]
+}
+
+fn blink_in_two_seconds() -> Command {
+    Command::Schedule(
+        Funct::EnableBlink { period_ms: 500 },
+        (Utc::now() + chrono::Duration::seconds(2)).into(),
+    )
+}
+
+fn exit(msg: &str) -> ! {
+    eprintln!("{msg}");
+    process::exit(1)
+}
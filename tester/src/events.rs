@@ -0,0 +1,38 @@
+use std::sync::Mutex;
+
+use the_protocol_serde::Event;
+
+type Subscriber = Box<dyn FnMut(Event) + Send>;
+
+static SUBSCRIBER: Mutex<Option<Subscriber>> = Mutex::new(None);
+
+/// Set the function that receives the events sent by the device. Replaces the previous subscriber.
+///
+/// Events are read from the serial port while waiting for a response or in [crate::poll_events].
+/// Events that arrive while there is no subscriber are printed.
+///
+/// N.b., the device only sends events after [the_protocol_serde::CommandV2::SubscribeEvents].
+pub fn subscribe(f: impl FnMut(Event) + Send + 'static) {
+    *SUBSCRIBER.lock().unwrap() = Some(Box::new(f));
+}
+
+/// Remove the subscriber set with [subscribe]
+pub fn unsubscribe() {
+    *SUBSCRIBER.lock().unwrap() = None;
+}
+
+/// Pass an event to the subscriber
+///
+/// Unexpected reboots, e.g., by the watchdog of the device, are also logged to stderr so that they
+/// are noticed even if the subscriber ignores them.
+pub(crate) fn publish(event: Event) {
+    if let Event::Rebooted { reason } = &event {
+        if reason.is_unexpected() {
+            eprintln!("WARNING: device rebooted unexpectedly: {reason:?}");
+        }
+    }
+    match SUBSCRIBER.lock().unwrap().as_mut() {
+        Some(f) => f(event),
+        None => println!("Unhandled Event: `{event:?}`"),
+    }
+}
@@ -1,59 +1,283 @@
-use std::{io, time};
+use std::{io, sync::Mutex, time};
 
 use serial2::SerialPort;
-use the_protocol_serde::{corncobs, Codec, Command, Response};
+use the_protocol_serde::{
+    corncobs, Batch, BatchResponse, Codec, Command, CommandV2, DeviceMessage, LinkMonitor,
+    LinkStats, PayloadV2, RejectReason, Reply, Request, Response, ResponseV2, RttEstimator,
+};
+
+use crate::events;
+use crate::security::{self, max_frame_len};
+
+/// Timeout for a response until the round-trip time has been measured, and the longest timeout
+const REPLY_TIMEOUT_MS: u64 = 1000;
+
+/// Shortest timeout for a response, which leaves room for the longest the device may take to reply:
+/// erasing a sector of flash may take several hundred milliseconds, during which the device does
+/// not read the line. A shorter timeout would give up on commands that the device executes anyway.
+/// Pass a timeout to [exchange] to wait longer.
+const MIN_REPLY_TIMEOUT_MS: u64 = 500;
+
+/// Number of times a command is sent again right away when the device answers that it arrived
+/// damaged or unauthenticated, see [wait_resending]
+const DAMAGED_RESENDS: usize = 2;
+
+/// Quality of the plain exchanges with the device, which sets the timeout for a response
+static MONITOR: Mutex<LinkMonitor> = Mutex::new(LinkMonitor::new(RttEstimator::new(
+    REPLY_TIMEOUT_MS,
+    MIN_REPLY_TIMEOUT_MS,
+    REPLY_TIMEOUT_MS,
+)));
 
 #[derive(Debug)]
 pub enum ResponseError {
     Timeout,
+    /// The device sent a frame that failed to decode
+    Corrupted,
+    /// The device sent a frame that failed verification or was a replay, see
+    /// [crate::start_encryption], or answered the command with [ResponseV2::Unauthenticated]
+    Unauthenticated,
+    /// The device answered with a response other than the expected one, e.g., [ResponseV2::Busy]
+    Unexpected(Box<ResponseV2>),
 }
 
 /// Send a command over serial and wait for response from the device. Blocks until response is
 /// received or timeout.
 ///
+/// The command is sent again right away if the device answers that it arrived damaged or
+/// unauthenticated.
+///
 /// # Arguments
 ///
 /// * `cmd` - command to send to device
 /// * `port` - serial port with a connected device (ESP32-C3 serial server)
-/// * `timeout` - optional timeout, default if `None`
+/// * `timeout` - optional timeout, adapted to the round-trip time if `None`, see [link_stats]
 pub fn exchange(
     cmd: &Command,
     port: &mut SerialPort,
     timeout: Option<time::Duration>,
 ) -> Result<Response, ResponseError> {
     send(cmd, port);
-    wait_for_response(port, timeout)
+    let reply = wait_resending(port, timeout, |port| write_request(cmd, port, true))?;
+    into_legacy(reply)
+}
+
+/// Send an extended command over serial and wait for response from the device. Blocks until
+/// response is received or timeout.
+///
+/// The device may answer with a legacy [Response], e.g., when it fails to decode the frame. Such
+/// responses are lifted into a [ResponseV2]. The command is sent again right away if the device
+/// answers that it arrived damaged or unauthenticated.
+///
+/// # Arguments
+///
+/// * `cmd` - extended command to send to device
+/// * `port` - serial port with a connected device (ESP32-C3 serial server)
+/// * `timeout` - optional timeout, adapted to the round-trip time if `None`, see [link_stats]
+pub fn exchange_v2(
+    cmd: &CommandV2,
+    port: &mut SerialPort,
+    timeout: Option<time::Duration>,
+) -> Result<ResponseV2, ResponseError> {
+    send_v2(cmd, port);
+    let req = Request::V2(cmd.clone());
+    let reply = wait_resending(port, timeout, |port| write_request(&req, port, true))?;
+    Ok(reply.into())
+}
+
+/// Send a batch of commands over serial in one frame and wait for the responses from the device.
+/// Blocks until the responses are received or timeout.
+///
+/// # Arguments
+///
+/// * `batch` - commands to send to device
+/// * `port` - serial port with a connected device (ESP32-C3 serial server)
+/// * `timeout` - optional timeout, adapted to the round-trip time if `None`, see [link_stats]
+pub fn exchange_batch(
+    batch: &Batch,
+    port: &mut SerialPort,
+    timeout: Option<time::Duration>,
+) -> Result<BatchResponse, ResponseError> {
+    match exchange_v2(&CommandV2::Batch(batch.clone()), port, timeout)? {
+        ResponseV2::Ok(Some(PayloadV2::Batch(resp))) => Ok(resp),
+        resp => Err(ResponseError::Unexpected(Box::new(resp))),
+    }
+}
+
+/// Statistics of the plain exchanges with the device since the start of the program, see
+/// [crate::Session::stats] for a session of the sliding-window transport
+///
+/// Every command is a data frame that is never sent again, so the round-trip time is measured
+/// from each command to its response.
+pub fn link_stats() -> LinkStats {
+    MONITOR.lock().unwrap().stats()
 }
 
 /// Send a command over serial
 pub fn send(cmd: &Command, port: &mut SerialPort) {
     println!("Serializing Command `{cmd:?}`");
-    // Construct the command packet
-    let mut cmd_buf = [0u8; Command::MAX_SERIALIZED_LEN];
-    let cmd_packet = cmd
-        .serialize(&mut cmd_buf)
+    write_request(cmd, port, false);
+}
+
+/// Send an extended command over serial
+pub fn send_v2(cmd: &CommandV2, port: &mut SerialPort) {
+    println!("Serializing CommandV2 `{cmd:?}`");
+    write_request(&Request::V2(cmd.clone()), port, false);
+}
+
+/// Serialize a legacy [Command] or a [Request] into a packet and send it over serial.
+/// `retransmission` tells whether the same command has been sent before.
+fn write_request<P>(req: &P, port: &mut SerialPort, retransmission: bool)
+where
+    P: Codec<P> + serde::Serialize + for<'de> serde::Deserialize<'de>,
+{
+    // Construct the command packet, a legacy command is never longer than a request
+    let mut cmd_buf = [0u8; max_frame_len(Request::MAX_SERIALIZED_LEN)];
+    let len = <P as Codec<P>>::serialize(req, &mut cmd_buf)
         // Hard error on failing to serialize a command on the host
-        .expect("Command ABI should not have changed");
+        .expect("commands should always serialize")
+        .len();
+    let cmd_packet = security::seal(&mut cmd_buf, len);
     println!("Serialized packet: `{cmd_packet:?}`");
 
     // Send the packet over serial
     port.write(cmd_packet)
         // Hard error on failing to write over serial
         .unwrap();
+    MONITOR.lock().unwrap().sent(retransmission);
+}
+
+/// Returns the legacy [Response] of a reply to a legacy command
+fn into_legacy(reply: Reply) -> Result<Response, ResponseError> {
+    match reply {
+        Reply::Legacy(response) => Ok(response),
+        // E.g., a device that answers a legacy command in the extended revision
+        Reply::V2(resp) => Err(ResponseError::Unexpected(Box::new(resp))),
+    }
+}
+
+/// Read events from the device for `duration` and pass them to the subscriber, see
+/// [crate::subscribe]. Returns the number of events received.
+///
+/// Use this to receive events while no command is being exchanged.
+pub fn poll_events(port: &mut SerialPort, duration: time::Duration) -> usize {
+    let prev_timeout = port.get_read_timeout().unwrap();
+    port.set_read_timeout(duration).unwrap();
+    let deadline = time::Instant::now() + duration;
+    let mut count = 0;
+    while time::Instant::now() < deadline {
+        match read_message(port) {
+            Ok(DeviceMessage::Event(event)) => {
+                count += 1;
+                events::publish(event);
+            }
+            Ok(DeviceMessage::Reply(reply)) => println!("Unexpected Reply: `{reply:?}`"),
+            Err(ResponseError::Timeout) => break,
+            Err(ResponseError::Corrupted | ResponseError::Unauthenticated) => {}
+            Err(e) => panic!("failed to read an event: {e:?}"),
+        }
+    }
+    port.set_read_timeout(prev_timeout).unwrap();
+    count
 }
 
-/// Wait for a [the_protocol::Response] from the device. Blocks until response is received or timeout.
-pub fn wait_for_response(
+/// Wait for the reply to the command just sent, and send it again with `resend`, up to
+/// [DAMAGED_RESENDS] times, while the device answers that the frame arrived damaged or
+/// unauthenticated. Returns [ResponseError::Unauthenticated] if the last frame was unauthenticated.
+///
+/// This is the negative acknowledgement of plain exchanges: the device did not execute the command
+/// in the damaged frame, so the command is sent again without waiting for a timeout and still runs
+/// at most once. A reply that arrives damaged is not asked for again, as sending the command again
+/// could run it twice. [crate::Session] asks for damaged replies again too.
+///
+/// A device that was reset answers the first frames with [ResponseV2::Unauthenticated] until the
+/// nonces of the host pass the mark it saved, see [the_protocol_serde::auth]. The answer raises
+/// the nonces of the host above the mark, so the command passes when sent again.
+fn wait_resending(
     port: &mut SerialPort,
     timeout: Option<time::Duration>,
-) -> Result<Response, ResponseError> {
-    const MAX_LEN: usize = Response::MAX_SERIALIZED_LEN;
+    mut resend: impl FnMut(&mut SerialPort),
+) -> Result<Reply, ResponseError> {
+    let mut resends = 0;
+    loop {
+        let reply = wait_for_reply(port, timeout)?;
+        let unauthenticated = match ResponseV2::from(reply.clone()) {
+            ResponseV2::Rejected(RejectReason::CorruptedFrame) => false,
+            ResponseV2::Unauthenticated => true,
+            _ => return Ok(reply),
+        };
+        if resends == DAMAGED_RESENDS {
+            return if unauthenticated {
+                Err(ResponseError::Unauthenticated)
+            } else {
+                Ok(reply)
+            };
+        }
+        let why = if unauthenticated {
+            "unauthenticated"
+        } else {
+            "damaged"
+        };
+        println!("Sending the command again, it arrived {why}");
+        resends += 1;
+        resend(port);
+    }
+}
 
-    let mut resp_buf = [0u8; MAX_LEN];
+/// Wait for a reply from the device, passing events to the subscriber. The timeout, unless given,
+/// adapts to the round-trip time of earlier exchanges.
+fn wait_for_reply(
+    port: &mut SerialPort,
+    timeout: Option<time::Duration>,
+) -> Result<Reply, ResponseError> {
+    let timeout =
+        timeout.unwrap_or_else(|| time::Duration::from_millis(MONITOR.lock().unwrap().rto_ms()));
+    port.set_read_timeout(timeout).unwrap();
+    let start = time::Instant::now();
+    loop {
+        let msg = read_message(port);
+        let mut monitor = MONITOR.lock().unwrap();
+        match msg {
+            Ok(DeviceMessage::Reply(reply)) => {
+                monitor.round_trip(start.elapsed().as_millis() as u64);
+                return Ok(reply);
+            }
+            Ok(DeviceMessage::Event(event)) => {
+                drop(monitor);
+                events::publish(event);
+            }
+            Err(e) => {
+                if let ResponseError::Timeout = e {
+                    monitor.timed_out();
+                }
+                return Err(e);
+            }
+        }
+    }
+}
+
+/// Read and decode one frame from the device
+fn read_message(port: &mut SerialPort) -> Result<DeviceMessage, ResponseError> {
+    let mut buf = [0u8; max_frame_len(DeviceMessage::MAX_SERIALIZED_LEN)];
+    read_frame(port, &mut buf)?;
+
+    let msg = security::open(&mut buf).and_then(|frame| {
+        DeviceMessage::deserialize_in_place(frame).map_err(|e| {
+            println!("Dropping corrupted frame: {e:?}");
+            ResponseError::Corrupted
+        })
+    });
+    MONITOR.lock().unwrap().received(msg.is_err());
+    let msg = msg?;
+    println!("Deserialized DeviceMessage: `{msg:?}`");
+    Ok(msg)
+}
 
+/// Read bytes into `buf` until a frame terminator is received or `buf` is full
+pub(crate) fn read_frame(port: &mut SerialPort, buf: &mut [u8]) -> Result<(), ResponseError> {
     // Read byte-by-byte until we receive a packet frame
-    for idx in 0..MAX_LEN {
-        let byte = &mut resp_buf[idx..idx + 1];
+    for idx in 0..buf.len() {
+        let byte = &mut buf[idx..idx + 1];
         port.read_exact(byte).map_err(|e| match e.kind() {
             io::ErrorKind::TimedOut => ResponseError::Timeout,
             // Hard error on any other type of error
@@ -63,13 +287,5 @@ pub fn wait_for_response(
             break;
         }
     }
-
-    if let Some(t) = timeout {
-        port.set_read_timeout(t).unwrap();
-    }
-    let response = Response::deserialize_in_place(&mut resp_buf)
-        // Hard error on failing to deserialize a response
-        .expect("Response ABI should not have changed");
-    println!("Deserialized Response: `{response:?}`");
-    Ok(response)
+    Ok(())
 }
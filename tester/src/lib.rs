@@ -1,6 +1,18 @@
+mod events;
 mod exchange;
+mod link;
+mod security;
 mod serial;
+mod transcript;
 
+pub use events::{subscribe, unsubscribe};
 pub use exchange::exchange;
+pub use exchange::exchange_batch;
+pub use exchange::exchange_v2;
+pub use exchange::link_stats;
+pub use exchange::poll_events;
 pub use exchange::ResponseError;
-pub use serial::open;
+pub use link::{Session, DEFAULT_WINDOW};
+pub use security::{parse_key, start_encryption};
+pub use serial::{change_baud_rate, open};
+pub use transcript::{Difference, Entry, Failure, Step, Transcript};
@@ -0,0 +1,233 @@
+use std::time;
+
+use serial2::SerialPort;
+use the_protocol_serde::{
+    format::{DefaultFormat, Format},
+    link::{self, Config, Kind, Link, Packet},
+    Codec, CommandV2, DeserializeError, DeviceMessage, LinkStats, Request, ResponseV2,
+};
+
+use crate::events;
+use crate::exchange::{read_frame, ResponseError};
+use crate::security::{self, max_frame_len};
+
+/// Capacity of the window of the host
+const WINDOW_CAPACITY: usize = 16;
+
+/// Window that keeps the command queue of the device full without overflowing it. The device
+/// answers the commands that overflow it with [ResponseV2::Busy] right away, i.e., ahead of the
+/// replies to earlier commands.
+pub const DEFAULT_WINDOW: usize = 4;
+
+/// Time after which unacknowledged frames are sent again until the round-trip time has been
+/// measured, and the shortest and longest such time
+const RETRANSMIT_TIMEOUT_MS: u64 = 200;
+const MIN_RETRANSMIT_TIMEOUT_MS: u64 = 50;
+const MAX_RETRANSMIT_TIMEOUT_MS: u64 = 1000;
+
+/// Number of retransmissions in a row without progress after which the device is considered gone
+const MAX_RETRIES: u32 = 5;
+
+/// Longest frame sent by the device, authenticated, encrypted or not
+const FRAME_LEN: usize = max_frame_len(link::max_frame_len(DeviceMessage::MAX_SERIALIZED_LEN));
+
+/// Longest frame sent to the device, authenticated, encrypted or not
+const SEND_FRAME_LEN: usize = max_frame_len(link::max_frame_len(Request::MAX_SERIALIZED_LEN));
+
+type HostLink = Link<WINDOW_CAPACITY, { Request::MAX_SERIALIZED_LEN }>;
+
+/// Session of the sliding-window transport, see [the_protocol_serde::link]
+///
+/// Keeps up to a window of commands in flight instead of waiting for the reply to each, and sends
+/// lost frames again. The device answers in kind until it receives a plain frame, e.g., from
+/// [crate::exchange].
+pub struct Session<'a> {
+    port: &'a mut SerialPort,
+    link: Box<HostLink>,
+    /// Origin of the time passed to the link
+    epoch: time::Instant,
+    /// Read timeout of the port, restored when the session ends
+    prev_timeout: time::Duration,
+}
+
+/// Outcome of [Session::read_until]
+enum Read {
+    /// The deadline passed
+    Deadline,
+    /// A link frame of the kind was handled
+    Link(Kind),
+    /// A frame that is not part of the session was dropped
+    Other,
+}
+
+impl<'a> Session<'a> {
+    /// Starts a session with the device on `port`, keeping up to `window` commands in flight.
+    /// Blocks until the device acknowledges or timeout.
+    pub fn start(port: &'a mut SerialPort, window: usize) -> Result<Self, ResponseError> {
+        let config = Config {
+            window,
+            timeout_ms: RETRANSMIT_TIMEOUT_MS,
+            min_timeout_ms: MIN_RETRANSMIT_TIMEOUT_MS,
+            max_timeout_ms: MAX_RETRANSMIT_TIMEOUT_MS,
+            nak: true,
+        };
+        let prev_timeout = port.get_read_timeout().unwrap();
+        let mut session = Self {
+            port,
+            link: Box::new(Link::new(config)),
+            epoch: time::Instant::now(),
+            prev_timeout,
+        };
+        for _ in 0..MAX_RETRIES {
+            let mut buf = [0u8; SEND_FRAME_LEN];
+            let len = session.link.sync_frame(&mut buf).unwrap().len();
+            session.write(&mut buf, len);
+            let deadline = session.now_ms() + RETRANSMIT_TIMEOUT_MS;
+            loop {
+                match session.read_until(deadline, &mut Vec::new()) {
+                    Read::Deadline => break,
+                    Read::Link(Kind::Ack) => return Ok(session),
+                    Read::Link(_) | Read::Other => {}
+                }
+            }
+        }
+        Err(ResponseError::Timeout)
+    }
+
+    /// Sends `cmds` in order, keeping up to a window of them in flight, and waits for their
+    /// responses. Blocks until every response is received or timeout.
+    ///
+    /// Events received in the meantime are passed to the subscriber, see [crate::subscribe].
+    pub fn exchange_all(&mut self, cmds: &[CommandV2]) -> Result<Vec<ResponseV2>, ResponseError> {
+        let mut replies = Vec::with_capacity(cmds.len());
+        let mut sent = 0;
+        let mut retries = 0;
+        let mut buf = [0u8; SEND_FRAME_LEN];
+        while replies.len() < cmds.len() {
+            while sent < cmds.len() && self.link.can_send() {
+                let req = Request::V2(cmds[sent].clone());
+                println!("Sending CommandV2 `{:?}`", cmds[sent]);
+                let seq = self
+                    .link
+                    .send(&req, self.now_ms())
+                    // Hard error on failing to serialize a command on the host
+                    .expect("extended commands should always serialize");
+                let len = self.link.frame(seq, &mut buf).unwrap().len();
+                self.write(&mut buf, len);
+                sent += 1;
+            }
+
+            let in_flight = self.link.in_flight();
+            let received = replies.len();
+            let deadline = self
+                .link
+                .deadline()
+                .unwrap_or(self.now_ms() + RETRANSMIT_TIMEOUT_MS);
+            // Wait for a frame unless there is room for the next command. The frame may call for
+            // an acknowledgement or ask for frames again.
+            if replies.len() < cmds.len() && !(sent < cmds.len() && self.link.can_send()) {
+                self.read_until(deadline, &mut replies);
+            }
+            if self.link.in_flight() < in_flight || replies.len() > received {
+                retries = 0;
+            }
+
+            if self.link.poll(self.now_ms()) {
+                retries += 1;
+                if retries > MAX_RETRIES {
+                    return Err(ResponseError::Timeout);
+                }
+                for seq in self.link.unacked() {
+                    println!("Retransmitting frame {seq}");
+                    let len = self.link.frame(seq, &mut buf).unwrap().len();
+                    self.write(&mut buf, len);
+                }
+            }
+            if self.link.needs_ack() {
+                let len = self.link.ack_frame(&mut buf).unwrap().len();
+                self.write(&mut buf, len);
+            }
+        }
+        Ok(replies)
+    }
+
+    /// Statistics of the host end of the session, with the round-trip time measured from each
+    /// data frame to its acknowledgement. Query the device for its end with
+    /// [CommandV2::LinkStats].
+    pub fn stats(&self) -> LinkStats {
+        self.link.stats()
+    }
+
+    /// Reads and handles one frame, unless `deadline` passes first. Replies delivered by the frame
+    /// are appended to `replies`.
+    fn read_until(&mut self, deadline: u64, replies: &mut Vec<ResponseV2>) -> Read {
+        let Some(remaining) = deadline.checked_sub(self.now_ms()).filter(|ms| *ms > 0) else {
+            return Read::Deadline;
+        };
+        self.port
+            .set_read_timeout(time::Duration::from_millis(remaining))
+            .unwrap();
+        let mut buf = [0u8; FRAME_LEN];
+        if let Err(ResponseError::Timeout) = read_frame(self.port, &mut buf) {
+            return Read::Deadline;
+        }
+
+        // A frame garbled on the line fails verification, so it is asked for again too
+        let unpacked = security::open(&mut buf)
+            .map_err(|_| DeserializeError::Unauthenticated)
+            .and_then(link::unpack);
+        match unpacked {
+            Ok(Packet::Link(header, payload)) => {
+                if let Some(payload) = self.link.receive(header, payload, self.now_ms()) {
+                    let msg: DeviceMessage = DefaultFormat::from_slice(payload)
+                        // Hard error on failing to deserialize a response
+                        .expect("device should send a reply or an event");
+                    println!("Received DeviceMessage: `{msg:?}`");
+                    match msg {
+                        DeviceMessage::Reply(reply) => replies.push(reply.into()),
+                        DeviceMessage::Event(event) => events::publish(event),
+                    }
+                }
+                Read::Link(header.kind)
+            }
+            // E.g., a reply of a device that has restarted since the session started
+            Ok(Packet::Plain(bytes))
+                if DefaultFormat::from_slice::<DeviceMessage>(bytes).is_ok() =>
+            {
+                println!("Ignoring frame outside of the session: `{bytes:?}`");
+                Read::Other
+            }
+            // A link frame with a damaged tag reads as a plain frame
+            Ok(Packet::Plain(_)) => {
+                println!("Dropping corrupted frame");
+                self.link.reject();
+                Read::Other
+            }
+            // Ask for the frame again rather than wait for the device to time out
+            Err(e) => {
+                println!("Dropping corrupted frame: {e:?}");
+                self.link.reject();
+                Read::Other
+            }
+        }
+    }
+
+    /// Writes the frame in `buf[..len]`, authenticated or encrypted if protecting frames
+    fn write(&mut self, buf: &mut [u8], len: usize) {
+        let frame = security::seal(buf, len);
+        self.port
+            .write_all(frame)
+            // Hard error on failing to write over serial
+            .unwrap();
+    }
+
+    fn now_ms(&self) -> u64 {
+        self.epoch.elapsed().as_millis() as u64
+    }
+}
+
+impl Drop for Session<'_> {
+    fn drop(&mut self) {
+        self.port.set_read_timeout(self.prev_timeout).ok();
+    }
+}
@@ -0,0 +1,158 @@
+use std::{
+    env,
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use serial2::SerialPort;
+use the_protocol_serde::{
+    auth::{self, Authenticator, Direction},
+    encryption::{self, Initiator, Session, HELLO_FRAME_LEN, RANDOM_LEN},
+    link, Codec, DeserializeError, DeviceMessage,
+};
+
+use crate::exchange::{read_frame, ResponseError};
+
+/// Time to wait for the device to answer a hello
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Number of hellos sent before the device is considered gone
+const HANDSHAKE_ATTEMPTS: usize = 3;
+
+/// Longest frame sent by the device, e.g., a reply of the previous session
+const FRAME_LEN: usize = max_frame_len(link::max_frame_len(DeviceMessage::MAX_SERIALIZED_LEN));
+
+/// How the frames exchanged with the device are protected
+enum Mode {
+    /// Frames are sent and read as they are
+    Plain,
+    /// Frames are authenticated, for devices built with the `auth` feature
+    Authenticated(Authenticator),
+    /// Frames are encrypted, for devices built with the `encryption` feature
+    Encrypted(Session),
+}
+
+/// Authenticates the frames exchanged with the device when a key is given as 32 hex digits in the
+/// `AUTH_KEY` environment variable, for devices built with the `auth` feature and the same key.
+/// Frames are encrypted once a session has been started with [start_encryption], e.g., by
+/// [crate::open] when a key is given in `ENCRYPTION_KEY`.
+static MODE: LazyLock<Mutex<Mode>> = LazyLock::new(|| {
+    let mode = match env::var("AUTH_KEY") {
+        Ok(key) => {
+            println!("Authenticating frames with the key in AUTH_KEY");
+            let key = parse_key(&key).expect("AUTH_KEY must be 32 hex digits");
+            Mode::Authenticated(Authenticator::new(key, Direction::ToDevice))
+        }
+        Err(_) => Mode::Plain,
+    };
+    Mutex::new(mode)
+});
+
+/// Length of the buffer for a frame carrying a frame of at most `frame_len` bytes, authenticated,
+/// encrypted or not
+pub(crate) const fn max_frame_len(frame_len: usize) -> usize {
+    let authenticated = auth::max_frame_len(frame_len);
+    let encrypted = encryption::max_frame_len(frame_len);
+    if authenticated > encrypted {
+        authenticated
+    } else {
+        encrypted
+    }
+}
+
+/// Parses a key of hex digits, e.g., of the `AUTH_KEY` or `ENCRYPTION_KEY` environment variable.
+/// Returns `None` unless `s` holds exactly `N` bytes.
+pub fn parse_key<const N: usize>(s: &str) -> Option<[u8; N]> {
+    if s.len() != 2 * N || !s.is_ascii() {
+        return None;
+    }
+    let mut key = [0; N];
+    for (idx, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[2 * idx..2 * idx + 2], 16).ok()?;
+    }
+    Some(key)
+}
+
+/// Starts a session of encrypted frames with the device on `port` with the pre-shared `key`,
+/// see [the_protocol_serde::encryption]. Blocks until the device answers the handshake or
+/// timeout. Every frame exchanged with the device afterwards is encrypted.
+///
+/// The session ends when the device resets, after which a new one must be started.
+pub fn start_encryption(port: &mut SerialPort, key: encryption::Key) -> Result<(), ResponseError> {
+    let mut random = [0; RANDOM_LEN];
+    // Hard error on a host without a source of randomness
+    getrandom::getrandom(&mut random).expect("the host should provide random values");
+    let initiator = Initiator::new(key, random);
+    let mut hello = [0u8; HELLO_FRAME_LEN];
+    let hello = initiator.hello().encode(&mut hello).unwrap();
+
+    let prev_timeout = port.get_read_timeout().unwrap();
+    port.set_read_timeout(HANDSHAKE_TIMEOUT).unwrap();
+    let mut session = None;
+    'attempts: for _ in 0..HANDSHAKE_ATTEMPTS {
+        println!("Sending hello");
+        port.write_all(hello)
+            // Hard error on failing to write over serial
+            .unwrap();
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        while Instant::now() < deadline {
+            let mut frame = [0u8; FRAME_LEN];
+            if let Err(ResponseError::Timeout) = read_frame(port, &mut frame) {
+                break;
+            }
+            // E.g., replies encrypted for the previous session
+            if !encryption::is_hello(&frame) {
+                continue;
+            }
+            match initiator.finish(&mut frame) {
+                Ok(s) => {
+                    session = Some(s);
+                    break 'attempts;
+                }
+                Err(e) => println!("Dropping unauthenticated answer: {e:?}"),
+            }
+        }
+    }
+    port.set_read_timeout(prev_timeout).unwrap();
+
+    let session = session.ok_or(ResponseError::Timeout)?;
+    println!("Encrypting frames with the key of the session");
+    *MODE.lock().unwrap() = Mode::Encrypted(session);
+    Ok(())
+}
+
+/// Wraps the COBS packet in `buf[..len]` into an authenticated or encrypted frame in place, if
+/// protecting frames. Returns the frame to write.
+pub(crate) fn seal(buf: &mut [u8], len: usize) -> &[u8] {
+    let sealed = match &mut *MODE.lock().unwrap() {
+        Mode::Plain => return &buf[..len],
+        Mode::Authenticated(authenticator) => {
+            // The time of day keeps the nonces increasing across runs of the tester
+            let now = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap();
+            authenticator.advance(now.as_micros() as u64);
+            authenticator.seal(buf, len)
+        }
+        Mode::Encrypted(session) => session.seal(buf, len),
+    };
+    // Hard error on a buffer not sized with [max_frame_len]
+    sealed.expect("buffer should fit the frame")
+}
+
+/// Verifies the authenticated or encrypted frame in `frame` and replaces it in place with the
+/// frame it carries, if protecting frames. Returns the frame to decode.
+pub(crate) fn open(frame: &mut [u8]) -> Result<&mut [u8], ResponseError> {
+    let opened = match &mut *MODE.lock().unwrap() {
+        Mode::Plain => return Ok(frame),
+        Mode::Authenticated(authenticator) => authenticator.open(frame),
+        Mode::Encrypted(session) => session.open(frame),
+    };
+    opened.map_err(|e| {
+        println!("Dropping unauthenticated frame: {e:?}");
+        match e {
+            DeserializeError::Unauthenticated => ResponseError::Unauthenticated,
+            _ => ResponseError::Corrupted,
+        }
+    })
+}
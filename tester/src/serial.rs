@@ -1,6 +1,13 @@
-use std::{env, io, sync, time::Duration};
+use std::{
+    env, io, sync, thread,
+    time::{Duration, Instant},
+};
 
 use serial2::SerialPort;
+use the_protocol_serde::{CommandV2, PayloadV2, ResponseV2, BAUD_FALLBACK_MS, DEFAULT_BAUD_RATE};
+
+use crate::exchange::{exchange_v2, ResponseError};
+use crate::security::{parse_key, start_encryption};
 
 /// File path to serial terminal, e.g., "/dev/ttyUSB0". Can be specified using the `COM_PATH`
 /// environment variable.
@@ -29,12 +36,20 @@ static COM_PATH: sync::LazyLock<String> = sync::LazyLock::new(|| {
     })
 });
 
-// One second timeout for getting a response from the device
+// One second timeout for writing to the device. The timeout for a response adapts to the
+// round-trip time, see [crate::link_stats].
 const DEFAULT_TIMEOUT: Duration = Duration::from_millis(1000);
 
-/// Opens a serial port
+/// Time for the device to switch to a new baud rate once the line has drained
+const SWITCH_DELAY: Duration = Duration::from_millis(10);
+
+/// Timeout for the reply to a command sent to confirm a new baud rate
+const PROBE_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Opens a serial port. Starts a session of encrypted frames when a key is given as 64 hex digits
+/// in the `ENCRYPTION_KEY` environment variable, see [crate::start_encryption].
 pub fn open() -> io::Result<SerialPort> {
-    let mut port = SerialPort::open(&*COM_PATH, 115200)?;
+    let mut port = SerialPort::open(&*COM_PATH, DEFAULT_BAUD_RATE)?;
 
     // Needed for windows, but should not hurt on Linux
     port.set_dtr(true)?;
@@ -42,5 +57,51 @@ pub fn open() -> io::Result<SerialPort> {
     port.set_write_timeout(DEFAULT_TIMEOUT)?;
     port.set_read_timeout(DEFAULT_TIMEOUT)?;
 
+    if let Ok(key) = env::var("ENCRYPTION_KEY") {
+        let key = parse_key(&key).expect("ENCRYPTION_KEY must be 64 hex digits");
+        start_encryption(&mut port, key)
+            .map_err(|e| io::Error::other(format!("handshake failed: {e:?}")))?;
+    }
+
     Ok(port)
 }
+
+/// Switches the device and `port` to the baud rate `rate`, see [CommandV2::SetBaudRate]. Blocks
+/// until the device answers at the new rate, or falls back to the current rate and returns
+/// [ResponseError::Timeout] when it does not.
+///
+/// The device must be talked to with plain frames, as a [crate::Session] keeps its own timing.
+pub fn change_baud_rate(port: &mut SerialPort, rate: u32) -> Result<(), ResponseError> {
+    let prev_rate = port.get_configuration().unwrap().get_baud_rate().unwrap();
+    match exchange_v2(&CommandV2::SetBaudRate(rate), port, None)? {
+        ResponseV2::Ok(None) => {}
+        resp => return Err(ResponseError::Unexpected(Box::new(resp))),
+    }
+    set_baud_rate(port, rate);
+    thread::sleep(SWITCH_DELAY);
+
+    // Confirm the rate with commands at it. Give up with time to spare before the device does,
+    // so that both ends end up at the same rate.
+    let give_up = Instant::now() + Duration::from_millis(BAUD_FALLBACK_MS / 2);
+    while Instant::now() < give_up {
+        // Any other outcome is a reply garbled at the new rate, or a rejection of a garbled command
+        let reply = exchange_v2(&CommandV2::FirmwareInfo, port, Some(PROBE_TIMEOUT));
+        if let Ok(ResponseV2::Ok(Some(PayloadV2::FirmwareInfo(_)))) = reply {
+            return Ok(());
+        }
+    }
+    println!("No reply at {rate} baud, falling back to {prev_rate} baud");
+    set_baud_rate(port, prev_rate);
+    Err(ResponseError::Timeout)
+}
+
+/// Switches `port` to the baud rate `rate`, dropping the bytes received at the previous rate
+fn set_baud_rate(port: &mut SerialPort, rate: u32) {
+    let mut settings = port.get_configuration().unwrap();
+    settings
+        .set_baud_rate(rate)
+        // Hard error on a rate that the serial adapter does not support
+        .unwrap();
+    port.set_configuration(&settings).unwrap();
+    port.discard_input_buffer().unwrap();
+}
@@ -0,0 +1,238 @@
+use std::{fmt, fs, io, path::Path, thread, time};
+
+use serde::{Deserialize, Serialize};
+use serial2::SerialPort;
+use the_protocol::{Command, Response};
+
+use crate::exchange::{exchange, ResponseError};
+
+/// A step of a scenario, see [Transcript::record]
+#[derive(Clone, Debug)]
+pub enum Step {
+    /// Exchange a command with the device
+    Exchange(Command),
+    /// Exchange the command built by the function when the step is run, e.g., for commands that
+    /// carry a date-time relative to the current time
+    ExchangeBuilt(fn() -> Command),
+    /// Wait before the next step, e.g., for a scheduled function to run
+    Sleep(time::Duration),
+}
+
+/// Why a command got no response, see [ResponseError]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum Failure {
+    /// See [ResponseError::Timeout]
+    Timeout,
+    /// See [ResponseError::Corrupted]
+    Corrupted,
+    /// See [ResponseError::Unauthenticated]
+    Unauthenticated,
+    /// See [ResponseError::Unexpected]
+    Unexpected,
+}
+
+impl From<&ResponseError> for Failure {
+    fn from(e: &ResponseError) -> Self {
+        match e {
+            ResponseError::Timeout => Failure::Timeout,
+            ResponseError::Corrupted => Failure::Corrupted,
+            ResponseError::Unauthenticated => Failure::Unauthenticated,
+            ResponseError::Unexpected(_) => Failure::Unexpected,
+        }
+    }
+}
+
+/// A command sent to the device and what came back
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Entry {
+    /// Time since the start of the scenario at which the command was sent, in microseconds
+    pub sent_us: u64,
+    pub command: Command,
+    pub reply: Result<Response, Failure>,
+    /// Time from sending the command to receiving the reply or giving up, in microseconds
+    pub latency_us: u64,
+}
+
+/// The commands of a scenario and the responses of a device with their timing
+///
+/// Record a scenario once against a device known to be good, e.g., the reference firmware, and
+/// save the transcript. Then record the same scenario against another device and [compare] the
+/// two.
+///
+/// [compare]: Transcript::compare
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+pub struct Transcript {
+    pub entries: Vec<Entry>,
+}
+
+/// A semantic difference between an expected and an actual transcript, see [Transcript::compare]
+#[derive(Clone, Debug, PartialEq)]
+pub enum Difference {
+    /// The scenarios differ, i.e., the transcripts are not comparable from entry `index` on
+    Command {
+        index: usize,
+        expected: Command,
+        actual: Command,
+    },
+    /// The devices replied differently to the command at `index`
+    Reply {
+        index: usize,
+        expected: Result<Response, Failure>,
+        actual: Result<Response, Failure>,
+    },
+    /// The latencies of the command at `index` differ by more than the tolerance
+    Latency {
+        index: usize,
+        expected: time::Duration,
+        actual: time::Duration,
+    },
+    /// The actual transcript ends at `index` while the expected one goes on
+    Missing { index: usize },
+    /// The actual transcript goes on from `index` while the expected one ends
+    Extra { index: usize },
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Difference::Command {
+                index,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "#{index}: expected command `{expected:?}`, sent `{actual:?}`"
+            ),
+            Difference::Reply {
+                index,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "#{index}: expected `{}`, got `{}`",
+                fmt_reply(expected),
+                fmt_reply(actual)
+            ),
+            Difference::Latency {
+                index,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "#{index}: expected a reply in {expected:?}, got one in {actual:?}"
+            ),
+            Difference::Missing { index } => write!(f, "#{index}: missing from the transcript"),
+            Difference::Extra { index } => write!(f, "#{index}: not in the expected transcript"),
+        }
+    }
+}
+
+impl Transcript {
+    /// Runs the scenario `steps` against the device on `port` and records every command with its
+    /// reply and timing. Blocks until the scenario is complete.
+    ///
+    /// Commands that get no reply are recorded as [Failure]s rather than stopping the scenario.
+    pub fn record(steps: &[Step], port: &mut SerialPort) -> Self {
+        let start = time::Instant::now();
+        let mut entries = vec![];
+        for step in steps {
+            let command = match step {
+                Step::Exchange(command) => command.clone(),
+                Step::ExchangeBuilt(build) => build(),
+                Step::Sleep(duration) => {
+                    thread::sleep(*duration);
+                    continue;
+                }
+            };
+            let sent = time::Instant::now();
+            let reply = exchange(&command, port, None);
+            let latency = sent.elapsed();
+            entries.push(Entry {
+                sent_us: (sent - start).as_micros() as u64,
+                command,
+                reply: reply.map_err(|e| Failure::from(&e)),
+                latency_us: latency.as_micros() as u64,
+            });
+        }
+        Self { entries }
+    }
+
+    /// Saves the transcript as JSON to `path`
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let json = serde_json::to_string_pretty(self).map_err(io::Error::other)?;
+        fs::write(path, json)
+    }
+
+    /// Loads a transcript saved with [Transcript::save] from `path`
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let json = fs::read_to_string(path)?;
+        serde_json::from_str(&json).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Compares the transcript `actual` against this one, which is expected. Returns the
+    /// differences in the order of the entries, none if the transcripts match.
+    ///
+    /// The comparison is semantic: the date-times carried by the commands, which differ from run
+    /// to run, are ignored, and the latencies match if they differ by at most `tolerance`.
+    pub fn compare(&self, actual: &Transcript, tolerance: time::Duration) -> Vec<Difference> {
+        let mut differences = vec![];
+        for (index, (expected, actual)) in self.entries.iter().zip(&actual.entries).enumerate() {
+            if !same_command(&expected.command, &actual.command) {
+                differences.push(Difference::Command {
+                    index,
+                    expected: expected.command.clone(),
+                    actual: actual.command.clone(),
+                });
+                // The rest of the scenario is not comparable
+                return differences;
+            }
+            if !same_reply(&expected.reply, &actual.reply) {
+                differences.push(Difference::Reply {
+                    index,
+                    expected: expected.reply.clone(),
+                    actual: actual.reply.clone(),
+                });
+            }
+            let expected = time::Duration::from_micros(expected.latency_us);
+            let actual = time::Duration::from_micros(actual.latency_us);
+            if expected.abs_diff(actual) > tolerance {
+                differences.push(Difference::Latency {
+                    index,
+                    expected,
+                    actual,
+                });
+            }
+        }
+        let (expected, actual) = (self.entries.len(), actual.entries.len());
+        differences.extend((actual..expected).map(|index| Difference::Missing { index }));
+        differences.extend((expected..actual).map(|index| Difference::Extra { index }));
+        differences
+    }
+}
+
+/// Whether the commands are the same up to the date-times they carry
+fn same_command(a: &Command, b: &Command) -> bool {
+    match (a, b) {
+        (Command::SetDateTime(a), Command::SetDateTime(b)) => a.is_some() == b.is_some(),
+        (Command::Schedule(a, _), Command::Schedule(b, _)) => a == b,
+        _ => a == b,
+    }
+}
+
+/// Whether the replies are the same up to the date-times of the commands they carry
+fn same_reply(a: &Result<Response, Failure>, b: &Result<Response, Failure>) -> bool {
+    match (a, b) {
+        (Ok(Response::OkRecovered(a, a_cmd)), Ok(Response::OkRecovered(b, b_cmd))) => {
+            a == b && same_command(a_cmd, b_cmd)
+        }
+        _ => a == b,
+    }
+}
+
+/// Formats a reply of an entry for [Difference]
+fn fmt_reply(reply: &Result<Response, Failure>) -> String {
+    match reply {
+        Ok(response) => format!("{response:?}"),
+        Err(failure) => format!("{failure:?}"),
+    }
+}
@@ -0,0 +1,85 @@
+//! Fills a command queue of [the_protocol_serde::COMMAND_QUEUE_LEN] commands the way the device
+//! does, and checks the [the_protocol_serde::ResponseV2::Busy] replies to the commands that do not
+//! fit
+//!
+//! Checks that the queue takes exactly its capacity in order, that the commands that do not fit
+//! are answered in the revision of the request, and that room is made as commands are processed.
+//!
+//! ```sh
+//! cargo run --example admission
+//! COMMAND_QUEUE_LEN=1 cargo run --example admission
+//! ```
+use std::collections::VecDeque;
+
+use the_protocol_serde::{
+    COMMAND_QUEUE_LEN, Codec, Command, CommandV2, DeviceMessage, RejectReason, Reply, Request,
+    Response, ResponseV2, Version, admit, parse_capacity,
+};
+
+/// Bounded queue like the channel of the device
+struct Queue(VecDeque<(CommandV2, Version)>);
+
+impl Queue {
+    fn try_send(&mut self, cmd: CommandV2, ver: Version) -> Result<(), ()> {
+        if self.0.len() == COMMAND_QUEUE_LEN {
+            return Err(());
+        }
+        self.0.push_back((cmd, ver));
+        Ok(())
+    }
+}
+
+fn main() {
+    assert_eq!(parse_capacity(None, 4), 4);
+    assert_eq!(parse_capacity(Some("16"), 4), 16);
+
+    let mut queue = Queue(VecDeque::new());
+    let requests: Vec<Request> = (0..COMMAND_QUEUE_LEN as u64 + 2)
+        .map(|n| match n % 2 {
+            0 => Request::V2(CommandV2::SetCounter(n)),
+            _ => Request::Legacy(Command::Counter),
+        })
+        .collect();
+
+    // The queue takes exactly its capacity, in order
+    let mut busy = vec![];
+    for req in requests.clone() {
+        if let Some(reply) = admit(req, |cmd, ver| queue.try_send(cmd, ver)) {
+            busy.push(reply);
+        }
+    }
+    assert_eq!(queue.0.len(), COMMAND_QUEUE_LEN);
+    for ((cmd, ver), req) in queue.0.iter().zip(&requests) {
+        assert_eq!(*cmd, CommandV2::from(req.clone()));
+        assert_eq!(*ver, req.version());
+    }
+
+    // The rest are answered in the revision of the request
+    assert_eq!(busy.len(), 2);
+    for (reply, req) in busy.into_iter().zip(&requests[COMMAND_QUEUE_LEN..]) {
+        let expected = match req.version() {
+            Version::V2 => Reply::V2(ResponseV2::Busy),
+            Version::Legacy => Reply::Legacy(Response::Rejected(RejectReason::InternalError)),
+        };
+        assert_eq!(reply, expected);
+        // The host decodes the reply it expects
+        let mut buf = [0u8; DeviceMessage::MAX_SERIALIZED_LEN];
+        let len = DeviceMessage::Reply(reply)
+            .serialize(&mut buf)
+            .unwrap()
+            .len();
+        match DeviceMessage::deserialize_in_place(&mut buf[..len]).unwrap() {
+            DeviceMessage::Reply(reply) => assert_eq!(reply, expected),
+            msg => panic!("expected a reply, got {msg:?}"),
+        }
+    }
+
+    // Processing a command makes room for exactly one more
+    queue.0.pop_front();
+    let req = Request::V2(CommandV2::Counter);
+    assert!(admit(req.clone(), |cmd, ver| queue.try_send(cmd, ver)).is_none());
+    assert!(admit(req, |cmd, ver| queue.try_send(cmd, ver)).is_some());
+
+    println!("queue of {COMMAND_QUEUE_LEN} commands");
+    println!("ok");
+}
@@ -0,0 +1,114 @@
+//! Seals frames into authenticated frames of [the_protocol_serde::auth] and opens them again
+//!
+//! Checks that the frames pass through unchanged, and that frames that were tampered with,
+//! replayed, reflected back to their sender, sealed with another key or not sealed at all are
+//! rejected. Also checks that a device that was reset continues above the nonces of the host, and
+//! that one that resumes above a saved mark rejects the frames recorded before the reset.
+//!
+//! ```sh
+//! cargo run --example auth --features auth
+//! ```
+use the_protocol_serde::{
+    Codec, CommandV2, DeserializeError, DeviceMessage, PayloadV2, Reply, Request, ResponseV2,
+    auth::{self, Authenticator, Direction, Key},
+    link::{self, Config, Link},
+};
+
+const KEY: Key = *b"sixteen byte key";
+const LEN: usize = auth::max_frame_len(link::max_frame_len(Request::MAX_SERIALIZED_LEN));
+
+fn main() {
+    let mut host = Authenticator::new(KEY, Direction::ToDevice);
+    let mut device = Authenticator::new(KEY, Direction::ToHost);
+    host.advance(1_760_000_000_000_000);
+
+    // Plain frames and link frames pass through unchanged
+    let req = Request::V2(CommandV2::SetCounter(u64::MAX));
+    let mut buf = [0u8; LEN];
+    let plain = req.serialize(&mut buf).unwrap().to_vec();
+    let sealed = seal(&mut host, &plain);
+    assert_eq!(open(&mut device, &sealed).unwrap(), plain);
+
+    let mut link = Link::<1, { Request::MAX_SERIALIZED_LEN }>::new(Config {
+        window: 1,
+        timeout_ms: 100,
+        min_timeout_ms: 10,
+        max_timeout_ms: 1000,
+        nak: true,
+    });
+    let seq = link.send(&req, 0).unwrap();
+    let framed = link.frame(seq, &mut buf).unwrap().to_vec();
+    assert_eq!(
+        open(&mut device, &seal(&mut host, &framed)).unwrap(),
+        framed
+    );
+
+    // A replay of a frame that was accepted, and older frames
+    let older = seal(&mut host, &plain);
+    let newer = seal(&mut host, &plain);
+    assert!(open(&mut device, &newer).is_ok());
+    assert!(rejected(open(&mut device, &newer)));
+    assert!(rejected(open(&mut device, &older)));
+
+    // Any change to the frame, including the nonce and the MAC
+    let sealed = seal(&mut host, &plain);
+    for i in 0..sealed.len() - 1 {
+        for bit in 0..8 {
+            let mut tampered = sealed.clone();
+            tampered[i] ^= 1 << bit;
+            assert!(open(&mut device, &tampered).is_err(), "byte {i} bit {bit}");
+        }
+    }
+    assert!(open(&mut device, &sealed).is_ok());
+
+    // A frame of the host sent back to the host, another key and a frame that was not sealed
+    let sealed = seal(&mut host, &plain);
+    assert!(rejected(open(&mut host, &sealed)));
+    let mut other = Authenticator::new(*b"another key, too", Direction::ToDevice);
+    other.advance(u64::MAX / 2);
+    let forged = seal(&mut other, &plain);
+    assert!(rejected(open(&mut device, &forged)));
+    assert!(rejected(open(&mut device, &plain)));
+
+    // A device that was reset numbers its frames above the latest nonce of the host
+    let reply = DeviceMessage::Reply(Reply::V2(ResponseV2::Ok(Some(PayloadV2::Counter(1)))));
+    let reply = reply.serialize(&mut buf).unwrap().to_vec();
+    assert!(open(&mut host, &seal(&mut device, &reply)).is_ok());
+    let mut device = Authenticator::new(KEY, Direction::ToHost);
+    assert!(rejected(open(&mut host, &seal(&mut device, &reply))));
+    assert!(open(&mut device, &seal(&mut host, &plain)).is_ok());
+    assert_eq!(open(&mut host, &seal(&mut device, &reply)).unwrap(), reply);
+
+    // A device that resumes above the mark it saved before a reset rejects the frames recorded
+    // before the reset. Its answer raises the nonces of the host above the mark.
+    let recorded = seal(&mut host, &plain);
+    assert!(open(&mut device, &recorded).is_ok());
+    let mark = device.high_water() + 1000;
+    let mut device = Authenticator::resume(KEY, Direction::ToHost, mark);
+    assert!(rejected(open(&mut device, &recorded)));
+    assert!(rejected(open(&mut device, &seal(&mut host, &plain))));
+    assert!(open(&mut host, &seal(&mut device, &reply)).is_ok());
+    assert!(open(&mut device, &seal(&mut host, &plain)).is_ok());
+    assert!(device.high_water() > mark);
+
+    println!(
+        "{} bytes of overhead per frame, largest request frame {LEN} bytes",
+        auth::OVERHEAD
+    );
+    println!("ok");
+}
+
+fn seal(auth: &mut Authenticator, frame: &[u8]) -> Vec<u8> {
+    let mut buf = [0u8; LEN];
+    buf[..frame.len()].copy_from_slice(frame);
+    auth.seal(&mut buf, frame.len()).unwrap().to_vec()
+}
+
+fn rejected(result: Result<Vec<u8>, DeserializeError>) -> bool {
+    matches!(result, Err(DeserializeError::Unauthenticated))
+}
+
+fn open(auth: &mut Authenticator, frame: &[u8]) -> Result<Vec<u8>, DeserializeError> {
+    let mut buf = frame.to_vec();
+    auth.open(&mut buf).map(|packet| packet.to_vec())
+}
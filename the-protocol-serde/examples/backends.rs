@@ -0,0 +1,165 @@
+//! Encodes messages with both serializer backends and checks that they carry the same values
+//!
+//! ```sh
+//! cargo run --example backends --features postcard
+//! ```
+use the_protocol_serde::{
+    Batch, BatchMode, BatchResponse, BlinkPattern, BlinkShape, Codec, Command, CommandV2,
+    DeviceMessage, Event, Funct, MaxSize, MorseText, PayloadV2, Recurrence, RejectReason, Reply,
+    Request, Response, ResponseV2, SDateTime,
+    chrono::{TimeZone, Utc},
+    format::{self, DefaultFormat, Format, Postcard, Ssmarshal},
+};
+
+/// Buffer large enough for any message with either backend
+const BUF_LEN: usize = 1024;
+
+fn main() {
+    let requests = requests();
+    let replies = replies();
+    for req in &requests {
+        round_trip(req);
+    }
+    for reply in &replies {
+        round_trip(reply);
+        round_trip(&DeviceMessage::Reply(reply.clone()));
+    }
+    round_trip(&DeviceMessage::Event(Event::JobFired {
+        id: u32::MAX,
+        at: date_time(),
+    }));
+    worst_case();
+    default_backend(&requests[0]);
+
+    // Small integers shrink to a byte, only values close to `u64::MAX` grow
+    let dt = Request::Legacy(Command::SetDateTime(Some(date_time())));
+    let (ssmarshal, postcard) = (frame::<Ssmarshal, _>(&dt), frame::<Postcard, _>(&dt));
+    println!(
+        "SetDateTime: {} bytes with ssmarshal, {} bytes with postcard",
+        ssmarshal.len(),
+        postcard.len()
+    );
+    assert!(postcard.len() < ssmarshal.len());
+    println!("ok");
+}
+
+fn date_time() -> SDateTime {
+    Utc.with_ymd_and_hms(2025, 10, 19, 12, 30, 5)
+        .unwrap()
+        .into()
+}
+
+fn requests() -> Vec<Request> {
+    let mut batch = Batch::new(BatchMode::Atomic);
+    batch.push(Command::Immediate(Funct::Increment)).unwrap();
+    batch
+        .push(Command::Schedule(
+            Funct::EnableBlink { period_ms: 500 },
+            date_time(),
+        ))
+        .unwrap();
+    vec![
+        Request::Legacy(Command::Reset),
+        Request::Legacy(Command::SetDateTime(Some(date_time()))),
+        Request::Legacy(Command::Immediate(Funct::EnableBlink {
+            period_ms: u64::MAX,
+        })),
+        Request::V2(CommandV2::ScheduleRecurring(
+            Funct::EnableRgb,
+            Recurrence::Repeat {
+                start: date_time(),
+                interval_s: 60,
+                count: 3,
+            },
+        )),
+        Request::V2(CommandV2::SetCounter(1 << 40)),
+        Request::V2(CommandV2::Batch(batch)),
+        Request::V2(CommandV2::SetBlinkPattern(BlinkPattern::forever(
+            BlinkShape::Morse {
+                unit_ms: 120,
+                text: MorseText::new("SOS").unwrap(),
+            },
+        ))),
+    ]
+}
+
+fn replies() -> Vec<Reply> {
+    let mut batch = BatchResponse::new();
+    batch.push(ResponseV2::Ok(None));
+    batch.push(ResponseV2::Rejected(RejectReason::IllegalCommand));
+    vec![
+        Reply::Legacy(Response::Ok(None)),
+        Reply::Legacy(Response::Rejected(RejectReason::CorruptedFrame)),
+        Reply::V2(ResponseV2::Ok(Some(PayloadV2::Counter(u64::MAX)))),
+        Reply::V2(ResponseV2::Ok(Some(PayloadV2::Batch(batch)))),
+        Reply::V2(ResponseV2::Busy),
+        Reply::V2(ResponseV2::Unauthenticated),
+    ]
+}
+
+/// Encodes `value` into a COBS frame with the backend `F`
+fn frame<F: Format, P: serde::Serialize>(value: &P) -> Vec<u8> {
+    let mut buf = [0u8; BUF_LEN];
+    format::encode::<F, _, BUF_LEN>(value, &mut buf)
+        .unwrap()
+        .to_vec()
+}
+
+fn decode<F: Format, P: serde::de::DeserializeOwned>(mut frame: Vec<u8>) -> P {
+    format::decode_in_place::<F, _>(&mut frame).unwrap()
+}
+
+/// Checks that both backends decode the value they encoded, and that a value decoded from one
+/// backend re-encodes into the same frame with the other
+fn round_trip<P>(value: &P)
+where
+    P: serde::Serialize + serde::de::DeserializeOwned + MaxSize + PartialEq + std::fmt::Debug,
+{
+    let ssmarshal = frame::<Ssmarshal, _>(value);
+    let postcard = frame::<Postcard, _>(value);
+    assert_eq!(&decode::<Ssmarshal, P>(ssmarshal.clone()), value);
+    assert_eq!(&decode::<Postcard, P>(postcard.clone()), value);
+
+    let from_ssmarshal: P = decode::<Ssmarshal, _>(ssmarshal.clone());
+    assert_eq!(frame::<Postcard, _>(&from_ssmarshal), postcard);
+    let from_postcard: P = decode::<Postcard, _>(postcard.clone());
+    assert_eq!(frame::<Ssmarshal, _>(&from_postcard), ssmarshal);
+
+    // Postcard frames never exceed the bound
+    assert!(
+        postcard.len() <= corncobs_len(P::POSTCARD_MAX_SIZE),
+        "{value:?}"
+    );
+}
+
+/// Length of a COBS frame of a message of `len` bytes in the worst case
+fn corncobs_len(len: usize) -> usize {
+    the_protocol_serde::corncobs::max_encoded_len(len)
+}
+
+/// The bound of [MaxSize] is reached by values with every integer at its widest
+fn worst_case() {
+    let widest = Funct::EnableBlink {
+        period_ms: u64::MAX,
+    };
+    let mut buf = [0u8; BUF_LEN];
+    let n = Postcard::to_slice(&widest, &mut buf).unwrap();
+    assert_eq!(n, Funct::POSTCARD_MAX_SIZE);
+    let n = Postcard::to_slice(&PayloadV2::Counter(u64::MAX), &mut buf).unwrap();
+    assert_eq!(n, 1 + u64::POSTCARD_MAX_SIZE);
+
+    assert_eq!(Request::POSTCARD_MAX_SIZE, 1 + CommandV2::POSTCARD_MAX_SIZE);
+    assert_eq!(
+        DeviceMessage::POSTCARD_MAX_SIZE,
+        1 + ResponseV2::POSTCARD_MAX_SIZE
+    );
+}
+
+/// [Codec] encodes with the backend selected by the `postcard` feature
+fn default_backend(req: &Request) {
+    let mut buf = [0u8; Request::MAX_SERIALIZED_LEN];
+    let codec = req.serialize(&mut buf).unwrap().to_vec();
+    assert_eq!(codec, frame::<DefaultFormat, _>(req));
+    let mut codec = codec;
+    assert_eq!(&Request::deserialize_in_place(&mut codec).unwrap(), req);
+}
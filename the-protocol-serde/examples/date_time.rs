@@ -0,0 +1,107 @@
+//! Checks that date-times that do not exist on the calendar are caught by
+//! [the_protocol_serde::to_utc] rather than panicking on conversion, and that commands carrying
+//! them are rejected by [Limits::allows], on their own and in a batch
+//!
+//! Such date-times decode fine, as [SDateTime] accepts any field values on the wire.
+//!
+//! ```sh
+//! cargo run --example date_time
+//! ```
+use serde::Serialize;
+use the_protocol_serde::{
+    Batch, BatchMode, Command, CommandV2, Funct, Limits, Recurrence, SDateTime,
+    chrono::{TimeZone, Utc},
+    is_valid_date_time, to_utc,
+};
+
+/// Same layout as [SDateTime], whose fields are not public
+#[derive(Serialize)]
+struct Fields(i32, u32, u32, u32, u32, u32, u32);
+
+fn main() {
+    let now = Utc.with_ymd_and_hms(2025, 2, 28, 23, 59, 59).unwrap();
+    assert_eq!(to_utc(&now.into()), Some(now));
+    assert!(is_valid_date_time(&date_time(2024, 2, 29, 0, 0, 0)));
+
+    for (y, mo, d, h, mi, s) in [
+        (2025, 13, 1, 0, 0, 0),
+        (2025, 0, 1, 0, 0, 0),
+        (2025, 2, 29, 0, 0, 0),
+        (2025, 4, 31, 0, 0, 0),
+        (2025, 1, 0, 0, 0, 0),
+        (2025, 1, 1, 24, 0, 0),
+        (2025, 1, 1, 0, 60, 0),
+        (2025, 1, 1, 0, 0, 60),
+        (i32::MAX, 1, 1, 0, 0, 0),
+    ] {
+        let dt = date_time(y, mo, d, h, mi, s);
+        assert!(!is_valid_date_time(&dt), "{dt:?}");
+        assert_eq!(to_utc(&dt), None);
+        for cmd in commands(dt.clone()) {
+            assert!(!LIMITS.allows(&cmd), "{cmd:?}");
+        }
+        // The commands of a batch are checked one by one as the batch is executed, so that the
+        // other commands of a best-effort batch still run
+        let batch = batch(dt);
+        assert!(LIMITS.allows(&CommandV2::Batch(batch.clone())));
+        let allowed: Vec<_> = batch
+            .iter()
+            .map(|cmd| LIMITS.allows(&cmd.clone().into()))
+            .collect();
+        assert_eq!(allowed, [true, false]);
+    }
+    for cmd in commands(now.into()) {
+        assert!(LIMITS.allows(&cmd), "{cmd:?}");
+    }
+    // Unsetting the time needs no date-time
+    assert!(LIMITS.allows(&CommandV2::SetDateTime(None)));
+    println!("ok");
+}
+
+const LIMITS: Limits = Limits {
+    min_blink_period_ms: 10,
+    max_blink_period_ms: 1000,
+    min_blink_step_ms: 10,
+    max_blink_step_ms: 1000,
+    min_rgb_period_ms: 200,
+    max_rgb_period_ms: 1000,
+    max_rgb_brightness: 255,
+    min_baud_rate: 9600,
+    max_baud_rate: 115_200,
+};
+
+/// Every command that carries a date-time
+fn commands(dt: SDateTime) -> Vec<CommandV2> {
+    let recurrences = [
+        Recurrence::Periodic {
+            start: dt.clone(),
+            period_s: 60,
+        },
+        Recurrence::Repeat {
+            start: dt.clone(),
+            interval_s: 60,
+            count: 3,
+        },
+    ];
+    let mut cmds = vec![
+        CommandV2::SetDateTime(Some(dt.clone())),
+        CommandV2::Schedule(Funct::Increment, dt.clone()),
+    ];
+    cmds.extend(recurrences.map(|r| CommandV2::ScheduleRecurring(Funct::Increment, r)));
+    cmds
+}
+
+/// A batch that schedules a job at the date-time among other commands
+fn batch(dt: SDateTime) -> Batch {
+    let mut batch = Batch::new(BatchMode::BestEffort);
+    batch.push(Command::Counter).unwrap();
+    batch.push(Command::Schedule(Funct::Increment, dt)).unwrap();
+    batch
+}
+
+/// Builds a date-time as the host may send it, valid or not
+fn date_time(y: i32, mo: u32, d: u32, h: u32, mi: u32, s: u32) -> SDateTime {
+    let mut buf = [0u8; 28];
+    let len = ssmarshal::serialize(&mut buf, &Fields(y, mo, d, h, mi, s, 0)).unwrap();
+    ssmarshal::deserialize(&buf[..len]).unwrap().0
+}
@@ -0,0 +1,152 @@
+//! Compares encoding a frame in place with serializing into the buffer, copying it and encoding
+//! the copy, which is how frames used to be encoded
+//!
+//! Checks that both produce the same frames, and measures the time and the stack used to encode
+//! the largest reply. Run in release mode for representative numbers:
+//!
+//! ```sh
+//! cargo run --release --example encode_bench
+//! ```
+use std::{hint::black_box, ptr, thread, time::Instant};
+
+use the_protocol_serde::{
+    BatchResponse, Codec, Command, DeviceMessage, Funct, MAX_BATCH_LEN, Payload, Reply, Response,
+    ResponseV2, SDateTime,
+    chrono::{TimeZone, Utc},
+    cobs, corncobs,
+    format::{DefaultFormat, Format},
+};
+
+const FRAME_LEN: usize = DeviceMessage::MAX_SERIALIZED_LEN;
+const ROUNDS: u32 = 20;
+const ITERATIONS: u32 = 10_000;
+/// Bytes of stack painted below the caller of the measured function
+const PAINT_LEN: usize = 64 * 1024;
+
+fn main() {
+    same_frames();
+
+    let msg = largest_reply();
+    let mut buf = [0u8; FRAME_LEN];
+    let in_place = Codec::serialize(&msg, &mut buf).unwrap().to_vec();
+    assert_eq!(encode_with_copy(&msg, &mut [0u8; FRAME_LEN]), &in_place[..]);
+
+    // Measure on a thread, whose whole stack is mapped up front
+    let (copy_stack, in_place_stack) = thread::Builder::new()
+        .stack_size(4 * PAINT_LEN)
+        .spawn(|| {
+            let msg = largest_reply();
+            let copy = stack_usage(&|| {
+                black_box(encode_with_copy(&msg, &mut [0u8; FRAME_LEN]).len());
+            });
+            let in_place = stack_usage(&|| {
+                black_box(Codec::serialize(&msg, &mut [0u8; FRAME_LEN]).unwrap().len());
+            });
+            (copy, in_place)
+        })
+        .unwrap()
+        .join()
+        .unwrap();
+
+    let copy_time = time(|| encode_with_copy(&msg, &mut buf).len());
+    let in_place_time = time(|| Codec::serialize(&msg, &mut buf).unwrap().len());
+
+    println!(
+        "frame of {} bytes in a buffer of {FRAME_LEN}",
+        in_place.len()
+    );
+    println!("copy:     {copy_time:>6.0} ns, {copy_stack:>5} bytes of stack");
+    println!("in place: {in_place_time:>6.0} ns, {in_place_stack:>5} bytes of stack");
+    // The buffer of the copy is gone from the stack
+    assert!(in_place_stack < copy_stack);
+    println!("ok");
+}
+
+/// The encoding before frames were encoded in place
+fn encode_with_copy<'a, const N: usize>(msg: &DeviceMessage, out_buf: &'a mut [u8; N]) -> &'a [u8] {
+    let n_ser = DefaultFormat::to_slice(msg, out_buf).unwrap();
+    let buf_copy = *out_buf;
+    let n = corncobs::encode_buf(&buf_copy[0..n_ser], out_buf);
+    &out_buf[0..n]
+}
+
+/// Reply with a full batch of the longest responses
+fn largest_reply() -> DeviceMessage {
+    let at: SDateTime = Utc
+        .with_ymd_and_hms(2025, 12, 31, 23, 59, 59)
+        .unwrap()
+        .into();
+    let mut batch = BatchResponse::new();
+    for _ in 0..MAX_BATCH_LEN {
+        let cmd = Command::Schedule(Funct::EnableBlink { period_ms: 500 }, at.clone());
+        batch.push(Response::OkRecovered(Some(Payload::Counter(u64::MAX)), cmd).into());
+    }
+    let payload = the_protocol_serde::PayloadV2::Batch(batch);
+    DeviceMessage::Reply(Reply::V2(ResponseV2::Ok(Some(payload))))
+}
+
+/// Frames encoded in place are identical to those of [corncobs], around the boundaries of COBS
+/// blocks in particular, both from the closest start and from the start that
+/// [the_protocol_serde::format::encode] uses for any message that fits the buffer
+fn same_frames() {
+    let mut msg = Vec::new();
+    for len in 0..1100 {
+        for zero_every in [0, 1, 2, 200, 254, 255, 509] {
+            msg.clear();
+            msg.extend((0..len).map(|i| match zero_every {
+                0 => 0xA5,
+                n if i % n == n - 1 => 0,
+                _ => (i % 255 + 1) as u8,
+            }));
+            let mut expected = [0u8; 2048];
+            let n = corncobs::encode_buf(&msg, &mut expected);
+            let mut buf = [0u8; 2048];
+            for start in [cobs::start(len), cobs::start(buf.len())] {
+                buf[start..start + len].copy_from_slice(&msg);
+                let n_in_place = cobs::encode_in_place(&mut buf, start, len);
+                assert_eq!(
+                    &buf[..n_in_place],
+                    &expected[..n],
+                    "{len} bytes, zero every {zero_every}, from {start}"
+                );
+            }
+        }
+    }
+}
+
+/// Time of `f` in nanoseconds, the average of the fastest of several rounds to filter out
+/// interruptions
+fn time(mut f: impl FnMut() -> usize) -> f64 {
+    let mut fastest = f64::MAX;
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        for _ in 0..ITERATIONS {
+            black_box(f());
+        }
+        fastest = fastest.min(start.elapsed().as_nanos() as f64 / ITERATIONS as f64);
+    }
+    fastest
+}
+
+/// Largest depth of stack used by `f`, measured by painting the stack below this function and
+/// finding the deepest byte that `f` overwrote
+#[inline(never)]
+fn stack_usage(f: &dyn Fn()) -> usize {
+    const PAINT: u8 = 0xA5;
+    // Leave the frame of this function alone
+    let marker = 0u8;
+    let top = black_box(&marker) as *const u8 as usize - 256;
+    let bottom = top - PAINT_LEN;
+    // SAFETY: the region is within the stack of the thread, below the frames in use, and is only
+    // accessed by volatile reads and writes of bytes
+    unsafe {
+        for addr in bottom..top {
+            ptr::write_volatile(addr as *mut u8, PAINT);
+        }
+    }
+    f();
+    let deepest = (bottom..top)
+        .find(|&addr| unsafe { ptr::read_volatile(addr as *const u8) } != PAINT)
+        .unwrap_or(top);
+    top - deepest
+}
@@ -0,0 +1,139 @@
+//! Runs the handshake of [the_protocol_serde::encryption] and passes frames through the session
+//!
+//! Checks that the frames pass through unchanged and that their content does not show on the
+//! line, and that frames that were tampered with, replayed, reflected back to their sender or
+//! recorded in another session are rejected, as are hellos without the key.
+//!
+//! ```sh
+//! cargo run --example encryption --features encryption
+//! ```
+use the_protocol_serde::{
+    Codec, CommandV2, DeserializeError, DeviceMessage, PayloadV2, Reply, Request, ResponseV2,
+    encryption::{self, HELLO_FRAME_LEN, Initiator, Key, Responder, Session},
+    link::{self, Config, Link},
+};
+
+const KEY: Key = *b"a pre-shared key of 32 bytes....";
+const LEN: usize = encryption::max_frame_len(link::max_frame_len(Request::MAX_SERIALIZED_LEN));
+
+fn main() {
+    let (mut host, mut device) = handshake(&KEY, [1; 16], [2; 16]).unwrap();
+
+    // Plain frames and link frames pass through unchanged, and the content does not show
+    let req = Request::V2(CommandV2::SetCounter(0x0123_4567_89ab_cdef));
+    let mut buf = [0u8; LEN];
+    let plain = req.serialize(&mut buf).unwrap().to_vec();
+    let sealed = seal(&mut host, &plain);
+    assert_eq!(sealed.len(), plain.len() + encryption::OVERHEAD);
+    assert!(!sealed.windows(4).any(|w| w == [0xef, 0xcd, 0xab, 0x89]));
+    assert_eq!(open(&mut device, &sealed).unwrap(), plain);
+
+    let mut link = Link::<1, { Request::MAX_SERIALIZED_LEN }>::new(Config {
+        window: 1,
+        timeout_ms: 100,
+        min_timeout_ms: 10,
+        max_timeout_ms: 1000,
+        nak: true,
+    });
+    let seq = link.send(&req, 0).unwrap();
+    let framed = link.frame(seq, &mut buf).unwrap().to_vec();
+    assert_eq!(
+        open(&mut device, &seal(&mut host, &framed)).unwrap(),
+        framed
+    );
+
+    let reply = DeviceMessage::Reply(Reply::V2(ResponseV2::Ok(Some(PayloadV2::Counter(1)))));
+    let reply = reply.serialize(&mut buf).unwrap().to_vec();
+    assert_eq!(open(&mut host, &seal(&mut device, &reply)).unwrap(), reply);
+
+    // The same frame encrypts differently every time
+    assert_ne!(seal(&mut host, &plain), seal(&mut host, &plain));
+
+    // A replay of a frame that was accepted, and older frames
+    let older = seal(&mut host, &plain);
+    let newer = seal(&mut host, &plain);
+    assert!(open(&mut device, &newer).is_ok());
+    assert!(rejected(open(&mut device, &newer)));
+    assert!(rejected(open(&mut device, &older)));
+
+    // Any change to the frame, including the counter and the tag
+    let sealed = seal(&mut host, &plain);
+    for i in 0..sealed.len() - 1 {
+        for bit in 0..8 {
+            let mut tampered = sealed.clone();
+            tampered[i] ^= 1 << bit;
+            assert!(open(&mut device, &tampered).is_err(), "byte {i} bit {bit}");
+        }
+    }
+    assert!(open(&mut device, &sealed).is_ok());
+
+    // A frame of the host sent back to the host, and a frame that was not encrypted
+    let sealed = seal(&mut host, &plain);
+    assert!(rejected(open(&mut host, &sealed)));
+    assert!(rejected(open(&mut device, &plain)));
+
+    // A frame of another session, even with the same random value of the host
+    let recorded = seal(&mut host, &plain);
+    let (mut host, mut device) = handshake(&KEY, [1; 16], [3; 16]).unwrap();
+    assert!(rejected(open(&mut device, &recorded)));
+    assert_eq!(open(&mut device, &seal(&mut host, &plain)).unwrap(), plain);
+
+    // Hellos and answers without the key
+    let other = *b"another key, also of 32 bytes...";
+    assert!(handshake(&other, [1; 16], [2; 16]).is_none());
+    let initiator = Initiator::new(KEY, [4; 16]);
+    let mut hello = [0u8; HELLO_FRAME_LEN];
+    Initiator::new(other, [4; 16])
+        .hello()
+        .encode(&mut hello)
+        .unwrap();
+    let (_, answer) = Responder::new(other).accept(&mut hello, [5; 16]).unwrap();
+    let mut frame = [0u8; HELLO_FRAME_LEN];
+    answer.encode(&mut frame).unwrap();
+    assert!(initiator.finish(&mut frame).is_err());
+
+    // An answer for another host
+    let mut hello = [0u8; HELLO_FRAME_LEN];
+    Initiator::new(KEY, [6; 16])
+        .hello()
+        .encode(&mut hello)
+        .unwrap();
+    assert!(encryption::is_hello(&hello));
+    let (_, answer) = Responder::new(KEY).accept(&mut hello, [7; 16]).unwrap();
+    answer.encode(&mut frame).unwrap();
+    assert!(initiator.finish(&mut frame).is_err());
+
+    println!(
+        "{} bytes of overhead per frame, largest request frame {LEN} bytes",
+        encryption::OVERHEAD
+    );
+    println!("ok");
+}
+
+/// Runs the handshake between a host with [KEY] and a device with `key`, which contribute the
+/// random values `host` and `device`
+fn handshake(key: &Key, host: [u8; 16], device: [u8; 16]) -> Option<(Session, Session)> {
+    let initiator = Initiator::new(KEY, host);
+    let mut frame = [0u8; HELLO_FRAME_LEN];
+    initiator.hello().encode(&mut frame).ok()?;
+    let (device, answer) = Responder::new(*key).accept(&mut frame, device).ok()?;
+    let mut frame = [0u8; HELLO_FRAME_LEN];
+    answer.encode(&mut frame).ok()?;
+    let host = initiator.finish(&mut frame).ok()?;
+    Some((host, device))
+}
+
+fn rejected(result: Result<Vec<u8>, DeserializeError>) -> bool {
+    matches!(result, Err(DeserializeError::Unauthenticated))
+}
+
+fn seal(session: &mut Session, frame: &[u8]) -> Vec<u8> {
+    let mut buf = [0u8; LEN];
+    buf[..frame.len()].copy_from_slice(frame);
+    session.seal(&mut buf, frame.len()).unwrap().to_vec()
+}
+
+fn open(session: &mut Session, frame: &[u8]) -> Result<Vec<u8>, DeserializeError> {
+    let mut buf = frame.to_vec();
+    session.open(&mut buf).map(|packet| packet.to_vec())
+}
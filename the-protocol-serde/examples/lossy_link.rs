@@ -0,0 +1,351 @@
+//! Runs the sliding-window transport of [the_protocol_serde::link] between a simulated host and
+//! device over a lossy in-memory serial line
+//!
+//! Checks that every command and reply is delivered exactly once and in order at each window size
+//! and rate of damaged frames, that a window of several frames beats stop-and-wait, and that NAKs
+//! recover from damaged frames faster than the retransmission timeout. Also checks that the
+//! retransmission timeout adapts to the round-trip time without spurious retransmissions, and
+//! that the statistics of both ends account for the damage, and that no frame with a flipped bit
+//! passes as a link frame. Time is simulated, so the results are the same on every run.
+//!
+//! ```sh
+//! cargo run --example lossy_link
+//! ```
+use std::collections::VecDeque;
+
+use serde::Serialize;
+use the_protocol_serde::{
+    CommandV2, DeviceMessage, LinkStats, PayloadV2, Reply, Request, ResponseV2, RttEstimator,
+    format::{DefaultFormat, Format},
+    link::{self, Config, Kind, Link, Packet},
+};
+
+/// Bytes on the line per second at 115 200 baud, with a start and a stop bit per byte
+const BYTES_PER_S: u64 = 115_200 / 10;
+/// Latency of the USB serial adapter in each direction
+const LATENCY_MS: u64 = 2;
+/// Initial, shortest and longest retransmission timeout
+const TIMEOUT_MS: u64 = 50;
+const MIN_TIMEOUT_MS: u64 = 10;
+const MAX_TIMEOUT_MS: u64 = 1000;
+/// Enough to wrap the sequence numbers a few times
+const COMMANDS: u64 = 600;
+/// Capacity of the windows and the largest serialized message
+const W: usize = 16;
+const N: usize = 64;
+/// Simulated time after which a run is considered stuck
+const GIVE_UP_MS: u64 = 600_000;
+
+fn main() {
+    // The device keeps its link across runs, so every run starts from a stale session
+    let mut device = Endpoint::<DeviceMessage>::new(8, true);
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    flipped_bits();
+    backoff();
+
+    println!("window  damaged  nak  time (ms)  retransmissions  errors  srtt (ms)  rto (ms)");
+    let mut stop_and_wait_ms = 0;
+    for window in [1, 4, 8] {
+        for damage_percent in [0, 10, 30] {
+            let mut timeout_only_ms = 0;
+            for nak in [false, true] {
+                let run = run(&mut device, window, nak, damage_percent, &mut rng);
+                let (host, device) = (run.host, run.device);
+                println!(
+                    "{window:>6} {damage_percent:>7}% {:>4} {:>10} {:>16} {:>7} {:>10} {:>9}",
+                    if nak { "on" } else { "off" },
+                    run.elapsed_ms,
+                    host.retransmissions + device.retransmissions,
+                    host.frame_errors + device.frame_errors,
+                    host.srtt_ms,
+                    host.rto_ms,
+                );
+                // Both ends measured the round trip
+                assert!(host.srtt_ms > 0 && device.srtt_ms > 0);
+                if damage_percent == 0 {
+                    // The timeout leaves room for the round trip and its variation
+                    assert_eq!(host.retransmissions + device.retransmissions, 0);
+                    assert_eq!(host.frame_errors + device.frame_errors, 0);
+                    assert!(host.rto_ms < TIMEOUT_MS as u32);
+                } else {
+                    assert!(host.frame_errors > 0 && device.frame_errors > 0);
+                    assert!(host.retransmissions + device.retransmissions > 0);
+                }
+                if !nak {
+                    timeout_only_ms = run.elapsed_ms;
+                } else if damage_percent > 0 {
+                    // Asking for the missing frames saves waiting for the timeout
+                    assert!(run.elapsed_ms < timeout_only_ms);
+                }
+                if damage_percent == 0 && !nak {
+                    if window == 1 {
+                        stop_and_wait_ms = run.elapsed_ms;
+                    } else {
+                        // Keeping frames in flight fills the idle time of the round trips
+                        assert!(run.elapsed_ms * 2 < stop_and_wait_ms);
+                    }
+                }
+            }
+        }
+    }
+    println!("ok");
+}
+
+/// Flips every bit of a data frame and of an acknowledgement in turn, none of which may arrive as a
+/// link frame, e.g., with a sequence number or an acknowledgement off by one
+fn flipped_bits() {
+    let mut link = Link::<W, N>::new(Config {
+        window: 4,
+        timeout_ms: TIMEOUT_MS,
+        min_timeout_ms: MIN_TIMEOUT_MS,
+        max_timeout_ms: MAX_TIMEOUT_MS,
+        nak: true,
+    });
+    let seq = link
+        .send(&Request::V2(CommandV2::SetCounter(7)), 0)
+        .unwrap();
+    let mut data = [0u8; link::max_frame_len(N)];
+    let data = link.frame(seq, &mut data).unwrap().to_vec();
+    let mut ack = [0u8; link::max_frame_len(N)];
+    let ack = link.ack_frame(&mut ack).unwrap().to_vec();
+    for frame in [data, ack] {
+        assert!(matches!(
+            link::unpack(&mut frame.clone()),
+            Ok(Packet::Link(..))
+        ));
+        for bit in 0..frame.len() * 8 {
+            let mut damaged = frame.clone();
+            damaged[bit / 8] ^= 1 << (bit % 8);
+            let unpacked = link::unpack(&mut damaged);
+            assert!(!matches!(unpacked, Ok(Packet::Link(..))), "bit {bit}");
+        }
+    }
+}
+
+/// The timeout doubles after each expiry up to the longest timeout, whatever the shortest one
+fn backoff() {
+    for (initial, min) in [(300, 10), (1, 0), (0, 0)] {
+        let mut rtt = RttEstimator::new(initial, min, MAX_TIMEOUT_MS);
+        let mut rto = rtt.rto_ms();
+        for _ in 0..200 {
+            rtt.backoff();
+            assert!(rtt.rto_ms() >= rto && rtt.rto_ms() <= MAX_TIMEOUT_MS);
+            rto = rtt.rto_ms();
+        }
+        if initial > 0 {
+            assert_eq!(rto, MAX_TIMEOUT_MS);
+        }
+        rtt.progress();
+        assert_eq!(rtt.rto_ms(), initial.max(min));
+    }
+    let mut rtt = RttEstimator::new(300, 10, MAX_TIMEOUT_MS);
+    rtt.backoff();
+    assert_eq!(rtt.rto_ms(), 600);
+}
+
+struct Run {
+    elapsed_ms: u64,
+    host: LinkStats,
+    device: LinkStats,
+}
+
+/// Sends [COMMANDS] commands from a new host to `device`, which replies to each
+fn run(
+    device: &mut Endpoint<DeviceMessage>,
+    window: usize,
+    nak: bool,
+    damage_percent: u64,
+    rng: &mut Rng,
+) -> Run {
+    let mut host = Endpoint::<Request>::new(window, nak);
+    device.link = Link::new(Config {
+        nak,
+        ..device.link.config()
+    });
+    let mut to_device = Line::new(damage_percent);
+    let mut to_host = Line::new(damage_percent);
+    let mut executed = Vec::new();
+    let mut replies = Vec::new();
+
+    // Start the session, repeating the handshake until it is acknowledged
+    let mut now = 0;
+    let mut synced = false;
+    let mut buf = [0u8; link::max_frame_len(N)];
+    while !synced {
+        if now % TIMEOUT_MS == 0 {
+            to_device.write(host.link.sync_frame(&mut buf).unwrap(), now, rng);
+        }
+        device.step(now, &mut to_device, &mut to_host, rng);
+        synced = host.step(now, &mut to_host, &mut to_device, rng).synced;
+        now += 1;
+    }
+    let start = now;
+
+    host.outbox
+        .extend((0..COMMANDS).map(|n| Request::V2(CommandV2::SetCounter(n))));
+    loop {
+        for payload in device
+            .step(now, &mut to_device, &mut to_host, rng)
+            .delivered
+        {
+            let Request::V2(CommandV2::SetCounter(n)) =
+                DefaultFormat::from_slice(&payload).unwrap()
+            else {
+                panic!("unexpected request");
+            };
+            executed.push(n);
+            let resp = ResponseV2::Ok(Some(PayloadV2::Counter(n)));
+            device.outbox.push_back(Reply::V2(resp).into());
+        }
+        for payload in host.step(now, &mut to_host, &mut to_device, rng).delivered {
+            match DefaultFormat::from_slice(&payload).unwrap() {
+                DeviceMessage::Reply(Reply::V2(ResponseV2::Ok(Some(PayloadV2::Counter(n))))) => {
+                    replies.push(n)
+                }
+                msg => panic!("unexpected message {msg:?}"),
+            }
+        }
+        let done = replies.len() as u64 == COMMANDS;
+        if done && host.link.is_idle() && device.link.is_idle() {
+            break;
+        }
+        now += 1;
+        assert!(now < GIVE_UP_MS, "stuck with {} replies", replies.len());
+    }
+
+    // Exactly once and in order
+    let expected: Vec<u64> = (0..COMMANDS).collect();
+    assert_eq!(executed, expected);
+    assert_eq!(replies, expected);
+    Run {
+        elapsed_ms: now - start,
+        host: host.link.stats(),
+        device: device.link.stats(),
+    }
+}
+
+/// Frames handled by [Endpoint::step]
+struct Step {
+    /// Serialized messages delivered in order
+    delivered: Vec<Vec<u8>>,
+    /// Whether an acknowledgement was received
+    synced: bool,
+}
+
+/// One end of the link and the messages waiting for room in its window
+struct Endpoint<P> {
+    link: Link<W, N>,
+    outbox: VecDeque<P>,
+}
+
+impl<P: Serialize> Endpoint<P> {
+    fn new(window: usize, nak: bool) -> Self {
+        Self {
+            link: Link::new(Config {
+                window,
+                timeout_ms: TIMEOUT_MS,
+                min_timeout_ms: MIN_TIMEOUT_MS,
+                max_timeout_ms: MAX_TIMEOUT_MS,
+                nak,
+            }),
+            outbox: VecDeque::new(),
+        }
+    }
+
+    /// Handles the frames that have arrived by `now_ms` and writes the frames that are due
+    fn step(&mut self, now_ms: u64, rx: &mut Line, tx: &mut Line, rng: &mut Rng) -> Step {
+        let mut step = Step {
+            delivered: Vec::new(),
+            synced: false,
+        };
+        while let Some(mut frame) = rx.read(now_ms) {
+            let (header, payload) = match link::unpack(&mut frame) {
+                Ok(Packet::Link(header, payload)) => (header, payload),
+                // Only a link frame with a damaged tag reads as a plain frame
+                Ok(Packet::Plain(_)) | Err(_) => {
+                    self.link.reject();
+                    continue;
+                }
+            };
+            step.synced |= header.kind == Kind::Ack;
+            if let Some(payload) = self.link.receive(header, payload, now_ms) {
+                step.delivered.push(payload.to_vec());
+            }
+        }
+
+        let mut buf = [0u8; link::max_frame_len(N)];
+        while self.link.can_send() {
+            let Some(msg) = self.outbox.pop_front() else {
+                break;
+            };
+            let seq = self.link.send(&msg, now_ms).unwrap();
+            tx.write(self.link.frame(seq, &mut buf).unwrap(), now_ms, rng);
+        }
+        if self.link.poll(now_ms) {
+            for seq in self.link.unacked() {
+                tx.write(self.link.frame(seq, &mut buf).unwrap(), now_ms, rng);
+            }
+        }
+        if self.link.needs_ack() {
+            tx.write(self.link.ack_frame(&mut buf).unwrap(), now_ms, rng);
+        }
+        step
+    }
+}
+
+/// One direction of a serial line that damages frames at random. Half of the damaged frames
+/// are lost and the rest arrive with bits flipped at random.
+struct Line {
+    /// Frames in transit and the time at which they arrive
+    frames: VecDeque<(u64, Vec<u8>)>,
+    /// Time at which the line has finished sending the frames written so far
+    free_at_ms: u64,
+    damage_percent: u64,
+}
+
+impl Line {
+    fn new(damage_percent: u64) -> Self {
+        Self {
+            frames: VecDeque::new(),
+            free_at_ms: 0,
+            damage_percent,
+        }
+    }
+
+    fn write(&mut self, frame: &[u8], now_ms: u64, rng: &mut Rng) {
+        let send_ms = (frame.len() as u64 * 1000).div_ceil(BYTES_PER_S);
+        self.free_at_ms = self.free_at_ms.max(now_ms) + send_ms;
+        let arrival_ms = self.free_at_ms + LATENCY_MS;
+        if rng.next() % 100 >= self.damage_percent {
+            self.frames.push_back((arrival_ms, frame.to_vec()));
+        } else if rng.next().is_multiple_of(2) {
+            // Noise flips a few bits anywhere in the frame, including the header and the CRC
+            let mut frame = frame.to_vec();
+            for _ in 0..=rng.next() % 3 {
+                let bit = rng.next() as usize % (frame.len() * 8);
+                frame[bit / 8] ^= 1 << (bit % 8);
+            }
+            self.frames.push_back((arrival_ms, frame));
+        }
+        // Otherwise the frame is lost, though it took its time on the line
+    }
+
+    fn read(&mut self, now_ms: u64) -> Option<Vec<u8>> {
+        match self.frames.front() {
+            Some((at_ms, _)) if *at_ms <= now_ms => self.frames.pop_front().map(|(_, f)| f),
+            _ => None,
+        }
+    }
+}
+
+/// Xorshift generator, so that every run loses the same frames
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
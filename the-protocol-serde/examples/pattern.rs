@@ -0,0 +1,193 @@
+//! Steps through [the_protocol_serde::BlinkPattern]s the way the device plays them
+//!
+//! Checks the steps of each shape, that a pattern loops for its repetitions and forever without
+//! drifting, and that malformed patterns, including those that only arise on the wire, have no
+//! steps rather than stalling the led.
+//!
+//! ```sh
+//! cargo run --example pattern
+//! ```
+use the_protocol_serde::{BlinkPattern, BlinkShape, BlinkStep, MAX_MORSE_LEN, MorseText};
+
+fn main() {
+    shapes();
+    looping();
+    malformed();
+    println!("ok");
+}
+
+/// One cycle of each shape
+fn shapes() {
+    let duty = BlinkShape::Duty {
+        on_ms: 100,
+        off_ms: 300,
+    };
+    assert_eq!(steps(once(duty)), [(true, 100), (false, 300)]);
+
+    let burst = BlinkShape::Burst {
+        flashes: 3,
+        on_ms: 50,
+        off_ms: 100,
+        pause_ms: 1000,
+    };
+    assert_eq!(
+        steps(once(burst)),
+        [
+            (true, 50),
+            (false, 100),
+            (true, 50),
+            (false, 100),
+            (true, 50),
+            (false, 1000)
+        ]
+    );
+    // A single flash needs no time between flashes
+    let flash = BlinkShape::Burst {
+        flashes: 1,
+        on_ms: 50,
+        off_ms: 0,
+        pause_ms: 1000,
+    };
+    assert_eq!(steps(once(flash)), [(true, 50), (false, 1000)]);
+
+    // Gaps of one unit between symbols, three between characters and seven at the end
+    let sos = morse(10, "SOS");
+    let (dot, dash) = ((true, 10), (true, 30));
+    let (symbol_gap, char_gap, word_gap) = ((false, 10), (false, 30), (false, 70));
+    assert_eq!(
+        steps(once(sos)),
+        [
+            dot, symbol_gap, dot, symbol_gap, dot, char_gap, //
+            dash, symbol_gap, dash, symbol_gap, dash, char_gap, //
+            dot, symbol_gap, dot, symbol_gap, dot, word_gap,
+        ]
+    );
+    // Spaces lengthen the gap, lower case is signalled as upper case
+    assert_eq!(
+        steps(once(morse(10, "e  t"))),
+        [dot, word_gap, dash, word_gap]
+    );
+}
+
+/// Repetitions, and patterns that play until replaced
+fn looping() {
+    let duty = BlinkShape::Duty {
+        on_ms: 100,
+        off_ms: 300,
+    };
+    let pattern = BlinkPattern {
+        shape: duty,
+        repetitions: Some(3),
+    };
+    let played = steps(pattern);
+    assert_eq!(played.len(), 6);
+    assert_eq!(played.iter().map(|(_, ms)| ms).sum::<u32>(), 3 * 400);
+
+    // The last cycle of a pattern of many repetitions ends it
+    let pattern = BlinkPattern {
+        shape: duty,
+        repetitions: Some(u16::MAX),
+    };
+    assert_eq!(pattern.steps().count(), 2 * u16::MAX as usize);
+
+    // A pattern that plays forever keeps repeating the same cycle
+    let sos = morse(10, "SOS");
+    let cycle = steps(once(sos));
+    let forever: Vec<_> = BlinkPattern::forever(sos)
+        .steps()
+        .take(cycle.len() * 1000)
+        .map(|s| (s.on, s.ms))
+        .collect();
+    assert_eq!(forever.len(), cycle.len() * 1000);
+    assert!(forever.chunks(cycle.len()).all(|c| c == cycle));
+
+    // Durations saturate rather than wrap
+    let long = morse(u32::MAX / 2, "T");
+    assert_eq!(
+        once(long).steps().next(),
+        Some(BlinkStep {
+            on: true,
+            ms: u32::MAX
+        })
+    );
+}
+
+/// Patterns that cannot be played
+fn malformed() {
+    for pattern in [
+        once(BlinkShape::Duty {
+            on_ms: 0,
+            off_ms: 100,
+        }),
+        once(BlinkShape::Duty {
+            on_ms: 100,
+            off_ms: 0,
+        }),
+        once(BlinkShape::Burst {
+            flashes: 0,
+            on_ms: 50,
+            off_ms: 100,
+            pause_ms: 1000,
+        }),
+        once(BlinkShape::Burst {
+            flashes: 2,
+            on_ms: 50,
+            off_ms: 0,
+            pause_ms: 1000,
+        }),
+        once(BlinkShape::Burst {
+            flashes: 2,
+            on_ms: 50,
+            off_ms: 100,
+            pause_ms: 0,
+        }),
+        once(morse(0, "SOS")),
+        BlinkPattern {
+            shape: morse(10, "SOS"),
+            repetitions: Some(0),
+        },
+        decoded_with_len(0),
+        decoded_with_len(MAX_MORSE_LEN as u8 + 1),
+        decoded_with_len(u8::MAX),
+    ] {
+        assert!(!pattern.is_valid(), "{pattern:?}");
+        assert_eq!(pattern.steps().next(), None, "{pattern:?}");
+    }
+
+    // Text without anything to signal, or with characters that have no code
+    for text in ["", "   ", "SOS!", "ÉTÉ", "ABCDEFGHIJKLMNOPQ"] {
+        assert_eq!(MorseText::new(text), None, "{text:?}");
+    }
+    assert!(MorseText::new("ABCDEFGHIJKLMNOP").is_some());
+}
+
+/// Plays the shape once
+fn once(shape: BlinkShape) -> BlinkPattern {
+    BlinkPattern {
+        shape,
+        repetitions: Some(1),
+    }
+}
+
+fn morse(unit_ms: u32, text: &str) -> BlinkShape {
+    BlinkShape::Morse {
+        unit_ms,
+        text: MorseText::new(text).unwrap(),
+    }
+}
+
+/// All steps of a pattern that ends, as whether the led is on and the duration
+fn steps(pattern: BlinkPattern) -> Vec<(bool, u32)> {
+    assert!(pattern.is_valid(), "{pattern:?}");
+    pattern.steps().map(|s| (s.on, s.ms)).collect()
+}
+
+/// A Morse pattern as the host may send it, with a length that [MorseText::new] never produces
+fn decoded_with_len(len: u8) -> BlinkPattern {
+    let mut buf = [0u8; 64];
+    let n = ssmarshal::serialize(&mut buf, &once(morse(10, "SOS"))).unwrap();
+    // The length precedes the characters
+    let at = buf[..n].windows(4).position(|w| w == b"\x03SOS").unwrap();
+    buf[at] = len;
+    ssmarshal::deserialize(&buf[..n]).unwrap().0
+}
@@ -0,0 +1,92 @@
+//! Feeds bytes into a [the_protocol_serde::FrameReceiver] and checks the frames that come out
+//!
+//! Checks frames sent back to back, a frame that does not fit the buffer, which must be reported
+//! once and not leak its tail into the next frame, and partial frames left behind by a host that
+//! stopped mid-frame. Time is given in plain numbers of milliseconds.
+//!
+//! ```sh
+//! cargo run --example receiver
+//! ```
+use the_protocol_serde::{FrameReceiver, Received};
+
+const IDLE_TIMEOUT_MS: u64 = 50;
+
+/// What came out of the receiver for a run of bytes
+#[derive(Debug, PartialEq)]
+enum Out {
+    Frame(Vec<u8>),
+    Overflow,
+    Stale,
+}
+
+fn main() {
+    let mut rx = FrameReceiver::<8>::new(IDLE_TIMEOUT_MS);
+
+    // Frames back to back, including one that fills the buffer exactly
+    let out = feed(&mut rx, &[1, 2, 0, 3, 0, 1, 2, 3, 4, 5, 6, 7, 0], 0);
+    assert_eq!(
+        out,
+        [
+            Out::Frame(vec![1, 2, 0]),
+            Out::Frame(vec![3, 0]),
+            Out::Frame(vec![1, 2, 3, 4, 5, 6, 7, 0]),
+        ]
+    );
+    assert!(rx.is_empty());
+
+    // A frame that does not fit is reported once, and its tail is not taken for a frame
+    let oversized = [9; 20];
+    let mut bytes = oversized.to_vec();
+    bytes.extend([0, 4, 5, 0]);
+    let out = feed(&mut rx, &bytes, 10);
+    assert_eq!(out, [Out::Overflow, Out::Frame(vec![4, 5, 0])]);
+
+    // A frame that overflows on its termination byte has no tail to drop
+    let out = feed(&mut rx, &[9, 9, 9, 9, 9, 9, 9, 9, 0, 6, 0], 20);
+    assert_eq!(out, [Out::Overflow, Out::Frame(vec![6, 0])]);
+
+    // Back-to-back oversized frames are reported once each
+    let mut bytes = vec![];
+    for _ in 0..2 {
+        bytes.extend([9; 12]);
+        bytes.push(0);
+    }
+    let out = feed(&mut rx, &bytes, 30);
+    assert_eq!(out, [Out::Overflow, Out::Overflow]);
+    assert!(rx.is_empty());
+
+    // A partial frame left behind is dropped after the idle timeout rather than prepended
+    assert_eq!(feed(&mut rx, &[1, 2, 3], 100), []);
+    assert_eq!(rx.len(), 3);
+    let out = feed(&mut rx, &[4, 0], 100 + IDLE_TIMEOUT_MS);
+    assert_eq!(out, [Out::Stale, Out::Frame(vec![4, 0])]);
+
+    // Bytes within the timeout still belong to the same frame
+    assert_eq!(feed(&mut rx, &[1, 2], 200), []);
+    let out = feed(&mut rx, &[3, 0], 200 + IDLE_TIMEOUT_MS - 1);
+    assert_eq!(out, [Out::Frame(vec![1, 2, 3, 0])]);
+
+    // The tail of an oversized frame that never arrives does not swallow the next frame
+    assert_eq!(feed(&mut rx, &[9; 10], 300), [Out::Overflow]);
+    assert!(!rx.is_empty());
+    let out = feed(&mut rx, &[7, 0], 300 + IDLE_TIMEOUT_MS);
+    assert_eq!(out, [Out::Frame(vec![7, 0])]);
+
+    println!("ok");
+}
+
+/// Pushes the bytes, all arriving at `now_ms`, the way the UART interrupt of the device does
+fn feed<const N: usize>(rx: &mut FrameReceiver<N>, bytes: &[u8], now_ms: u64) -> Vec<Out> {
+    let mut out = vec![];
+    for &byte in bytes {
+        if rx.expire(now_ms) {
+            out.push(Out::Stale);
+        }
+        match rx.push(byte, now_ms) {
+            Received::Pending => {}
+            Received::Frame(frame) => out.push(Out::Frame(frame.to_vec())),
+            Received::Overflow => out.push(Out::Overflow),
+        }
+    }
+    out
+}
@@ -0,0 +1,118 @@
+//! Computes the occurrences of [the_protocol_serde::Recurrence] rules
+//!
+//! Checks the first and next occurrences at and around the edges of the intervals, that missed
+//! occurrences are skipped without drift, that a repeat ends after its count and that a daily rule
+//! wraps to the next day. Also checks that rules that cannot fire are rejected, including those
+//! with a start that does not exist on the calendar.
+//!
+//! ```sh
+//! cargo run --example recurrence
+//! ```
+use serde::Serialize;
+use the_protocol_serde::{
+    Recurrence, SDateTime,
+    chrono::{TimeZone, Utc},
+};
+
+/// Same layout as [SDateTime], whose fields are not public
+#[derive(Serialize)]
+struct Fields(i32, u32, u32, u32, u32, u32, u32);
+
+fn main() {
+    let start = at(12, 0, 0);
+    let periodic = Recurrence::Periodic {
+        start: start.clone(),
+        period_s: 60,
+    };
+    assert!(periodic.is_valid());
+    // Before, at and just after the start
+    assert_eq!(periodic.first(&at(11, 0, 0)), Some(start.clone()));
+    assert_eq!(periodic.first(&start), Some(start.clone()));
+    assert_eq!(periodic.first(&at(12, 0, 1)), Some(at(12, 1, 0)));
+    // At and around the edge of an interval
+    assert_eq!(periodic.first(&at(12, 4, 59)), Some(at(12, 5, 0)));
+    assert_eq!(periodic.first(&at(12, 5, 0)), Some(at(12, 5, 0)));
+    assert_eq!(periodic.after(&at(12, 5, 0)), Some(at(12, 6, 0)));
+    // Fired late, the next occurrence stays on the grid of the start
+    assert_eq!(periodic.after(&at(12, 5, 42)), Some(at(12, 6, 0)));
+    // Missed occurrences are skipped rather than fired in a burst
+    assert_eq!(periodic.after(&at(13, 30, 30)), Some(at(13, 31, 0)));
+
+    let repeat = Recurrence::Repeat {
+        start: start.clone(),
+        interval_s: 10,
+        count: 3,
+    };
+    assert_eq!(repeat.first(&at(11, 59, 59)), Some(start.clone()));
+    assert_eq!(repeat.after(&start), Some(at(12, 0, 10)));
+    assert_eq!(repeat.after(&at(12, 0, 10)), Some(at(12, 0, 20)));
+    // The third occurrence is the last one
+    assert_eq!(repeat.after(&at(12, 0, 20)), None);
+    assert_eq!(repeat.first(&at(12, 0, 21)), None);
+
+    let daily = Recurrence::Daily {
+        hour: 6,
+        minute: 30,
+    };
+    assert_eq!(daily.first(&at(6, 29, 59)), Some(at(6, 30, 0)));
+    assert_eq!(daily.first(&at(6, 30, 0)), Some(at(6, 30, 0)));
+    // Wraps to the next day, and across the end of the month
+    let next_day = Utc.with_ymd_and_hms(2025, 4, 1, 6, 30, 0).unwrap();
+    assert_eq!(daily.after(&at(6, 30, 0)), Some(next_day.into()));
+    assert_eq!(daily.first(&at(23, 59, 59)), Some(next_day.into()));
+
+    // Rules that cannot fire
+    for rule in [
+        Recurrence::Periodic {
+            start: start.clone(),
+            period_s: 0,
+        },
+        Recurrence::Repeat {
+            start: start.clone(),
+            interval_s: 10,
+            count: 0,
+        },
+        Recurrence::Daily {
+            hour: 24,
+            minute: 0,
+        },
+        Recurrence::Daily {
+            hour: 0,
+            minute: 60,
+        },
+        Recurrence::Periodic {
+            start: date_time(2025, 2, 30, 12, 0, 0),
+            period_s: 60,
+        },
+        Recurrence::Repeat {
+            start: date_time(2025, 3, 31, 25, 0, 0),
+            interval_s: 10,
+            count: 3,
+        },
+    ] {
+        assert!(!rule.is_valid(), "{rule:?}");
+        assert_eq!(rule.first(&start), None, "{rule:?}");
+        assert_eq!(rule.after(&start), None, "{rule:?}");
+    }
+
+    // A current time that does not exist on the calendar has no occurrences either
+    let invalid_now = date_time(2025, 13, 1, 0, 0, 0);
+    assert_eq!(periodic.first(&invalid_now), None);
+    assert_eq!(daily.after(&invalid_now), None);
+
+    println!("ok");
+}
+
+/// The 31st of March 2025 at the time of day
+fn at(hour: u32, minute: u32, second: u32) -> SDateTime {
+    Utc.with_ymd_and_hms(2025, 3, 31, hour, minute, second)
+        .unwrap()
+        .into()
+}
+
+/// Builds a date-time as the host may send it, valid or not
+fn date_time(y: i32, mo: u32, d: u32, h: u32, mi: u32, s: u32) -> SDateTime {
+    let mut buf = [0u8; 28];
+    let len = ssmarshal::serialize(&mut buf, &Fields(y, mo, d, h, mi, s, 0)).unwrap();
+    ssmarshal::deserialize(&buf[..len]).unwrap().0
+}
@@ -0,0 +1,326 @@
+//! Encodes the widest value of every variant of the messages and checks that it fits the buffers
+//! sized by [WireSize], and by [MaxSize] with the `postcard` feature
+//!
+//! ```sh
+//! cargo run --example wire_size
+//! cargo run --example wire_size --features postcard
+//! ```
+use the_protocol_serde::{
+    Batch, BatchMode, BatchResponse, BlinkPattern, BlinkShape, Codec, Color, Command, CommandV2,
+    DeviceMessage, Diagnostics, Event, FirmwareInfo, Funct, LedState, Limits, LinkStats,
+    MAX_BATCH_LEN, MAX_MORSE_LEN, MaxSize, MorseText, Payload, PayloadV2, Press, Recurrence,
+    RejectReason, Reply, Request, ResetReason, Response, ResponseV2, RgbEffect, SDateTime,
+    SchedulePage, ScheduledJob, WireSize,
+    chrono::{TimeZone, Utc},
+    format::{Format, Ssmarshal},
+};
+
+fn main() {
+    let commands = commands();
+    let replies = replies();
+    let events = events();
+
+    // The widest variant of each type takes exactly the wire size
+    let widest = check_all(commands.iter().cloned().map(Request::V2));
+    assert_eq!(widest, Request::WIRE_SIZE);
+    let widest = check_all(replies.iter().cloned().map(Reply::V2));
+    assert_eq!(widest, Reply::WIRE_SIZE);
+    let widest = check_all(
+        replies
+            .iter()
+            .cloned()
+            .map(|resp| DeviceMessage::Reply(Reply::V2(resp)))
+            .chain(events.iter().cloned().map(DeviceMessage::Event)),
+    );
+    assert_eq!(widest, DeviceMessage::WIRE_SIZE);
+    check_all([Request::Legacy(widest_command())]);
+    check_all([Reply::Legacy(widest_response())]);
+    check_all([scheduled_job()]);
+
+    println!(
+        "Request: {} bytes, Reply: {} bytes, DeviceMessage: {} bytes",
+        Request::WIRE_SIZE,
+        Reply::WIRE_SIZE,
+        DeviceMessage::WIRE_SIZE
+    );
+    println!("ok");
+}
+
+/// Encodes every message with the available backends into buffers of exactly the worst-case size and
+/// through [Codec]. Returns the length of the longest ssmarshal encoding.
+fn check_all<P>(messages: impl IntoIterator<Item = P>) -> usize
+where
+    P: Codec<P> + WireSize + MaxSize + serde::Serialize + PartialEq + std::fmt::Debug,
+    P: for<'de> serde::Deserialize<'de>,
+{
+    let mut widest = 0;
+    for msg in messages {
+        let mut buf = vec![0u8; P::WIRE_SIZE];
+        let n = Ssmarshal::to_slice(&msg, &mut buf).unwrap_or_else(|e| panic!("{e:?}: {msg:?}"));
+        widest = widest.max(n);
+        #[cfg(feature = "postcard")]
+        {
+            use the_protocol_serde::format::Postcard;
+            let mut buf = vec![0u8; P::POSTCARD_MAX_SIZE];
+            Postcard::to_slice(&msg, &mut buf).unwrap_or_else(|e| panic!("{e:?}: {msg:?}"));
+        }
+
+        let mut buf = [0u8; 2048];
+        assert!(<P as Codec<P>>::MAX_SERIALIZED_LEN <= buf.len());
+        let frame = <P as Codec<P>>::serialize(&msg, &mut buf).unwrap();
+        assert!(frame.len() <= <P as Codec<P>>::MAX_SERIALIZED_LEN);
+        assert_eq!(<P as Codec<P>>::deserialize_in_place(frame).unwrap(), msg);
+    }
+    widest
+}
+
+fn date_time() -> SDateTime {
+    Utc.with_ymd_and_hms(2025, 12, 31, 23, 59, 59)
+        .unwrap()
+        .into()
+}
+
+fn widest_funct() -> Funct {
+    Funct::EnableBlink {
+        period_ms: u64::MAX,
+    }
+}
+
+fn widest_command() -> Command {
+    Command::Schedule(widest_funct(), date_time())
+}
+
+fn widest_response() -> Response {
+    Response::OkRecovered(Some(Payload::Counter(u64::MAX)), widest_command())
+}
+
+fn widest_recurrence() -> Recurrence {
+    Recurrence::Repeat {
+        start: date_time(),
+        interval_s: u32::MAX,
+        count: u32::MAX,
+    }
+}
+
+fn scheduled_job() -> ScheduledJob {
+    ScheduledJob {
+        id: u32::MAX,
+        funct: widest_funct(),
+        at: date_time(),
+        recurrence: Some(widest_recurrence()),
+    }
+}
+
+fn color() -> Color {
+    Color::Rgb {
+        r: 255,
+        g: 255,
+        b: 255,
+    }
+}
+
+/// Widest value of every variant of [CommandV2]
+fn commands() -> Vec<CommandV2> {
+    let mut batch = Batch::new(BatchMode::BestEffort);
+    while batch.push(widest_command()).is_ok() {}
+    assert_eq!(batch.len(), MAX_BATCH_LEN);
+    let morse = MorseText::new(&"S".repeat(MAX_MORSE_LEN)).unwrap();
+    let commands = vec![
+        CommandV2::Reset,
+        CommandV2::Counter,
+        CommandV2::SetDateTime(Some(date_time())),
+        CommandV2::Immediate(widest_funct()),
+        CommandV2::Schedule(widest_funct(), date_time()),
+        CommandV2::ScheduleRecurring(widest_funct(), widest_recurrence()),
+        CommandV2::CancelJob(u32::MAX),
+        CommandV2::ClearSchedule,
+        CommandV2::ListSchedule { page: u8::MAX },
+        CommandV2::LedState,
+        CommandV2::DateTime,
+        CommandV2::SetCounter(u64::MAX),
+        CommandV2::FirmwareInfo,
+        CommandV2::Diagnostics,
+        CommandV2::Batch(batch),
+        CommandV2::SubscribeEvents(true),
+        CommandV2::SetBlinkPattern(BlinkPattern {
+            shape: BlinkShape::Morse {
+                unit_ms: u32::MAX,
+                text: morse,
+            },
+            repetitions: Some(u16::MAX),
+        }),
+        CommandV2::SetRgbEffect(RgbEffect::Breathe {
+            color: color(),
+            period_ms: u32::MAX,
+        }),
+        CommandV2::SetRgbBrightness(u8::MAX),
+        CommandV2::Limits,
+        CommandV2::LinkStats,
+        CommandV2::SetBaudRate(u32::MAX),
+    ];
+    for cmd in &commands {
+        covers_command(cmd);
+    }
+    commands
+}
+
+/// Fails to compile when a variant is added to [CommandV2] without adding it to [commands]
+fn covers_command(cmd: &CommandV2) {
+    match cmd {
+        CommandV2::Reset
+        | CommandV2::Counter
+        | CommandV2::SetDateTime(_)
+        | CommandV2::Immediate(_)
+        | CommandV2::Schedule(..)
+        | CommandV2::ScheduleRecurring(..)
+        | CommandV2::CancelJob(_)
+        | CommandV2::ClearSchedule
+        | CommandV2::ListSchedule { .. }
+        | CommandV2::LedState
+        | CommandV2::DateTime
+        | CommandV2::SetCounter(_)
+        | CommandV2::FirmwareInfo
+        | CommandV2::Diagnostics
+        | CommandV2::Batch(_)
+        | CommandV2::SubscribeEvents(_)
+        | CommandV2::SetBlinkPattern(_)
+        | CommandV2::SetRgbEffect(_)
+        | CommandV2::SetRgbBrightness(_)
+        | CommandV2::Limits
+        | CommandV2::LinkStats
+        | CommandV2::SetBaudRate(_) => {}
+    }
+}
+
+/// Widest value of every variant of [ResponseV2] and [PayloadV2]
+fn replies() -> Vec<ResponseV2> {
+    let mut batch = BatchResponse::new();
+    for _ in 0..MAX_BATCH_LEN {
+        batch.push(widest_response().into());
+    }
+    let page = SchedulePage {
+        page: u8::MAX,
+        page_count: u8::MAX,
+        jobs: std::array::from_fn(|_| Some(scheduled_job())),
+    };
+    // The highest page index ends a listing rather than overflowing
+    assert!(page.is_last());
+    let payloads = vec![
+        PayloadV2::Counter(u64::MAX),
+        PayloadV2::LedState(LedState {
+            blink_period_ms: u64::MAX,
+            is_on: true,
+        }),
+        PayloadV2::DateTime(Some(date_time())),
+        PayloadV2::FirmwareInfo(FirmwareInfo {
+            major: u16::MAX,
+            minor: u16::MAX,
+            patch: u16::MAX,
+            protocol: u16::MAX,
+        }),
+        PayloadV2::JobId(u32::MAX),
+        PayloadV2::SchedulePage(page),
+        PayloadV2::Diagnostics(Diagnostics {
+            uptime_ms: u64::MAX,
+            free_schedule_slots: u16::MAX,
+            frames_received: u32::MAX,
+            corrupted_frames: u32::MAX,
+            stale_frames: u32::MAX,
+            uart_overruns: u32::MAX,
+            busy_rejections: u32::MAX,
+            dropped_replies: u32::MAX,
+            write_errors: u32::MAX,
+            auth_failures: u32::MAX,
+            reset_reason: ResetReason::Other(u8::MAX),
+        }),
+        PayloadV2::Batch(batch),
+        PayloadV2::Limits(Limits {
+            min_blink_period_ms: u64::MAX,
+            max_blink_period_ms: u64::MAX,
+            min_blink_step_ms: u32::MAX,
+            max_blink_step_ms: u32::MAX,
+            min_rgb_period_ms: u32::MAX,
+            max_rgb_period_ms: u32::MAX,
+            max_rgb_brightness: u8::MAX,
+            min_baud_rate: u32::MAX,
+            max_baud_rate: u32::MAX,
+        }),
+        PayloadV2::LinkStats(LinkStats {
+            frames_sent: u32::MAX,
+            retransmissions: u32::MAX,
+            frames_received: u32::MAX,
+            frame_errors: u32::MAX,
+            frame_error_rate: u16::MAX,
+            retransmission_rate: u16::MAX,
+            srtt_ms: u32::MAX,
+            rttvar_ms: u32::MAX,
+            rto_ms: u32::MAX,
+        }),
+    ];
+    let widest_command = commands()
+        .into_iter()
+        .max_by_key(|cmd| {
+            let mut buf = [0u8; CommandV2::WIRE_SIZE];
+            Ssmarshal::to_slice(cmd, &mut buf).unwrap()
+        })
+        .unwrap();
+
+    let mut replies = vec![
+        ResponseV2::Rejected(RejectReason::InternalError),
+        ResponseV2::Busy,
+        ResponseV2::Unauthenticated,
+    ];
+    for payload in payloads {
+        covers_payload(&payload);
+        replies.push(ResponseV2::Ok(Some(payload.clone())));
+        replies.push(ResponseV2::OkRecovered(
+            Some(payload),
+            widest_command.clone(),
+        ));
+    }
+    replies
+}
+
+/// Fails to compile when a variant is added to [PayloadV2] without adding it to [replies]
+fn covers_payload(payload: &PayloadV2) {
+    match payload {
+        PayloadV2::Counter(_)
+        | PayloadV2::LedState(_)
+        | PayloadV2::DateTime(_)
+        | PayloadV2::FirmwareInfo(_)
+        | PayloadV2::JobId(_)
+        | PayloadV2::SchedulePage(_)
+        | PayloadV2::Diagnostics(_)
+        | PayloadV2::Batch(_)
+        | PayloadV2::Limits(_)
+        | PayloadV2::LinkStats(_) => {}
+    }
+}
+
+/// Widest value of every variant of [Event]
+fn events() -> Vec<Event> {
+    let events = vec![
+        Event::JobFired {
+            id: u32::MAX,
+            at: date_time(),
+        },
+        Event::ClockUnset,
+        Event::Rebooted {
+            reason: ResetReason::Other(u8::MAX),
+        },
+        Event::ButtonPressed {
+            press: Press::Long,
+            funct: widest_funct(),
+        },
+    ];
+    for event in &events {
+        match event {
+            // Fails to compile when a variant is added to [Event] without adding it here
+            Event::JobFired { .. }
+            | Event::ClockUnset
+            | Event::Rebooted { .. }
+            | Event::ButtonPressed { .. } => {}
+        }
+    }
+    events
+}
@@ -0,0 +1,44 @@
+//! Admission of requests into the bounded command queue of the device
+//!
+//! The device decodes requests as they arrive and queues them for processing. A request that
+//! arrives while the queue is full is answered with [ResponseV2::Busy] right away rather than
+//! dropped, so that the host may send it again later instead of waiting for a timeout.
+use crate::{CommandV2, Reply, Request, ResponseV2, Version};
+
+/// Capacity of the command queue of the device. Set with the `COMMAND_QUEUE_LEN` environment
+/// variable at build time, e.g., by a build script, 4 by default.
+pub const COMMAND_QUEUE_LEN: usize = parse_capacity(option_env!("COMMAND_QUEUE_LEN"), 4);
+
+/// Parses a decimal capacity at compile time, or returns `default` if none is given
+pub const fn parse_capacity(s: Option<&str>, default: usize) -> usize {
+    let Some(s) = s else {
+        return default;
+    };
+    let bytes = s.as_bytes();
+    assert!(!bytes.is_empty(), "the capacity must be a decimal number");
+    let mut value = 0;
+    let mut idx = 0;
+    while idx < bytes.len() {
+        assert!(
+            bytes[idx].is_ascii_digit(),
+            "the capacity must be a decimal number"
+        );
+        value = value * 10 + (bytes[idx] - b'0') as usize;
+        idx += 1;
+    }
+    assert!(value > 0, "the capacity must be at least one");
+    value
+}
+
+/// Offers the request to the command queue with `try_send`, which fails when the queue is full.
+/// Returns the [ResponseV2::Busy] reply to send in place of processing the request if the queue is
+/// full, encoded for the revision of the request, or `None` if the request was queued.
+pub fn admit<E>(
+    req: Request,
+    try_send: impl FnOnce(CommandV2, Version) -> Result<(), E>,
+) -> Option<Reply> {
+    let ver = req.version();
+    try_send(req.into(), ver)
+        .err()
+        .map(|_| Reply::new(ver, ResponseV2::Busy))
+}
@@ -0,0 +1,202 @@
+//! Authenticated frames
+//!
+//! Without authentication, anyone attached to the line can send commands to the device. In the
+//! authenticated mode, every frame is wrapped into an authenticated frame that carries a message
+//! authentication code (MAC) computed with a pre-shared [Key]:
+//!
+//! | [AUTH_TAG] | nonce | packet ... | MAC |
+//!
+//! The packet is the decoded content of a plain frame of [crate::Codec] or a link frame of
+//! [crate::link], which is authenticated as it is, and the authenticated frame is a COBS packet
+//! itself. The nonce is a little-endian `u64`, and the MAC is SipHash-2-4 keyed with the key over
+//! the direction of the frame, the tag, the nonce and the packet. The direction keeps a frame of
+//! the device from passing for a frame of the host.
+//!
+//! Every sender numbers its frames with increasing nonces, and the receiver accepts only nonces
+//! greater than that of the latest frame it accepted, which blocks replays of recorded frames. A
+//! receiver also sends nonces greater than those it has received, so that the device, whose
+//! nonces start over after a reset, continues above the nonces of the host. The host derives its
+//! nonces from the time of day, which keeps them increasing across runs of the host. The device
+//! saves a mark above its [Authenticator::high_water] in flash and carries on above the mark after
+//! a reset with [Authenticator::resume], so that frames recorded before the reset stay replays.
+//!
+//! A frame that is not authenticated, fails verification or is a replay is rejected with
+//! [DeserializeError::Unauthenticated]. The device counts it in
+//! [crate::Diagnostics::auth_failures] and answers it with [crate::ResponseV2::Unauthenticated],
+//! sealed with a nonce above the mark, after which the host sends the command again above it.
+use core::hash::Hasher;
+
+use corncobs::max_encoded_len;
+use siphasher::sip::SipHasher24;
+
+use crate::{DeserializeError, SerializeError, cobs};
+
+/// First byte of an authenticated frame
+pub const AUTH_TAG: u8 = 0xF5;
+
+/// Length of the pre-shared key in bytes
+pub const KEY_LEN: usize = 16;
+
+/// Length of the nonce in bytes
+pub const NONCE_LEN: usize = 8;
+
+/// Length of the MAC in bytes
+pub const MAC_LEN: usize = 8;
+
+/// Bytes that authentication adds to a packet
+pub const OVERHEAD: usize = 1 + NONCE_LEN + MAC_LEN;
+
+/// Pre-shared key of the host and the device
+pub type Key = [u8; KEY_LEN];
+
+/// Length of the COBS packet of an authenticated frame carrying a frame of at most `frame_len`
+/// bytes, as encoded, including the framing zero
+pub const fn max_frame_len(frame_len: usize) -> usize {
+    max_encoded_len(OVERHEAD + frame_len)
+}
+
+/// Sender of the frames that an [Authenticator] seals
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Direction {
+    /// Frames from the host to the device
+    ToDevice = 0,
+    /// Frames from the device to the host
+    ToHost = 1,
+}
+
+impl Direction {
+    fn reverse(self) -> Self {
+        match self {
+            Direction::ToDevice => Direction::ToHost,
+            Direction::ToHost => Direction::ToDevice,
+        }
+    }
+}
+
+/// Seals the frames of one end into authenticated frames and opens those of the peer
+pub struct Authenticator {
+    key: Key,
+    /// Direction of the frames sealed
+    direction: Direction,
+    /// Nonce of the next frame sealed
+    next_nonce: u64,
+    /// Nonce of the latest frame accepted, `None` until a frame is accepted
+    last_nonce: Option<u64>,
+}
+
+impl Authenticator {
+    /// Creates an authenticator that seals the frames going in `direction` with `key`
+    pub const fn new(key: Key, direction: Direction) -> Self {
+        Self {
+            key,
+            direction,
+            next_nonce: 0,
+            last_nonce: None,
+        }
+    }
+
+    /// Creates an authenticator that carries on above `mark` after a reset, e.g., of a device that
+    /// saved the mark before the reset. Only frames with nonces greater than `mark` are accepted,
+    /// and the frames sealed get greater nonces too.
+    pub const fn resume(key: Key, direction: Direction, mark: u64) -> Self {
+        Self {
+            key,
+            direction,
+            next_nonce: mark.saturating_add(1),
+            last_nonce: Some(mark),
+        }
+    }
+
+    /// Highest nonce accepted or sealed so far, which a mark given to [Authenticator::resume]
+    /// must not be below
+    pub fn high_water(&self) -> u64 {
+        // Accepting a nonce raises the nonce of the next frame above it
+        self.next_nonce.saturating_sub(1)
+    }
+
+    /// Raises the nonce of the next frame to at least `nonce`, e.g., to the time of day
+    pub fn advance(&mut self, nonce: u64) {
+        self.next_nonce = self.next_nonce.max(nonce);
+    }
+
+    /// Wraps the COBS packet in `buf[..len]` into an authenticated frame in place. Returns the
+    /// sub-slice of `buf` that was allocated.
+    ///
+    /// # Errors
+    ///
+    /// * [SerializeError::BufferTooSmall] if `buf` is shorter than [max_frame_len] of the packet,
+    ///   or `buf[..len]` is not a COBS packet
+    pub fn seal<'a>(
+        &mut self,
+        buf: &'a mut [u8],
+        len: usize,
+    ) -> Result<&'a mut [u8], SerializeError> {
+        let n = corncobs::decode_in_place(&mut buf[..len])
+            .map_err(|_| SerializeError::BufferTooSmall)?;
+        let sealed_len = OVERHEAD + n;
+        if max_encoded_len(sealed_len) > buf.len() {
+            return Err(SerializeError::BufferTooSmall);
+        }
+        let start = cobs::start(sealed_len);
+        let nonce = self.next_nonce;
+        self.next_nonce = nonce.wrapping_add(1);
+
+        // The packet moves up, past the start of the encoding and the header
+        let body = start + 1 + NONCE_LEN;
+        buf.copy_within(0..n, body);
+        buf[start] = AUTH_TAG;
+        buf[start + 1..body].copy_from_slice(&nonce.to_le_bytes());
+        let mac = self.mac(self.direction, &buf[start..body + n]);
+        buf[body + n..body + n + MAC_LEN].copy_from_slice(&mac);
+        let n = cobs::encode_in_place(buf, start, sealed_len);
+        Ok(&mut buf[0..n])
+    }
+
+    /// Verifies the authenticated frame in the COBS packet `frame` and replaces it in place with
+    /// the COBS packet it carries. Returns the sub-slice of `frame` that holds the packet.
+    ///
+    /// # Errors
+    ///
+    /// * [DeserializeError::Cobs] if `frame` is not a COBS packet
+    /// * [DeserializeError::Unauthenticated] if the frame is not authenticated, the MAC does not
+    ///   match or the nonce is not greater than that of the latest frame accepted
+    pub fn open<'a>(&mut self, frame: &'a mut [u8]) -> Result<&'a mut [u8], DeserializeError> {
+        let n = corncobs::decode_in_place(frame).map_err(|_| DeserializeError::Cobs)?;
+        if n < OVERHEAD || frame[0] != AUTH_TAG {
+            return Err(DeserializeError::Unauthenticated);
+        }
+        let (signed, mac) = frame[..n].split_at(n - MAC_LEN);
+        let expected = self.mac(self.direction.reverse(), signed);
+        // Compare every byte, so that the time taken tells nothing about the MAC
+        let diff = expected
+            .iter()
+            .zip(mac)
+            .fold(0, |diff, (a, b)| diff | (a ^ b));
+        if diff != 0 {
+            return Err(DeserializeError::Unauthenticated);
+        }
+        let mut nonce = [0; NONCE_LEN];
+        nonce.copy_from_slice(&signed[1..1 + NONCE_LEN]);
+        let nonce = u64::from_le_bytes(nonce);
+        if self.last_nonce.is_some_and(|last| nonce <= last) {
+            return Err(DeserializeError::Unauthenticated);
+        }
+        self.last_nonce = Some(nonce);
+        self.advance(nonce.wrapping_add(1));
+
+        // Encode the packet again, which is shorter than the frame by at least the overhead
+        let len = n - OVERHEAD;
+        let start = cobs::start(len);
+        frame.copy_within(1 + NONCE_LEN..1 + NONCE_LEN + len, start);
+        let n = cobs::encode_in_place(frame, start, len);
+        Ok(&mut frame[0..n])
+    }
+
+    fn mac(&self, direction: Direction, signed: &[u8]) -> [u8; MAC_LEN] {
+        let mut hasher = SipHasher24::new_with_key(&self.key);
+        hasher.write(&[direction as u8]);
+        hasher.write(signed);
+        hasher.finish().to_le_bytes()
+    }
+}
@@ -0,0 +1,106 @@
+//! Several commands carried in one frame
+use serde::{Deserialize, Serialize};
+use the_protocol::{Command, Response};
+
+use crate::{ResponseV2, v2::lower_response};
+
+/// Maximum number of commands in a [Batch]
+pub const MAX_BATCH_LEN: usize = 8;
+
+/// How the device handles a command of a [Batch] being rejected
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum BatchMode {
+    /// Commands are executed until one is rejected, after which the effects of the batch are
+    /// rolled back
+    Atomic,
+    /// Every command is executed, regardless of the preceding ones being rejected
+    BestEffort,
+}
+
+/// Commands executed in order by [crate::CommandV2::Batch]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Batch {
+    /// How rejected commands are handled
+    pub mode: BatchMode,
+    /// Commands to execute, terminated by the first `None`
+    pub commands: [Option<Command>; MAX_BATCH_LEN],
+}
+
+impl Batch {
+    /// Creates an empty batch
+    pub const fn new(mode: BatchMode) -> Self {
+        Self {
+            mode,
+            commands: [const { None }; MAX_BATCH_LEN],
+        }
+    }
+
+    /// Appends a command to the batch. Returns the command back if the batch is full.
+    pub fn push(&mut self, cmd: Command) -> Result<(), Command> {
+        match self.commands.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => {
+                *slot = Some(cmd);
+                Ok(())
+            }
+            None => Err(cmd),
+        }
+    }
+
+    /// Iterates over the commands of the batch in order
+    pub fn iter(&self) -> impl Iterator<Item = &Command> {
+        self.commands.iter().map_while(Option::as_ref)
+    }
+
+    /// Number of commands in the batch
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    /// Whether the batch has no commands
+    pub fn is_empty(&self) -> bool {
+        self.commands[0].is_none()
+    }
+}
+
+/// Responses to the commands of a [Batch], returned as [crate::PayloadV2::Batch]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct BatchResponse {
+    /// Whether the effects of the batch are in place. Always `true` for
+    /// [BatchMode::BestEffort]. `false` if a command of a [BatchMode::Atomic] batch was rejected
+    /// and the batch was rolled back.
+    pub committed: bool,
+    /// Response to each command in the order of the batch. The responses stop at the rejected
+    /// command of an atomic batch that was rolled back.
+    pub responses: [Option<Response>; MAX_BATCH_LEN],
+}
+
+impl BatchResponse {
+    /// Creates a committed response with no responses to commands
+    pub const fn new() -> Self {
+        Self {
+            committed: true,
+            responses: [const { None }; MAX_BATCH_LEN],
+        }
+    }
+
+    /// Appends the response to the next command of the batch
+    ///
+    /// An extended response is converted into the legacy [Response] that the command would get
+    /// on its own. The response is dropped if there are already [MAX_BATCH_LEN] responses.
+    pub fn push(&mut self, resp: ResponseV2) {
+        if let Some(slot) = self.responses.iter_mut().find(|slot| slot.is_none()) {
+            *slot = Some(lower_response(resp));
+        }
+    }
+
+    /// Iterates over the responses in the order of the batch
+    pub fn iter(&self) -> impl Iterator<Item = &Response> {
+        self.responses.iter().map_while(Option::as_ref)
+    }
+}
+
+impl Default for BatchResponse {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,25 @@
+//! Renegotiation of the baud rate of the serial line
+//!
+//! Both ends start at [DEFAULT_BAUD_RATE]. The host proposes another rate with
+//! [crate::CommandV2::SetBaudRate], which the device acknowledges at the current rate before
+//! switching:
+//!
+//! 1. The device answers [crate::ResponseV2::Ok], or rejects a rate outside of its
+//!    [crate::Limits] with [crate::RejectReason::IllegalCommand].
+//! 2. Once the reply has been written, and acknowledged by the host during a session of
+//!    [crate::link], the device switches to the new rate.
+//! 3. The host switches once it receives the reply, and sends a command at the new rate.
+//! 4. The device keeps the new rate once it receives a valid frame at it. Otherwise it falls back
+//!    to the previous rate after [BAUD_FALLBACK_MS], and so does the host when no valid reply
+//!    arrives at the new rate by then.
+//!
+//! A rate that is too fast for the line thus costs one fallback period rather than the connection.
+//! The host should give up before the device does, as the device may confirm the rate with a
+//! frame of the host even though its replies do not get through.
+
+/// Baud rate of the serial line after a reset
+pub const DEFAULT_BAUD_RATE: u32 = 115_200;
+
+/// Time in milliseconds after switching to a new baud rate within which a valid frame must arrive
+/// at it, or the previous rate is restored
+pub const BAUD_FALLBACK_MS: u64 = 1_000;
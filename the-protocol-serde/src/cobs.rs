@@ -0,0 +1,52 @@
+//! COBS encoding within a single buffer
+//!
+//! The message is serialized into the end of the output buffer and encoded towards its start, so
+//! that a frame never needs a second buffer, see [crate::format::encode]. The output is identical
+//! to [corncobs::encode_buf].
+use corncobs::ZERO;
+
+/// Longest run of non-zero bytes in a COBS block
+pub const MAX_RUN: usize = 254;
+
+/// Offset in a buffer of `len` bytes from which a message can be encoded in place by
+/// [encode_in_place]
+///
+/// The encoding grows by one byte for every [MAX_RUN] bytes of the message and the leading code
+/// byte, so the encoded bytes never overtake the bytes yet to be encoded.
+pub const fn start(len: usize) -> usize {
+    if len == 0 { 1 } else { len.div_ceil(MAX_RUN) }
+}
+
+/// Encodes the `len` bytes at `buf[start..]` into a COBS packet at the start of `buf`, including
+/// the framing zero. Returns the length of the packet.
+///
+/// `start` must be at least [start] of the length of the message, and `buf` must hold
+/// [corncobs::max_encoded_len] of it.
+pub fn encode_in_place(buf: &mut [u8], start: usize, len: usize) -> usize {
+    debug_assert!(start >= self::start(len));
+    let end = start + len;
+    let mut read = start;
+    let mut write = 0;
+    loop {
+        // Run of non-zero bytes up to the next zero, the end or the length of a maximal block
+        let limit = end.min(read + MAX_RUN);
+        let run = buf[read..limit]
+            .iter()
+            .position(|&b| b == ZERO)
+            .unwrap_or(limit - read);
+        // Stays behind `read` as `start` covers the code bytes of the blocks
+        buf.copy_within(read..read + run, write + 1);
+        buf[write] = (run + 1) as u8;
+        write += run + 1;
+        read += run;
+        if read == end {
+            break;
+        }
+        // The zero is implied by the block, whereas a maximal block carries no zero
+        if run < MAX_RUN {
+            read += 1;
+        }
+    }
+    buf[write] = ZERO;
+    write + 1
+}
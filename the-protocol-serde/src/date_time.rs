@@ -0,0 +1,42 @@
+//! Checked conversion of the date-times sent by the host
+//!
+//! [SDateTime] accepts any field values on the wire, e.g., the 13th month, and its conversion into
+//! [DateTime] panics on those. Every date-time from the host must pass [to_utc] or
+//! [is_valid_date_time] before it is converted or stored.
+use serde::Deserialize;
+use the_protocol::{
+    SDateTime,
+    chrono::{DateTime, TimeZone, Utc},
+};
+
+/// Fields of [SDateTime], which are not public, in the same order
+#[derive(Deserialize)]
+struct Fields {
+    year: i32,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+    #[allow(dead_code)]
+    nanoseconds: u32,
+}
+
+/// Length of [SDateTime] encoded with `ssmarshal`, one `i32` and six `u32`s
+const FIELDS_LEN: usize = 7 * 4;
+
+/// Converts the date-time into UTC, or returns `None` if it does not exist on the calendar, e.g.,
+/// the 30th of February or the 24th hour
+pub fn to_utc(dt: &SDateTime) -> Option<DateTime<Utc>> {
+    // The fields are read back in a mirror struct, as `ssmarshal` ignores the names
+    let mut buf = [0u8; FIELDS_LEN];
+    let len = ssmarshal::serialize(&mut buf, dt).ok()?;
+    let (f, _) = ssmarshal::deserialize::<Fields>(&buf[..len]).ok()?;
+    Utc.with_ymd_and_hms(f.year, f.month, f.day, f.hour, f.minute, f.second)
+        .single()
+}
+
+/// Whether the date-time exists on the calendar, see [to_utc]
+pub fn is_valid_date_time(dt: &SDateTime) -> bool {
+    to_utc(dt).is_some()
+}
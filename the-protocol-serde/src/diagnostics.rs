@@ -0,0 +1,64 @@
+//! Device health information
+use serde::{Deserialize, Serialize};
+
+/// Device health information as returned by [crate::CommandV2::Diagnostics]
+///
+/// Counters are reset only when the device is reset by hardware, not by [crate::Command::Reset].
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Diagnostics {
+    /// Time since the device booted in milliseconds
+    pub uptime_ms: u64,
+    /// Number of jobs that can still be added to the schedule
+    pub free_schedule_slots: u16,
+    /// Number of frames received, including corrupted ones
+    pub frames_received: u32,
+    /// Number of frames rejected with [crate::RejectReason::CorruptedFrame]
+    pub corrupted_frames: u32,
+    /// Number of partial frames discarded because the host stopped sending mid-frame
+    pub stale_frames: u32,
+    /// Number of times the UART receive FIFO overflowed, losing bytes
+    pub uart_overruns: u32,
+    /// Number of commands answered with [crate::ResponseV2::Busy] because the command queue of the
+    /// device was full
+    pub busy_rejections: u32,
+    /// Number of responses dropped because the response queue of the device was full
+    pub dropped_replies: u32,
+    /// Number of responses that could not be written to UART
+    pub write_errors: u32,
+    /// Number of frames rejected because they were not authenticated or encrypted, failed
+    /// verification or were replays. Always zero unless the device requires authenticated or
+    /// encrypted frames.
+    pub auth_failures: u32,
+    /// Reason for the most recent reset of the device
+    pub reset_reason: ResetReason,
+}
+
+/// Reason for the most recent reset of the device
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum ResetReason {
+    /// The device was powered on
+    PowerOn,
+    /// The device was reset by software, e.g., after a panic
+    Software,
+    /// The device was reset by a watchdog timer
+    Watchdog,
+    /// The supply voltage dropped below a safe level
+    BrownOut,
+    /// The device woke up from deep sleep
+    DeepSleep,
+    /// Another platform-specific reason, identified by its raw value
+    Other(u8),
+    /// The reason could not be determined
+    Unknown,
+}
+
+impl ResetReason {
+    /// Whether the reset was caused by a fault rather than by the user, i.e., the device was reset
+    /// by a watchdog timer, a brown-out or an unknown cause
+    pub fn is_unexpected(&self) -> bool {
+        !matches!(
+            self,
+            ResetReason::PowerOn | ResetReason::Software | ResetReason::DeepSleep
+        )
+    }
+}
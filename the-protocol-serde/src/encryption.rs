@@ -0,0 +1,344 @@
+//! Encrypted frames
+//!
+//! Authenticated frames of [crate::auth] keep others from sending commands, but anyone attached to
+//! the line can still read them. In the encrypted mode, every frame is encrypted and authenticated
+//! with ChaCha20-Poly1305 under a key of the current session:
+//!
+//! | [SEALED_TAG] | counter | encrypted packet ... | Poly1305 tag |
+//!
+//! The packet is the decoded content of a plain frame of [crate::Codec] or a link frame of
+//! [crate::link], and the encrypted frame is a COBS packet itself. The counter is a little-endian
+//! `u64` that numbers the frames of each end from zero. It makes up the nonce together with the
+//! [Role] of the sender, and the receiver accepts only counters greater than that of the latest
+//! frame it accepted, which blocks replays within the session.
+//!
+//! A session starts with a handshake, in which the host and the device exchange [Hello] frames:
+//!
+//! | [HELLO_TAG] | role | random | MAC |
+//!
+//! 1. The host sends a hello with a random value of its own, see [Initiator].
+//! 2. The device answers with a hello with a random value of its own and starts the session, see
+//!    [Responder]. Any earlier session ends.
+//! 3. The host starts the session once it receives the answer.
+//!
+//! The MAC of a hello is HMAC-SHA256 keyed with the pre-shared [Key], truncated to [MAC_LEN]
+//! bytes, over the hello and, for the answer, the random value of the host, so that only holders of
+//! the key can answer and an answer cannot be replayed to another host. The key of the session is
+//! derived from the pre-shared key and both random values with HKDF-SHA256, so frames recorded in
+//! one session do not pass in another. A recorded hello of the host can be replayed to end the
+//! session of the device, but the device answers it with a session that the sender has no key for.
+//!
+//! Frames that are not encrypted, fail to decrypt or are replays are rejected with
+//! [DeserializeError::Unauthenticated], and the device counts them in
+//! [crate::Diagnostics::auth_failures].
+use chacha20poly1305::{
+    AeadInPlace, ChaCha20Poly1305, KeyInit, Nonce, Tag, aead::generic_array::GenericArray,
+};
+use corncobs::max_encoded_len;
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::{DeserializeError, SerializeError, cobs};
+
+/// First byte of an encrypted frame
+pub const SEALED_TAG: u8 = 0xF6;
+
+/// First byte of a [Hello] frame
+pub const HELLO_TAG: u8 = 0xF7;
+
+/// Length of the pre-shared key in bytes
+pub const KEY_LEN: usize = 32;
+
+/// Length of the random value of a [Hello] in bytes
+pub const RANDOM_LEN: usize = 16;
+
+/// Length of the MAC of a [Hello] in bytes
+pub const MAC_LEN: usize = 16;
+
+/// Length of the counter of an encrypted frame in bytes
+pub const COUNTER_LEN: usize = 8;
+
+/// Length of the Poly1305 tag of an encrypted frame in bytes
+pub const TAG_LEN: usize = 16;
+
+/// Bytes that encryption adds to a packet
+pub const OVERHEAD: usize = 1 + COUNTER_LEN + TAG_LEN;
+
+/// Length of a [Hello] in bytes, as decoded
+const HELLO_LEN: usize = 2 + RANDOM_LEN + MAC_LEN;
+
+/// Length of the COBS packet of a [Hello], including the framing zero
+pub const HELLO_FRAME_LEN: usize = max_encoded_len(HELLO_LEN);
+
+/// Context of the keys of the sessions derived by HKDF
+const SESSION_INFO: &[u8] = b"the-protocol session key";
+
+/// Pre-shared key of the host and the device
+pub type Key = [u8; KEY_LEN];
+
+/// Random value that each end contributes to the key of a session. It must not repeat, or
+/// frames of an earlier session pass in the new one.
+pub type Random = [u8; RANDOM_LEN];
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Length of the COBS packet of an encrypted frame carrying a frame of at most `frame_len` bytes,
+/// as encoded, including the framing zero
+pub const fn max_frame_len(frame_len: usize) -> usize {
+    max_encoded_len(OVERHEAD + frame_len)
+}
+
+/// Whether the COBS packet `frame` holds a [Hello], without decoding it
+pub fn is_hello(frame: &[u8]) -> bool {
+    // A first block of one byte stands for a leading zero
+    frame.len() > 2 && frame[0] > 1 && frame[1] == HELLO_TAG
+}
+
+/// End of the line that a frame comes from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Role {
+    /// The host, which starts the handshake
+    Host = 0,
+    /// The device, which answers the handshake
+    Device = 1,
+}
+
+/// Frame of the handshake, see the [module](self) documentation
+#[derive(Clone, Debug)]
+pub struct Hello {
+    role: Role,
+    random: Random,
+    mac: [u8; MAC_LEN],
+}
+
+impl Hello {
+    /// Encodes the hello into a COBS packet at the start of `buf`. Returns the sub-slice of `buf`
+    /// that was allocated.
+    ///
+    /// # Errors
+    ///
+    /// * [SerializeError::BufferTooSmall] if `buf` is shorter than [HELLO_FRAME_LEN]
+    pub fn encode<'a>(&self, buf: &'a mut [u8]) -> Result<&'a mut [u8], SerializeError> {
+        if buf.len() < HELLO_FRAME_LEN {
+            return Err(SerializeError::BufferTooSmall);
+        }
+        let start = cobs::start(HELLO_LEN);
+        buf[start] = HELLO_TAG;
+        buf[start + 1] = self.role as u8;
+        buf[start + 2..start + 2 + RANDOM_LEN].copy_from_slice(&self.random);
+        buf[start + 2 + RANDOM_LEN..start + HELLO_LEN].copy_from_slice(&self.mac);
+        let n = cobs::encode_in_place(buf, start, HELLO_LEN);
+        Ok(&mut buf[0..n])
+    }
+}
+
+/// Decodes the hello of `role` in the COBS packet `frame` and verifies its MAC, which also covers
+/// `peer` for an answer. Returns the random value of the hello.
+fn open_hello(
+    frame: &mut [u8],
+    key: &Key,
+    role: Role,
+    peer: Option<&Random>,
+) -> Result<Random, DeserializeError> {
+    let n = corncobs::decode_in_place(frame).map_err(|_| DeserializeError::Cobs)?;
+    if n != HELLO_LEN || frame[0] != HELLO_TAG || frame[1] != role as u8 {
+        return Err(DeserializeError::Unauthenticated);
+    }
+    let random: Random = frame[2..2 + RANDOM_LEN].try_into().unwrap();
+    hello_mac(key, role, &random, peer)
+        .verify_truncated_left(&frame[2 + RANDOM_LEN..HELLO_LEN])
+        .map_err(|_| DeserializeError::Unauthenticated)?;
+    Ok(random)
+}
+
+/// HMAC of a hello, to be finalized or verified
+fn hello_mac(key: &Key, role: Role, random: &Random, peer: Option<&Random>) -> HmacSha256 {
+    let mut mac = <HmacSha256 as Mac>::new_from_slice(key).unwrap();
+    mac.update(&[HELLO_TAG, role as u8]);
+    mac.update(random);
+    if let Some(peer) = peer {
+        mac.update(peer);
+    }
+    mac
+}
+
+/// Creates the [Hello] of `role` with `random`, covering `peer` for an answer
+fn hello(key: &Key, role: Role, random: Random, peer: Option<&Random>) -> Hello {
+    let tag = hello_mac(key, role, &random, peer).finalize().into_bytes();
+    let mut mac = [0; MAC_LEN];
+    mac.copy_from_slice(&tag[..MAC_LEN]);
+    Hello { role, random, mac }
+}
+
+/// End of the host in the handshake
+pub struct Initiator {
+    key: Key,
+    random: Random,
+}
+
+impl Initiator {
+    /// Creates the end of the host with the pre-shared `key` and a fresh `random` value
+    pub const fn new(key: Key, random: Random) -> Self {
+        Self { key, random }
+    }
+
+    /// Hello that starts the handshake
+    pub fn hello(&self) -> Hello {
+        hello(&self.key, Role::Host, self.random, None)
+    }
+
+    /// Verifies the answer of the device in the COBS packet `frame` and starts the session
+    ///
+    /// # Errors
+    ///
+    /// * [DeserializeError::Cobs] if `frame` is not a COBS packet
+    /// * [DeserializeError::Unauthenticated] if `frame` is not an answer to [Initiator::hello]
+    pub fn finish(&self, frame: &mut [u8]) -> Result<Session, DeserializeError> {
+        let device = open_hello(frame, &self.key, Role::Device, Some(&self.random))?;
+        Ok(Session::new(&self.key, &self.random, &device, Role::Host))
+    }
+}
+
+/// End of the device in the handshake
+pub struct Responder {
+    key: Key,
+}
+
+impl Responder {
+    /// Creates the end of the device with the pre-shared `key`
+    pub const fn new(key: Key) -> Self {
+        Self { key }
+    }
+
+    /// Verifies the hello of the host in the COBS packet `frame` and starts a session with a fresh
+    /// `random` value. Returns the session and the answer to send to the host.
+    ///
+    /// # Errors
+    ///
+    /// * [DeserializeError::Cobs] if `frame` is not a COBS packet
+    /// * [DeserializeError::Unauthenticated] if `frame` is not a hello of a host with the key
+    pub fn accept(
+        &self,
+        frame: &mut [u8],
+        random: Random,
+    ) -> Result<(Session, Hello), DeserializeError> {
+        let host = open_hello(frame, &self.key, Role::Host, None)?;
+        let session = Session::new(&self.key, &host, &random, Role::Device);
+        let answer = hello(&self.key, Role::Device, random, Some(&host));
+        Ok((session, answer))
+    }
+}
+
+/// Session of one end, which encrypts its frames and decrypts those of the peer
+pub struct Session {
+    cipher: ChaCha20Poly1305,
+    /// Sender of the frames encrypted
+    role: Role,
+    /// Counter of the next frame encrypted
+    next_counter: u64,
+    /// Counter of the latest frame accepted, `None` until a frame is accepted
+    last_counter: Option<u64>,
+}
+
+impl Session {
+    fn new(key: &Key, host: &Random, device: &Random, role: Role) -> Self {
+        let mut salt = [0; 2 * RANDOM_LEN];
+        salt[..RANDOM_LEN].copy_from_slice(host);
+        salt[RANDOM_LEN..].copy_from_slice(device);
+        let mut session_key = [0; KEY_LEN];
+        Hkdf::<Sha256>::new(Some(&salt), key)
+            .expand(SESSION_INFO, &mut session_key)
+            // The length of the key is well within the limit of HKDF
+            .unwrap();
+        Self {
+            cipher: ChaCha20Poly1305::new(&session_key.into()),
+            role,
+            next_counter: 0,
+            last_counter: None,
+        }
+    }
+
+    /// Encrypts the COBS packet in `buf[..len]` into an encrypted frame in place. Returns the
+    /// sub-slice of `buf` that was allocated.
+    ///
+    /// # Errors
+    ///
+    /// * [SerializeError::BufferTooSmall] if `buf` is shorter than [max_frame_len] of the packet,
+    ///   or `buf[..len]` is not a COBS packet
+    pub fn seal<'a>(
+        &mut self,
+        buf: &'a mut [u8],
+        len: usize,
+    ) -> Result<&'a mut [u8], SerializeError> {
+        let n = corncobs::decode_in_place(&mut buf[..len])
+            .map_err(|_| SerializeError::BufferTooSmall)?;
+        let sealed_len = OVERHEAD + n;
+        if max_encoded_len(sealed_len) > buf.len() {
+            return Err(SerializeError::BufferTooSmall);
+        }
+        let start = cobs::start(sealed_len);
+        let counter = self.next_counter;
+        self.next_counter += 1;
+
+        // The packet moves up, past the start of the encoding and the header
+        let body = start + 1 + COUNTER_LEN;
+        buf.copy_within(0..n, body);
+        buf[start] = SEALED_TAG;
+        buf[start + 1..body].copy_from_slice(&counter.to_le_bytes());
+        let (header, rest) = buf[start..].split_at_mut(1 + COUNTER_LEN);
+        let tag = self
+            .cipher
+            .encrypt_in_place_detached(&nonce(self.role, counter), header, &mut rest[..n])
+            // Only fails for packets longer than anything that fits in memory
+            .unwrap();
+        rest[n..n + TAG_LEN].copy_from_slice(&tag);
+        let n = cobs::encode_in_place(buf, start, sealed_len);
+        Ok(&mut buf[0..n])
+    }
+
+    /// Decrypts the encrypted frame in the COBS packet `frame` and replaces it in place with the
+    /// COBS packet it carries. Returns the sub-slice of `frame` that holds the packet.
+    ///
+    /// # Errors
+    ///
+    /// * [DeserializeError::Cobs] if `frame` is not a COBS packet
+    /// * [DeserializeError::Unauthenticated] if the frame is not encrypted, fails to decrypt or
+    ///   the counter is not greater than that of the latest frame accepted
+    pub fn open<'a>(&mut self, frame: &'a mut [u8]) -> Result<&'a mut [u8], DeserializeError> {
+        let n = corncobs::decode_in_place(frame).map_err(|_| DeserializeError::Cobs)?;
+        if n < OVERHEAD || frame[0] != SEALED_TAG {
+            return Err(DeserializeError::Unauthenticated);
+        }
+        let len = n - OVERHEAD;
+        let (header, rest) = frame.split_at_mut(1 + COUNTER_LEN);
+        let counter = u64::from_le_bytes(header[1..].try_into().unwrap());
+        if self.last_counter.is_some_and(|last| counter <= last) {
+            return Err(DeserializeError::Unauthenticated);
+        }
+        let (packet, tag) = rest[..len + TAG_LEN].split_at_mut(len);
+        let peer = match self.role {
+            Role::Host => Role::Device,
+            Role::Device => Role::Host,
+        };
+        self.cipher
+            .decrypt_in_place_detached(&nonce(peer, counter), header, packet, Tag::from_slice(tag))
+            .map_err(|_| DeserializeError::Unauthenticated)?;
+        self.last_counter = Some(counter);
+
+        // Encode the packet again, which is shorter than the frame by at least the overhead
+        let start = cobs::start(len);
+        frame.copy_within(1 + COUNTER_LEN..1 + COUNTER_LEN + len, start);
+        let n = cobs::encode_in_place(frame, start, len);
+        Ok(&mut frame[0..n])
+    }
+}
+
+/// Nonce of the frame numbered `counter` of `role`
+fn nonce(role: Role, counter: u64) -> Nonce {
+    let mut nonce = GenericArray::default();
+    nonce[0] = role as u8;
+    nonce[4..].copy_from_slice(&counter.to_le_bytes());
+    nonce
+}
@@ -0,0 +1,117 @@
+//! Notifications sent by the device without a preceding command
+//!
+//! An event frame starts with [EVENT_TAG], which never starts a [Reply], so that the host can tell
+//! events and replies apart by decoding every frame from the device as a [DeviceMessage].
+//!
+//! The device only sends events after the host has subscribed to them with
+//! [crate::CommandV2::SubscribeEvents], so that hosts which only speak the frozen ABI never receive
+//! a frame they cannot decode.
+use core::fmt;
+
+use serde::{
+    Deserialize, Deserializer, Serialize, Serializer,
+    de::{SeqAccess, Visitor},
+};
+use the_protocol::{Funct, SDateTime};
+
+use crate::{
+    JobId, Reply, ResetReason,
+    v2::{MAX_FIELDS, next, reply_with_tag},
+};
+
+/// Leading byte of an event message
+///
+/// Never collides with [crate::V2_TAG] or the variant index of a legacy [the_protocol::Response].
+pub const EVENT_TAG: u8 = 0xF3;
+
+/// Unsolicited notification from the device
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum Event {
+    /// A scheduled job fired
+    JobFired {
+        /// Identifier of the job
+        id: JobId,
+        /// Time at which the job was scheduled to fire
+        at: SDateTime,
+    },
+    /// The time of the device was unset, e.g., by [the_protocol::Command::Reset]. Scheduled jobs
+    /// do not fire until the time is set again.
+    ClockUnset,
+    /// The device has booted since the previous subscription. Sent once after the first
+    /// subscription after boot.
+    Rebooted {
+        /// Reason for the reset that preceded the boot
+        reason: ResetReason,
+    },
+    /// The operator pressed the button of the device, which executed `funct` as if it had been
+    /// sent with [the_protocol::Command::Immediate]
+    ButtonPressed {
+        /// How long the button was held
+        press: Press,
+        /// Action taken for the press
+        funct: Funct,
+    },
+}
+
+/// Press of the button of the device, told apart by how long the button was held
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum Press {
+    /// The button was released quickly
+    Short,
+    /// The button was held for a while
+    Long,
+}
+
+/// Any message sent by the device: a reply to a command or an event
+#[derive(Clone, Debug, PartialEq)]
+#[allow(clippy::large_enum_variant)]
+pub enum DeviceMessage {
+    /// Reply to a command of the host
+    Reply(Reply),
+    /// Unsolicited notification
+    Event(Event),
+}
+
+impl From<Reply> for DeviceMessage {
+    fn from(reply: Reply) -> Self {
+        DeviceMessage::Reply(reply)
+    }
+}
+
+impl From<Event> for DeviceMessage {
+    fn from(event: Event) -> Self {
+        DeviceMessage::Event(event)
+    }
+}
+
+impl Serialize for DeviceMessage {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            DeviceMessage::Reply(reply) => reply.serialize(serializer),
+            DeviceMessage::Event(event) => (EVENT_TAG, event).serialize(serializer),
+        }
+    }
+}
+
+struct DeviceMessageVisitor;
+
+impl<'de> Visitor<'de> for DeviceMessageVisitor {
+    type Value = DeviceMessage;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a response or an event")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<DeviceMessage, A::Error> {
+        match next::<u8, _>(&mut seq, 0, &self)? {
+            EVENT_TAG => Ok(DeviceMessage::Event(next(&mut seq, 1, &self)?)),
+            tag => reply_with_tag(tag, &mut seq, &self).map(DeviceMessage::Reply),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for DeviceMessage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_tuple(1 + MAX_FIELDS, DeviceMessageVisitor)
+    }
+}
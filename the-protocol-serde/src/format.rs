@@ -0,0 +1,116 @@
+//! Serializer backends that encode messages into bytes before COBS framing
+//!
+//! [Codec] encodes with [Ssmarshal] by default, which is the format of the frozen
+//! [the_protocol] ABI. The `postcard` feature switches [Codec] to `Postcard`, which encodes
+//! integers as varints and shrinks most frames considerably. The host and the device must be built
+//! with the same backend.
+//!
+//! With the `postcard` feature, both backends are available through [encode] and
+//! [decode_in_place], e.g., to compare the frames of the backends or to talk to a device built with
+//! the other backend.
+//!
+//! [Codec]: crate::Codec
+use corncobs::max_encoded_len;
+use serde::{Serialize, de::DeserializeOwned};
+
+use crate::{DeserializeError, SerializeError, cobs};
+
+/// Serializer backend
+pub trait Format {
+    /// Serializes `value` into the start of `buf`. Returns the number of bytes written.
+    fn to_slice<P: Serialize>(value: &P, buf: &mut [u8]) -> Result<usize, SerializeError>;
+
+    /// Deserializes a value from the start of `buf`
+    fn from_slice<P: DeserializeOwned>(buf: &[u8]) -> Result<P, DeserializeError>;
+}
+
+/// [ssmarshal] backend, which encodes integers at their full width
+///
+/// The frozen [the_protocol] ABI is defined in this format. The worst-case size of a message is
+/// given by [crate::WireSize].
+pub struct Ssmarshal;
+
+impl Format for Ssmarshal {
+    fn to_slice<P: Serialize>(value: &P, buf: &mut [u8]) -> Result<usize, SerializeError> {
+        ssmarshal::serialize(buf, value).map_err(|e| match e {
+            ssmarshal::Error::EndOfStream => SerializeError::BufferTooSmall,
+            _ => SerializeError::Unsupported,
+        })
+    }
+
+    fn from_slice<P: DeserializeOwned>(buf: &[u8]) -> Result<P, DeserializeError> {
+        ssmarshal::deserialize(buf)
+            .map(|(value, _bytes_used)| value)
+            .map_err(|_| DeserializeError::Malformed)
+    }
+}
+
+/// [postcard] backend, which encodes integers other than `u8` as varints
+///
+/// The worst-case size of a message is given by [crate::MaxSize].
+#[cfg(feature = "postcard")]
+pub struct Postcard;
+
+#[cfg(feature = "postcard")]
+impl Format for Postcard {
+    fn to_slice<P: Serialize>(value: &P, buf: &mut [u8]) -> Result<usize, SerializeError> {
+        postcard::to_slice(value, buf)
+            .map(|used| used.len())
+            .map_err(|e| match e {
+                postcard::Error::SerializeBufferFull => SerializeError::BufferTooSmall,
+                _ => SerializeError::Unsupported,
+            })
+    }
+
+    fn from_slice<P: DeserializeOwned>(buf: &[u8]) -> Result<P, DeserializeError> {
+        postcard::from_bytes(buf).map_err(|_| DeserializeError::Malformed)
+    }
+}
+
+/// Backend used by [crate::Codec], selected with the `postcard` feature
+#[cfg(not(feature = "postcard"))]
+pub type DefaultFormat = Ssmarshal;
+/// Backend used by [crate::Codec], selected with the `postcard` feature
+#[cfg(feature = "postcard")]
+pub type DefaultFormat = Postcard;
+
+/// Serializes `value` with the backend `F` into a COBS packet. Returns the sub-slice of `out_buf`
+/// that was allocated.
+///
+/// The value is serialized into the end of `out_buf` and encoded towards its start, so no other
+/// buffer is needed.
+///
+/// # Errors
+///
+/// * [SerializeError::BufferTooSmall] if `out_buf` cannot hold the worst-case COBS encoding of
+///   the serialized value
+pub fn encode<'a, F: Format, P: Serialize, const N: usize>(
+    value: &P,
+    out_buf: &'a mut [u8; N],
+) -> Result<&'a mut [u8], SerializeError> {
+    // Leave room for the overhead of the largest packet that fits the buffer
+    let start = cobs::start(N);
+    let ser_buf = out_buf
+        .get_mut(start..)
+        .ok_or(SerializeError::BufferTooSmall)?;
+    let n_ser = F::to_slice(value, ser_buf)?;
+    if max_encoded_len(n_ser) > N {
+        return Err(SerializeError::BufferTooSmall);
+    }
+    let n = cobs::encode_in_place(out_buf, start, n_ser);
+    Ok(&mut out_buf[0..n])
+}
+
+/// Deserializes a value with the backend `F` from a COBS packet, reusing `in_buf` for the decoded
+/// bytes
+///
+/// # Errors
+///
+/// * [DeserializeError::Cobs] if `in_buf` is not a COBS packet
+/// * [DeserializeError::Malformed] if the packet does not carry a value of type `P`
+pub fn decode_in_place<F: Format, P: DeserializeOwned>(
+    in_buf: &mut [u8],
+) -> Result<P, DeserializeError> {
+    let n = corncobs::decode_in_place(in_buf).map_err(|_| DeserializeError::Cobs)?;
+    F::from_slice(&in_buf[0..n])
+}
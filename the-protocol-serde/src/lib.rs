@@ -10,11 +10,52 @@
 // The serialization layer must be documented thoroughly
 #![deny(missing_docs)]
 
+mod admission;
+#[cfg(feature = "auth")]
+pub mod auth;
+mod batch;
+mod baud;
+pub mod cobs;
 mod codec;
+mod date_time;
+mod diagnostics;
+#[cfg(feature = "encryption")]
+pub mod encryption;
+mod event;
+pub mod format;
+mod limits;
+pub mod link;
+mod pattern;
+mod receiver;
+mod recurrence;
+mod rgb;
+mod schedule;
 mod serde;
+mod stats;
+mod v2;
+mod wire_size;
 
+pub use admission::{COMMAND_QUEUE_LEN, admit, parse_capacity};
+pub use batch::{Batch, BatchMode, BatchResponse, MAX_BATCH_LEN};
+pub use baud::{BAUD_FALLBACK_MS, DEFAULT_BAUD_RATE};
 pub use codec::Codec;
 pub use corncobs;
+pub use date_time::{is_valid_date_time, to_utc};
+pub use diagnostics::{Diagnostics, ResetReason};
+pub use event::{DeviceMessage, EVENT_TAG, Event, Press};
+pub use limits::Limits;
+pub use pattern::{BlinkPattern, BlinkShape, BlinkStep, BlinkSteps, MAX_MORSE_LEN, MorseText};
+pub use receiver::{FrameReceiver, Received};
+pub use recurrence::Recurrence;
+pub use rgb::{Color, RgbEffect};
+pub use schedule::{JobId, SCHEDULE_PAGE_LEN, SchedulePage, ScheduledJob};
+pub use serde::{DeserializeError, SerializeError};
+pub use stats::{LinkMonitor, LinkStats, RttEstimator};
+pub use v2::{
+    CommandV2, FirmwareInfo, LedState, PROTOCOL_REVISION, PayloadV2, Reply, Request, ResponseV2,
+    V2_TAG, Version,
+};
+pub use wire_size::{MaxSize, WireSize};
 
 // Expose all visible items from [the_protocol]
 pub use the_protocol::*;
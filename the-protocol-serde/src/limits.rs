@@ -0,0 +1,113 @@
+//! Ranges of command arguments that a device accepts
+//!
+//! The device checks every command against its [Limits] before executing it, and rejects a command
+//! with a value out of range, or a date-time that does not exist on the calendar, with
+//! [crate::RejectReason::IllegalCommand]. The host can retrieve the
+//! limits with [CommandV2::Limits] to check its commands up front.
+use serde::{Deserialize, Serialize};
+use the_protocol::Funct;
+
+use crate::{BlinkPattern, BlinkShape, CommandV2, RgbEffect, is_valid_date_time};
+
+/// Ranges of command arguments accepted by the device, returned for [CommandV2::Limits]
+///
+/// Ranges are inclusive.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct Limits {
+    /// Shortest blink period of [Funct::EnableBlink] in milliseconds
+    pub min_blink_period_ms: u64,
+    /// Longest blink period of [Funct::EnableBlink] in milliseconds
+    pub max_blink_period_ms: u64,
+    /// Shortest step of a [BlinkPattern] in milliseconds, e.g., a dot of Morse code
+    pub min_blink_step_ms: u32,
+    /// Longest step of a [BlinkPattern] in milliseconds, e.g., a word gap of Morse code
+    pub max_blink_step_ms: u32,
+    /// Shortest period of an animated [RgbEffect] in milliseconds
+    pub min_rgb_period_ms: u32,
+    /// Longest period of an animated [RgbEffect], or duration of a fade, in milliseconds
+    pub max_rgb_period_ms: u32,
+    /// Highest brightness of [CommandV2::SetRgbBrightness]
+    pub max_rgb_brightness: u8,
+    /// Lowest baud rate of [CommandV2::SetBaudRate]
+    pub min_baud_rate: u32,
+    /// Highest baud rate of [CommandV2::SetBaudRate]
+    pub max_baud_rate: u32,
+}
+
+impl Limits {
+    /// Whether every argument of the command is within the limits, including the functionality of
+    /// scheduled jobs. Date-times, including the start of a [crate::Recurrence], must exist on the
+    /// calendar.
+    ///
+    /// A batch is always allowed as a unit. Its commands are checked one by one as the batch is
+    /// executed, so that a command out of range is rejected on its own: the other commands of a
+    /// [crate::BatchMode::BestEffort] batch still run, and an atomic batch is rolled back.
+    pub fn allows(&self, cmd: &CommandV2) -> bool {
+        match cmd {
+            CommandV2::Immediate(f) => self.allows_funct(f),
+            CommandV2::Schedule(f, at) => self.allows_funct(f) && is_valid_date_time(at),
+            CommandV2::ScheduleRecurring(f, rule) => self.allows_funct(f) && rule.is_valid(),
+            CommandV2::SetDateTime(dt) => dt.as_ref().is_none_or(is_valid_date_time),
+            CommandV2::SetBlinkPattern(pattern) => self.allows_pattern(pattern),
+            CommandV2::SetRgbEffect(effect) => self.allows_effect(effect),
+            CommandV2::SetRgbBrightness(brightness) => *brightness <= self.max_rgb_brightness,
+            CommandV2::SetBaudRate(rate) => {
+                (self.min_baud_rate..=self.max_baud_rate).contains(rate)
+            }
+            CommandV2::Reset
+            | CommandV2::Counter
+            | CommandV2::CancelJob(_)
+            | CommandV2::ClearSchedule
+            | CommandV2::ListSchedule { .. }
+            | CommandV2::LedState
+            | CommandV2::DateTime
+            | CommandV2::SetCounter(_)
+            | CommandV2::FirmwareInfo
+            | CommandV2::Diagnostics
+            | CommandV2::SubscribeEvents(_)
+            | CommandV2::Limits
+            | CommandV2::LinkStats
+            | CommandV2::Batch(_) => true,
+        }
+    }
+
+    /// Whether the arguments of the functionality are within the limits
+    pub fn allows_funct(&self, f: &Funct) -> bool {
+        match f {
+            Funct::EnableBlink { period_ms } => {
+                (self.min_blink_period_ms..=self.max_blink_period_ms).contains(period_ms)
+            }
+            Funct::Increment | Funct::DisableBlink | Funct::EnableRgb | Funct::DisableRgb => true,
+        }
+    }
+
+    /// Whether the pattern is valid and every step of it is within the limits
+    pub fn allows_pattern(&self, pattern: &BlinkPattern) -> bool {
+        let step = |ms: u32| (self.min_blink_step_ms..=self.max_blink_step_ms).contains(&ms);
+        let steps_allowed = match pattern.shape {
+            BlinkShape::Duty { on_ms, off_ms } => step(on_ms) && step(off_ms),
+            BlinkShape::Burst {
+                flashes,
+                on_ms,
+                off_ms,
+                pause_ms,
+            } => step(on_ms) && (flashes == 1 || step(off_ms)) && step(pause_ms),
+            // The steps range from a dot of one unit to a word gap of seven units
+            BlinkShape::Morse { unit_ms, .. } => step(unit_ms) && step(unit_ms.saturating_mul(7)),
+        };
+        pattern.is_valid() && steps_allowed
+    }
+
+    /// Whether the effect is valid and its period or duration is within the limits
+    pub fn allows_effect(&self, effect: &RgbEffect) -> bool {
+        let allowed = match effect {
+            RgbEffect::Breathe { period_ms, .. } | RgbEffect::Rainbow { period_ms } => {
+                (self.min_rgb_period_ms..=self.max_rgb_period_ms).contains(period_ms)
+            }
+            // A fade may be instant
+            RgbEffect::Fade { duration_ms, .. } => *duration_ms <= self.max_rgb_period_ms,
+            RgbEffect::Off | RgbEffect::Static(_) => true,
+        };
+        effect.is_valid() && allowed
+    }
+}
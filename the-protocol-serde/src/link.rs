@@ -0,0 +1,531 @@
+//! Sliding-window transport of messages
+//!
+//! With plain frames, the host sends one command and waits for its reply, which leaves the line
+//! idle for most of each round trip. A [Link] keeps up to a window of frames in flight instead.
+//! Every data frame carries a sequence number, and every frame carries a cumulative
+//! acknowledgement: the sequence number of the next data frame that its sender expects from the
+//! peer. When the oldest frame in flight is not acknowledged within the retransmission timeout,
+//! it is sent again along with every frame after it (Go-Back-N). The receiver only accepts data
+//! frames in order, so messages are delivered exactly once and in the order they were sent.
+//!
+//! The receiver does not wait for the timeout when it can tell that a frame went missing. It
+//! answers a damaged frame, or a data frame ahead of the one it expects, with a [Kind::Nak]
+//! carrying the sequence number of the frame it expects, and the sender goes back to that frame
+//! right away. The NAK names the frame expected rather than the damaged frame, whose sequence
+//! number cannot be trusted: the frame expected is the oldest one that may be missing, and
+//! Go-Back-N sends every frame after it again anyway.
+//!
+//! Link frames are COBS packets like the frames of [crate::Codec], so link frames and plain frames
+//! can share the line. A link frame starts with a header followed by the message serialized with
+//! [DefaultFormat]:
+//!
+//! | [LINK_TAG] | [Kind] | sequence number | acknowledgement | CRC-16 | message ... |
+//!
+//! The CRC-16 (CCITT-FALSE, little endian) covers every other byte of the frame. A frame damaged
+//! on the line fails the check in [unpack] rather than deliver a wrong message, or slide the
+//! window with a wrong acknowledgement, and is handled like a frame that was lost.
+//!
+//! [LINK_TAG] is neither the variant of a legacy message nor [crate::V2_TAG] or
+//! [crate::EVENT_TAG], so [unpack] tells link frames from plain frames by the first byte.
+//!
+//! The retransmission timeout adapts to the line. The link measures the round-trip time of the
+//! frames that were acknowledged without being sent again, and keeps the [LinkStats] of its end,
+//! see [crate::LinkMonitor].
+//!
+//! A session starts with a [Kind::Sync] frame from the host, after which both ends count sequence
+//! numbers from zero. The link does not read a clock itself. Like [crate::FrameReceiver], the
+//! caller passes the current time in milliseconds, which makes the link deterministic and
+//! testable against a simulated channel.
+use checksum::{CRC_16_CCITT_FALSE, Crc};
+use corncobs::max_encoded_len;
+use serde::Serialize;
+
+use crate::{
+    DeserializeError, LinkMonitor, LinkStats, RttEstimator, SerializeError, cobs,
+    format::{DefaultFormat, Format},
+};
+
+/// First byte of a link frame
+pub const LINK_TAG: u8 = 0xF4;
+
+/// Length of the header of a link frame in bytes
+pub const HEADER_LEN: usize = 6;
+
+/// Largest number of frames in flight: half of the sequence numbers, which are one byte, so that
+/// the receiver tells the frames ahead of the one it expects from duplicates of earlier frames
+pub const MAX_WINDOW: usize = 128;
+
+/// Length of the COBS packet of a link frame carrying a message of at most `payload_len` bytes,
+/// including the framing zero
+pub const fn max_frame_len(payload_len: usize) -> usize {
+    max_encoded_len(HEADER_LEN + payload_len)
+}
+
+/// Kind of a link frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Kind {
+    /// Carries a message with a sequence number
+    Data = 0,
+    /// Carries only an acknowledgement
+    Ack = 1,
+    /// Starts a session. The receiver drops the frames it has in flight, counts sequence numbers
+    /// from zero and acknowledges.
+    Sync = 2,
+    /// Acknowledges the frames before the sequence number, and asks for the frame with the
+    /// sequence number and every frame after it again
+    Nak = 3,
+}
+
+impl Kind {
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Kind::Data),
+            1 => Some(Kind::Ack),
+            2 => Some(Kind::Sync),
+            3 => Some(Kind::Nak),
+            _ => None,
+        }
+    }
+}
+
+/// Header of a link frame
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    /// Kind of the frame
+    pub kind: Kind,
+    /// Sequence number of a [Kind::Data] frame or of the frame asked for by a [Kind::Nak], zero
+    /// otherwise
+    pub seq: u8,
+    /// Sequence number of the next data frame that the sender expects
+    pub ack: u8,
+}
+
+/// Decoded frame, see [unpack]
+#[derive(Debug, PartialEq)]
+pub enum Packet<'a> {
+    /// Frame of [crate::Codec], which carries a serialized message
+    Plain(&'a [u8]),
+    /// Link frame, with the serialized message of a [Kind::Data] frame
+    Link(Header, &'a [u8]),
+}
+
+/// Decodes a COBS packet in place and tells a link frame from a plain frame
+///
+/// # Errors
+///
+/// * [DeserializeError::Cobs] if `frame` is not a COBS packet
+/// * [DeserializeError::Malformed] if the link header is truncated or has an unknown kind
+/// * [DeserializeError::Corrupted] if the link frame fails its CRC
+pub fn unpack(frame: &mut [u8]) -> Result<Packet<'_>, DeserializeError> {
+    let n = corncobs::decode_in_place(frame).map_err(|_| DeserializeError::Cobs)?;
+    match &frame[..n] {
+        [LINK_TAG, kind, seq, ack, crc_lo, crc_hi, payload @ ..] => {
+            if checksum(&[LINK_TAG, *kind, *seq, *ack], payload)
+                != u16::from_le_bytes([*crc_lo, *crc_hi])
+            {
+                return Err(DeserializeError::Corrupted);
+            }
+            let kind = Kind::from_byte(*kind).ok_or(DeserializeError::Malformed)?;
+            let header = Header {
+                kind,
+                seq: *seq,
+                ack: *ack,
+            };
+            Ok(Packet::Link(header, payload))
+        }
+        [LINK_TAG, ..] => Err(DeserializeError::Malformed),
+        bytes => Ok(Packet::Plain(bytes)),
+    }
+}
+
+/// Parameters of a [Link]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// Largest number of data frames in flight, at least one. A window of one is stop-and-wait.
+    pub window: usize,
+    /// Time in milliseconds after which unacknowledged frames are sent again, until the round-trip
+    /// time has been measured
+    pub timeout_ms: u64,
+    /// Shortest retransmission timeout in milliseconds
+    pub min_timeout_ms: u64,
+    /// Longest retransmission timeout in milliseconds, also after backing off
+    pub max_timeout_ms: u64,
+    /// Whether to ask for missing frames with [Kind::Nak] rather than wait for the peer to time
+    /// out
+    pub nak: bool,
+}
+
+/// Error of [Link::send]
+#[derive(Debug)]
+pub enum SendError {
+    /// The window is full until the peer acknowledges a frame
+    WindowFull,
+    /// The message could not be serialized into a slot of the window
+    Serialize(SerializeError),
+}
+
+/// One end of a sliding-window link
+///
+/// The link keeps a copy of every data frame in flight for retransmission. The caller writes the
+/// frames to the line and passes the frames from the line to [Link::receive]:
+///
+/// 1. [Link::send] a message when [Link::can_send], then write [Link::frame] of it
+/// 2. [Link::receive] the frames unpacked with [unpack]
+/// 3. [Link::reject] the frames that fail to unpack
+/// 4. When [Link::poll] reports that the timer has expired or the peer has asked for frames
+///    again, write [Link::frame] of every [Link::unacked] frame again
+/// 5. When [Link::needs_ack] and there is no data frame to carry the acknowledgement, write
+///    [Link::ack_frame]
+///
+/// # Type arguments
+///
+/// * `W` - Capacity of the window in frames, at most [MAX_WINDOW]
+/// * `N` - Maximum length of a serialized message in bytes
+pub struct Link<const W: usize, const N: usize> {
+    config: Config,
+    /// Sequence number of the oldest frame in flight
+    base: u8,
+    /// Sequence number of the next data frame to send
+    next: u8,
+    /// Slot of the frame `base`
+    head: usize,
+    /// Frames in flight
+    slots: [Slot<N>; W],
+    /// Time at which the retransmission timer was started, `None` while nothing is in flight
+    timer_ms: Option<u64>,
+    /// Sequence number of the next data frame expected from the peer
+    expected: u8,
+    /// Whether the peer is owed an acknowledgement
+    ack_pending: bool,
+    /// Whether the peer is owed a [Kind::Nak] for the frame `expected`
+    nak_pending: bool,
+    /// Whether a [Kind::Nak] has been sent for the frame `expected`. The frame is asked for once,
+    /// after which the timer of the peer takes over.
+    nak_sent: bool,
+    /// Whether the peer has asked for the frames in flight again
+    resend: bool,
+    monitor: LinkMonitor,
+}
+
+/// Data frame in flight
+#[derive(Clone, Copy)]
+struct Slot<const N: usize> {
+    /// Serialized message and its length
+    buf: [u8; N],
+    len: usize,
+    /// Time at which the message was sent
+    sent_ms: u64,
+    /// Whether the frame has been written, and whether more than once
+    written: bool,
+    retransmitted: bool,
+}
+
+impl<const W: usize, const N: usize> Link<W, N> {
+    /// Creates a link. The window is limited to the capacity `W`.
+    pub const fn new(config: Config) -> Self {
+        assert!(W >= 1 && W <= MAX_WINDOW);
+        let window = if config.window == 0 {
+            1
+        } else if config.window > W {
+            W
+        } else {
+            config.window
+        };
+        Self {
+            config: Config { window, ..config },
+            base: 0,
+            next: 0,
+            head: 0,
+            slots: [Slot {
+                buf: [0; N],
+                len: 0,
+                sent_ms: 0,
+                written: false,
+                retransmitted: false,
+            }; W],
+            timer_ms: None,
+            expected: 0,
+            ack_pending: false,
+            nak_pending: false,
+            nak_sent: false,
+            resend: false,
+            monitor: LinkMonitor::new(RttEstimator::new(
+                config.timeout_ms,
+                config.min_timeout_ms,
+                config.max_timeout_ms,
+            )),
+        }
+    }
+
+    /// Parameters of the link, with the window limited to the capacity
+    pub fn config(&self) -> Config {
+        self.config
+    }
+
+    /// Statistics of this end of the link since it was created
+    pub fn stats(&self) -> LinkStats {
+        self.monitor.stats()
+    }
+
+    /// Drops the frames in flight and counts sequence numbers from zero in both directions. The
+    /// statistics and the round-trip time carry over.
+    pub fn reset(&mut self) {
+        self.base = 0;
+        self.next = 0;
+        self.head = 0;
+        self.timer_ms = None;
+        self.expected = 0;
+        self.ack_pending = false;
+        self.nak_pending = false;
+        self.nak_sent = false;
+        self.resend = false;
+    }
+
+    /// Number of data frames sent but not acknowledged yet
+    pub fn in_flight(&self) -> usize {
+        self.next.wrapping_sub(self.base) as usize
+    }
+
+    /// Whether there is room in the window for another data frame
+    pub fn can_send(&self) -> bool {
+        self.in_flight() < self.config.window
+    }
+
+    /// Whether every data frame sent has been acknowledged
+    pub fn is_idle(&self) -> bool {
+        self.in_flight() == 0
+    }
+
+    /// Whether the peer is owed an acknowledgement that no frame has carried yet, or a
+    /// [Kind::Nak]
+    pub fn needs_ack(&self) -> bool {
+        self.ack_pending || self.nak_pending
+    }
+
+    /// Time at which the retransmission timer expires, `None` while nothing is in flight
+    pub fn deadline(&self) -> Option<u64> {
+        self.timer_ms
+            .map(|t| t.saturating_add(self.monitor.rto_ms()))
+    }
+
+    /// Serializes `msg` into a new data frame in the window at `now_ms`. Returns its sequence
+    /// number, to be written with [Link::frame].
+    ///
+    /// # Errors
+    ///
+    /// * [SendError::WindowFull] if the window is full
+    /// * [SendError::Serialize] if `msg` does not fit `N` bytes
+    pub fn send<P: Serialize>(&mut self, msg: &P, now_ms: u64) -> Result<u8, SendError> {
+        if !self.can_send() {
+            return Err(SendError::WindowFull);
+        }
+        let seq = self.next;
+        let slot = &mut self.slots[self.slot(seq)];
+        slot.len = DefaultFormat::to_slice(msg, &mut slot.buf).map_err(SendError::Serialize)?;
+        slot.sent_ms = now_ms;
+        slot.written = false;
+        slot.retransmitted = false;
+        self.next = seq.wrapping_add(1);
+        self.timer_ms.get_or_insert(now_ms);
+        Ok(seq)
+    }
+
+    /// Encodes the data frame `seq` into a COBS packet in `out_buf`, acknowledging the frames
+    /// received so far. Returns the sub-slice of `out_buf` that was allocated.
+    ///
+    /// `seq` must be in flight, i.e., returned by [Link::send] and not acknowledged yet.
+    ///
+    /// # Errors
+    ///
+    /// * [SerializeError::BufferTooSmall] if `out_buf` is shorter than [max_frame_len] of the
+    ///   message
+    pub fn frame<'a>(
+        &mut self,
+        seq: u8,
+        out_buf: &'a mut [u8],
+    ) -> Result<&'a mut [u8], SerializeError> {
+        debug_assert!((seq.wrapping_sub(self.base) as usize) < self.in_flight());
+        self.ack_pending = false;
+        let header = Header {
+            kind: Kind::Data,
+            seq,
+            ack: self.expected,
+        };
+        let slot = &mut self.slots[self.slot(seq)];
+        let frame = write_frame(header, &slot.buf[..slot.len], out_buf)?;
+        let retransmission = slot.written;
+        slot.retransmitted |= retransmission;
+        slot.written = true;
+        self.monitor.sent(retransmission);
+        Ok(frame)
+    }
+
+    /// Encodes a frame that only acknowledges the frames received so far into `out_buf`. The
+    /// frame is a [Kind::Nak] if a frame went missing since the latest one.
+    pub fn ack_frame<'a>(&mut self, out_buf: &'a mut [u8]) -> Result<&'a mut [u8], SerializeError> {
+        let kind = if self.nak_pending {
+            Kind::Nak
+        } else {
+            Kind::Ack
+        };
+        self.ack_pending = false;
+        self.nak_pending = false;
+        let header = Header {
+            kind,
+            seq: if kind == Kind::Nak { self.expected } else { 0 },
+            ack: self.expected,
+        };
+        write_frame(header, &[], out_buf)
+    }
+
+    /// Counts a frame that failed to [unpack] and asks the peer for the frame expected next, as
+    /// it may have been that frame. Does not ask if the frame has been asked for already or NAKs
+    /// are disabled.
+    pub fn reject(&mut self) {
+        self.monitor.received(true);
+        self.ask_again();
+    }
+
+    /// Asks the peer for the frame expected next, unless asked already
+    fn ask_again(&mut self) {
+        if self.config.nak && !self.nak_sent {
+            self.nak_pending = true;
+            self.nak_sent = true;
+        }
+    }
+
+    /// Starts a session: resets the link and encodes a [Kind::Sync] frame into `out_buf`. The
+    /// session has started once the peer acknowledges.
+    pub fn sync_frame<'a>(
+        &mut self,
+        out_buf: &'a mut [u8],
+    ) -> Result<&'a mut [u8], SerializeError> {
+        self.reset();
+        let header = Header {
+            kind: Kind::Sync,
+            seq: 0,
+            ack: 0,
+        };
+        write_frame(header, &[], out_buf)
+    }
+
+    /// Handles a link frame that arrived at `now_ms`. Returns the serialized message of a data
+    /// frame that is next in order, which is delivered only once.
+    ///
+    /// Data frames out of order are dropped; the peer sends them again after the frames missing
+    /// before them. A data frame ahead of the one expected is answered with a [Kind::Nak].
+    pub fn receive<'a>(
+        &mut self,
+        header: Header,
+        payload: &'a [u8],
+        now_ms: u64,
+    ) -> Option<&'a [u8]> {
+        self.monitor.received(false);
+        if header.kind == Kind::Sync {
+            self.reset();
+            self.ack_pending = true;
+            return None;
+        }
+        self.acknowledged(header.ack, now_ms);
+        match header.kind {
+            Kind::Data => {}
+            Kind::Nak => {
+                // Only the oldest frame in flight can be missing, a NAK for any other frame is
+                // stale
+                self.resend |= header.seq == self.base && !self.is_idle();
+                return None;
+            }
+            Kind::Ack | Kind::Sync => return None,
+        }
+        // Acknowledge duplicates too, as the acknowledgement of the original may have been lost
+        self.ack_pending = true;
+        let ahead = header.seq.wrapping_sub(self.expected) as usize;
+        if ahead != 0 {
+            if ahead < MAX_WINDOW {
+                // The frames before it went missing
+                self.ask_again();
+            }
+            return None;
+        }
+        self.expected = self.expected.wrapping_add(1);
+        self.nak_sent = false;
+        Some(payload)
+    }
+
+    /// Checks the retransmission timer at `now_ms`. Returns `true` if it has expired or the peer
+    /// has asked for the frames with a [Kind::Nak], in which case the caller must send every
+    /// [Link::unacked] frame again. The timer restarts.
+    pub fn poll(&mut self, now_ms: u64) -> bool {
+        let expired = self.deadline().is_some_and(|deadline| now_ms >= deadline);
+        let resend = expired || self.resend;
+        self.resend = false;
+        if !resend || self.is_idle() {
+            return false;
+        }
+        if expired {
+            self.monitor.timed_out();
+        }
+        self.timer_ms = Some(now_ms);
+        true
+    }
+
+    /// Sequence numbers of the data frames in flight, oldest first
+    pub fn unacked(&self) -> impl Iterator<Item = u8> + use<W, N> {
+        let base = self.base;
+        (0..self.in_flight()).map(move |i| base.wrapping_add(i as u8))
+    }
+
+    /// Slides the window past the frames before `ack`
+    fn acknowledged(&mut self, ack: u8, now_ms: u64) {
+        let count = ack.wrapping_sub(self.base) as usize;
+        // Stale and duplicate acknowledgements acknowledge nothing new
+        if count == 0 || count > self.in_flight() {
+            return;
+        }
+        // The newest frame acknowledged was just received by the peer, unless it was sent again
+        let newest = &self.slots[self.slot(ack.wrapping_sub(1))];
+        if newest.retransmitted {
+            self.monitor.progress();
+        } else {
+            self.monitor
+                .round_trip(now_ms.saturating_sub(newest.sent_ms));
+        }
+        self.base = ack;
+        self.head = (self.head + count) % W;
+        // The timer covers the oldest frame still in flight
+        self.timer_ms = if self.is_idle() { None } else { Some(now_ms) };
+    }
+
+    fn slot(&self, seq: u8) -> usize {
+        (self.head + seq.wrapping_sub(self.base) as usize) % W
+    }
+}
+
+/// Encodes the header and the serialized message into a COBS packet in place in `out_buf`
+fn write_frame<'a>(
+    header: Header,
+    payload: &[u8],
+    out_buf: &'a mut [u8],
+) -> Result<&'a mut [u8], SerializeError> {
+    let len = HEADER_LEN + payload.len();
+    if max_encoded_len(len) > out_buf.len() {
+        return Err(SerializeError::BufferTooSmall);
+    }
+    let start = cobs::start(len);
+    let fields = [LINK_TAG, header.kind as u8, header.seq, header.ack];
+    let crc = checksum(&fields, payload).to_le_bytes();
+    out_buf[start..start + HEADER_LEN]
+        .copy_from_slice(&[fields[0], fields[1], fields[2], fields[3], crc[0], crc[1]]);
+    out_buf[start + HEADER_LEN..start + len].copy_from_slice(payload);
+    let n = cobs::encode_in_place(out_buf, start, len);
+    Ok(&mut out_buf[0..n])
+}
+
+/// CRC of the fields of the header before it and the serialized message
+fn checksum(fields: &[u8; 4], payload: &[u8]) -> u16 {
+    let mut crc = Crc::new(&CRC_16_CCITT_FALSE);
+    crc.update(fields);
+    crc.update(payload);
+    crc.finish() as u16
+}
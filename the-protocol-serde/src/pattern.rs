@@ -0,0 +1,257 @@
+//! Blink patterns of the led and their interpretation into timed steps
+//!
+//! A [BlinkPattern] is played as a sequence of [BlinkStep]s, each of which holds the led on or off
+//! for a number of milliseconds. The device plays the steps back to back from a timer, so the
+//! pattern keeps its timing regardless of how long it runs.
+use serde::{Deserialize, Serialize};
+
+/// Maximum number of characters in a [MorseText]
+pub const MAX_MORSE_LEN: usize = 16;
+
+/// Led pattern set with [crate::CommandV2::SetBlinkPattern]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct BlinkPattern {
+    /// One cycle of the pattern
+    pub shape: BlinkShape,
+    /// Number of times the cycle is played, `None` to play it until the pattern is replaced. The
+    /// led is turned off after the last cycle.
+    pub repetitions: Option<u16>,
+}
+
+/// One cycle of a [BlinkPattern]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum BlinkShape {
+    /// On for `on_ms`, then off for `off_ms`
+    Duty {
+        /// Time on in milliseconds
+        on_ms: u32,
+        /// Time off in milliseconds
+        off_ms: u32,
+    },
+    /// `flashes` flashes of `on_ms` that are `off_ms` apart, followed by a pause of `pause_ms`
+    Burst {
+        /// Number of flashes
+        flashes: u8,
+        /// Time on of each flash in milliseconds
+        on_ms: u32,
+        /// Time off between flashes in milliseconds
+        off_ms: u32,
+        /// Time off after the last flash in milliseconds
+        pause_ms: u32,
+    },
+    /// The text in Morse code, followed by a word gap
+    ///
+    /// A dot lasts one unit and a dash three units. Symbols are one unit apart, characters three
+    /// units and words seven units.
+    Morse {
+        /// Length of a dot in milliseconds
+        unit_ms: u32,
+        /// Text to signal
+        text: MorseText,
+    },
+}
+
+/// Text of a [BlinkShape::Morse] pattern: letters, digits and spaces
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct MorseText {
+    pub(crate) len: u8,
+    pub(crate) chars: [u8; MAX_MORSE_LEN],
+}
+
+/// State of the led for a period of time
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BlinkStep {
+    /// Whether the led is on
+    pub on: bool,
+    /// Duration of the step in milliseconds
+    pub ms: u32,
+}
+
+impl BlinkPattern {
+    /// Plays `shape` until the pattern is replaced
+    pub fn forever(shape: BlinkShape) -> Self {
+        Self {
+            shape,
+            repetitions: None,
+        }
+    }
+
+    /// Whether the pattern can be played, i.e., every step has a duration and there is at least
+    /// one cycle to play
+    pub fn is_valid(&self) -> bool {
+        self.repetitions != Some(0) && self.shape.is_valid()
+    }
+
+    /// Returns the steps of the pattern in order
+    pub fn steps(&self) -> BlinkSteps {
+        BlinkSteps {
+            pattern: *self,
+            cycles: 0,
+            pos: 0,
+        }
+    }
+}
+
+impl BlinkShape {
+    /// Whether every step of the shape has a duration and there is at least one step
+    pub fn is_valid(&self) -> bool {
+        match self {
+            BlinkShape::Duty { on_ms, off_ms } => *on_ms > 0 && *off_ms > 0,
+            BlinkShape::Burst {
+                flashes,
+                on_ms,
+                off_ms,
+                pause_ms,
+            } => *flashes > 0 && *on_ms > 0 && (*flashes == 1 || *off_ms > 0) && *pause_ms > 0,
+            BlinkShape::Morse { unit_ms, text } => *unit_ms > 0 && text.is_valid(),
+        }
+    }
+
+    /// Returns the step at `pos` of one cycle, or `None` past the end of the cycle
+    fn step(&self, pos: usize) -> Option<BlinkStep> {
+        let (on, ms) = match *self {
+            BlinkShape::Duty { on_ms, off_ms } => match pos {
+                0 => (true, on_ms),
+                1 => (false, off_ms),
+                _ => return None,
+            },
+            BlinkShape::Burst {
+                flashes,
+                on_ms,
+                off_ms,
+                pause_ms,
+            } => {
+                let last = 2 * flashes as usize - 1;
+                match pos {
+                    _ if pos > last => return None,
+                    _ if pos == last => (false, pause_ms),
+                    _ if pos.is_multiple_of(2) => (true, on_ms),
+                    _ => (false, off_ms),
+                }
+            }
+            BlinkShape::Morse { unit_ms, text } => {
+                let (on, units) = text.step(pos)?;
+                (on, unit_ms.saturating_mul(units))
+            }
+        };
+        Some(BlinkStep { on, ms })
+    }
+}
+
+impl MorseText {
+    /// Returns the text if it fits and consists of letters, digits and spaces only
+    pub fn new(text: &str) -> Option<Self> {
+        if text.len() > MAX_MORSE_LEN {
+            return None;
+        }
+        let mut chars = [0; MAX_MORSE_LEN];
+        chars[..text.len()].copy_from_slice(text.as_bytes());
+        let text = Self {
+            len: text.len() as u8,
+            chars,
+        };
+        text.is_valid().then_some(text)
+    }
+
+    /// Characters of the text
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.chars[..(self.len as usize).min(MAX_MORSE_LEN)]
+    }
+
+    /// Whether the text has at least one character to signal and no characters without a code
+    fn is_valid(&self) -> bool {
+        let chars = self.as_bytes();
+        chars.iter().any(|c| *c != b' ')
+            && chars.iter().all(|c| *c == b' ' || morse_code(*c).is_some())
+    }
+
+    /// Returns whether the led is on and the duration in units of the step at `pos`
+    ///
+    /// Each symbol is a step on followed by a step off. The step off after the last symbol of a
+    /// character is the gap to the next character, or a word gap before a space and at the end.
+    fn step(&self, pos: usize) -> Option<(bool, u32)> {
+        let chars = self.as_bytes();
+        let mut n = 0;
+        for (i, c) in chars.iter().enumerate() {
+            let Some(code) = morse_code(*c) else {
+                // Spaces lengthen the gap after the preceding character instead
+                continue;
+            };
+            for (j, symbol) in code.bytes().enumerate() {
+                if pos == n {
+                    return Some((true, if symbol == b'-' { 3 } else { 1 }));
+                }
+                if pos == n + 1 {
+                    let gap = if j + 1 < code.len() {
+                        1
+                    } else if chars.get(i + 1).is_none_or(|next| *next == b' ') {
+                        7
+                    } else {
+                        3
+                    };
+                    return Some((false, gap));
+                }
+                n += 2;
+            }
+        }
+        None
+    }
+}
+
+/// Returns the dots and dashes of a letter or a digit
+fn morse_code(c: u8) -> Option<&'static str> {
+    const LETTERS: [&str; 26] = [
+        ".-", "-...", "-.-.", "-..", ".", "..-.", "--.", "....", "..", ".---", "-.-", ".-..", "--",
+        "-.", "---", ".--.", "--.-", ".-.", "...", "-", "..-", "...-", ".--", "-..-", "-.--",
+        "--..",
+    ];
+    const DIGITS: [&str; 10] = [
+        "-----", ".----", "..---", "...--", "....-", ".....", "-....", "--...", "---..", "----.",
+    ];
+    match c.to_ascii_uppercase() {
+        c @ b'A'..=b'Z' => Some(LETTERS[(c - b'A') as usize]),
+        c @ b'0'..=b'9' => Some(DIGITS[(c - b'0') as usize]),
+        _ => None,
+    }
+}
+
+/// Iterator over the steps of a [BlinkPattern], see [BlinkPattern::steps]
+///
+/// Ends after the last repetition, or never if the pattern repeats until replaced. An invalid
+/// pattern has no steps.
+#[derive(Clone, Debug)]
+pub struct BlinkSteps {
+    pattern: BlinkPattern,
+    /// Number of cycles played to the end
+    cycles: u16,
+    /// Position of the next step within the cycle
+    pos: usize,
+}
+
+impl Iterator for BlinkSteps {
+    type Item = BlinkStep;
+
+    fn next(&mut self) -> Option<BlinkStep> {
+        if !self.pattern.is_valid() {
+            return None;
+        }
+        loop {
+            if let Some(repetitions) = self.pattern.repetitions
+                && self.cycles >= repetitions
+            {
+                return None;
+            }
+            match self.pattern.shape.step(self.pos) {
+                Some(step) => {
+                    self.pos += 1;
+                    return Some(step);
+                }
+                None => {
+                    // A valid shape has at least one step, so this does not loop forever
+                    self.cycles = self.cycles.saturating_add(1);
+                    self.pos = 0;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! Aggregation of received bytes into COBS frames
+//!
+//! The receiver does not read a clock itself. The caller passes the time of arrival of each byte,
+//! which allows driving the receiver from a hardware timer on the device and from plain numbers
+//! in tests.
+use corncobs::ZERO;
+
+/// Result of pushing a byte into a [FrameReceiver]
+#[derive(Debug, PartialEq)]
+pub enum Received<'a> {
+    /// The byte was buffered, or dropped with the rest of a frame that overflowed. No frame is
+    /// complete yet.
+    Pending,
+    /// The byte terminated a frame. Contains the frame including the termination byte.
+    Frame(&'a mut [u8]),
+    /// The frame did not fit in the buffer and is discarded up to and including its termination
+    /// byte. Returned once per frame.
+    Overflow,
+}
+
+/// Buffers bytes until a termination byte completes a frame
+///
+/// A partial frame is discarded when no byte has been received for the idle timeout, so that the
+/// bytes left behind by a host that stopped mid-frame are not prepended to the next frame. A frame
+/// that does not fit is discarded as a whole, so that its tail is not taken for another frame.
+///
+/// # Type arguments
+///
+/// * `N` - Maximum length of a frame in bytes, including the termination byte
+pub struct FrameReceiver<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+    /// Whether the bytes up to the next termination byte belong to a frame that overflowed
+    discarding: bool,
+    /// Time of arrival of the latest byte in milliseconds
+    last_byte_ms: u64,
+    idle_timeout_ms: u64,
+}
+
+impl<const N: usize> FrameReceiver<N> {
+    /// Creates a receiver that discards partial frames after `idle_timeout_ms` milliseconds
+    /// without received bytes
+    pub const fn new(idle_timeout_ms: u64) -> Self {
+        Self {
+            buf: [0; N],
+            len: 0,
+            discarding: false,
+            last_byte_ms: 0,
+            idle_timeout_ms,
+        }
+    }
+
+    /// Changes the idle timeout. Applies to the partial frame that is currently buffered too.
+    pub fn set_idle_timeout(&mut self, idle_timeout_ms: u64) {
+        self.idle_timeout_ms = idle_timeout_ms;
+    }
+
+    /// Number of bytes in the partial frame
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether there is no partial frame
+    pub fn is_empty(&self) -> bool {
+        self.len == 0 && !self.discarding
+    }
+
+    /// Discards the partial frame if no byte has been received for the idle timeout. Returns
+    /// `true` if a partial frame was discarded. The rest of a frame that overflowed is no longer
+    /// waited for either.
+    ///
+    /// Should be called with the time of arrival before each call to [FrameReceiver::push].
+    pub fn expire(&mut self, now_ms: u64) -> bool {
+        let idle = now_ms.saturating_sub(self.last_byte_ms) >= self.idle_timeout_ms;
+        if idle {
+            self.discarding = false;
+        }
+        let stale = idle && self.len > 0;
+        if stale {
+            self.len = 0;
+        }
+        stale
+    }
+
+    /// Buffers a byte that arrived at `now_ms`
+    pub fn push(&mut self, byte: u8, now_ms: u64) -> Received<'_> {
+        self.last_byte_ms = now_ms;
+        if self.discarding {
+            self.discarding = byte != ZERO;
+            return Received::Pending;
+        }
+        if self.len == N {
+            self.len = 0;
+            // Unless the frame ends right here, drop the rest of it too
+            self.discarding = byte != ZERO;
+            return Received::Overflow;
+        }
+        self.buf[self.len] = byte;
+        self.len += 1;
+        if byte == ZERO {
+            let len = self.len;
+            self.len = 0;
+            Received::Frame(&mut self.buf[..len])
+        } else {
+            Received::Pending
+        }
+    }
+}
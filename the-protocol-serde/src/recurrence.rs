@@ -0,0 +1,120 @@
+//! Recurrence rules for jobs that fire more than once
+//!
+//! Every occurrence is computed from the anchor of the rule (the start time or the time of day)
+//! rather than from the previous occurrence, so that the delay in handling one occurrence does not
+//! accumulate into the next ones. Occurrences have a resolution of one second.
+use serde::{Deserialize, Serialize};
+use the_protocol::{
+    SDateTime,
+    chrono::{DateTime, Utc},
+};
+
+use crate::to_utc;
+
+const SECS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// Rule for when a recurring job fires
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub enum Recurrence {
+    /// Fires every `period_s` seconds, starting at `start`
+    Periodic {
+        /// Time of the first occurrence
+        start: SDateTime,
+        /// Time between occurrences in seconds
+        period_s: u32,
+    },
+    /// Fires every day at the specified time of day in UTC
+    Daily {
+        /// Hour of the day, 0--23
+        hour: u8,
+        /// Minute of the hour, 0--59
+        minute: u8,
+    },
+    /// Fires `count` times, `interval_s` seconds apart, starting at `start`
+    Repeat {
+        /// Time of the first occurrence
+        start: SDateTime,
+        /// Time between occurrences in seconds
+        interval_s: u32,
+        /// Total number of occurrences
+        count: u32,
+    },
+}
+
+impl Recurrence {
+    /// Whether the rule can produce occurrences at all, e.g., the period is not zero and the start
+    /// and the time of day exist
+    pub fn is_valid(&self) -> bool {
+        match self {
+            Recurrence::Periodic { start, period_s } => *period_s > 0 && to_secs(start).is_some(),
+            Recurrence::Daily { hour, minute } => *hour < 24 && *minute < 60,
+            Recurrence::Repeat {
+                start,
+                interval_s,
+                count,
+            } => *interval_s > 0 && *count > 0 && to_secs(start).is_some(),
+        }
+    }
+
+    /// Returns the first occurrence at or after `now`, or `None` if there is none
+    pub fn first(&self, now: &SDateTime) -> Option<SDateTime> {
+        self.at_or_after(to_secs(now)?).and_then(from_secs)
+    }
+
+    /// Returns the first occurrence strictly after `t`, or `None` if the recurrence has ended
+    ///
+    /// Occurrences that were missed between the previous occurrence and `t` are skipped rather
+    /// than fired in a burst.
+    pub fn after(&self, t: &SDateTime) -> Option<SDateTime> {
+        self.at_or_after(to_secs(t)? + 1).and_then(from_secs)
+    }
+
+    /// Returns the first occurrence at or after `t` in seconds since the Unix epoch
+    fn at_or_after(&self, t: i64) -> Option<i64> {
+        if !self.is_valid() {
+            return None;
+        }
+        match self {
+            Recurrence::Periodic { start, period_s } => {
+                let (start, _) = nth_at_or_after(to_secs(start)?, *period_s, t);
+                Some(start)
+            }
+            Recurrence::Daily { hour, minute } => {
+                let day_start = t - t.rem_euclid(SECS_PER_DAY);
+                let occurrence = day_start + *hour as i64 * 3600 + *minute as i64 * 60;
+                if occurrence < t {
+                    Some(occurrence + SECS_PER_DAY)
+                } else {
+                    Some(occurrence)
+                }
+            }
+            Recurrence::Repeat {
+                start,
+                interval_s,
+                count,
+            } => {
+                let (occurrence, n) = nth_at_or_after(to_secs(start)?, *interval_s, t);
+                (n < *count as i64).then_some(occurrence)
+            }
+        }
+    }
+}
+
+/// Returns the first point `start + n * period` that is at or after `t`, and its index `n`
+fn nth_at_or_after(start: i64, period: u32, t: i64) -> (i64, i64) {
+    if t <= start {
+        return (start, 0);
+    }
+    let period = period as i64;
+    let n = (t - start + period - 1) / period;
+    (start + n * period, n)
+}
+
+/// Seconds since the Unix epoch, or `None` if the date-time does not exist on the calendar
+fn to_secs(dt: &SDateTime) -> Option<i64> {
+    to_utc(dt).map(|dt| dt.timestamp())
+}
+
+fn from_secs(secs: i64) -> Option<SDateTime> {
+    DateTime::<Utc>::from_timestamp(secs, 0).map(SDateTime::from)
+}
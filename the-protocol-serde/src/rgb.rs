@@ -0,0 +1,69 @@
+//! Colours and animations of the RGB led
+use serde::{Deserialize, Serialize};
+
+/// Colour of the RGB led
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum Color {
+    /// Red, green and blue components, 0--255 each
+    Rgb {
+        /// Red component
+        r: u8,
+        /// Green component
+        g: u8,
+        /// Blue component
+        b: u8,
+    },
+    /// Hue, saturation and value, 0--255 each. A hue of 0 is red, 85 green and 170 blue.
+    Hsv {
+        /// Hue
+        h: u8,
+        /// Saturation
+        s: u8,
+        /// Value
+        v: u8,
+    },
+}
+
+/// Animation of the RGB led set with [crate::CommandV2::SetRgbEffect]
+///
+/// The effect applies to every led of the chain on the device. The brightness set with
+/// [crate::CommandV2::SetRgbBrightness] scales every effect.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub enum RgbEffect {
+    /// The led is off
+    Off,
+    /// Shows a colour
+    Static(Color),
+    /// Fades the colour in and out, taking `period_ms` for one breath
+    Breathe {
+        /// Colour at the top of the breath
+        color: Color,
+        /// Duration of one breath in milliseconds
+        period_ms: u32,
+    },
+    /// Cycles through the hues, taking `period_ms` for a full cycle. The hues are spread along
+    /// the chain of leds.
+    Rainbow {
+        /// Duration of one cycle in milliseconds
+        period_ms: u32,
+    },
+    /// Fades from the current colour to `to` over `duration_ms`, then shows `to`
+    Fade {
+        /// Colour at the end of the fade
+        to: Color,
+        /// Duration of the fade in milliseconds
+        duration_ms: u32,
+    },
+}
+
+impl RgbEffect {
+    /// Whether the effect can be rendered, i.e., an animation has a period
+    pub fn is_valid(&self) -> bool {
+        match self {
+            RgbEffect::Breathe { period_ms, .. } | RgbEffect::Rainbow { period_ms } => {
+                *period_ms > 0
+            }
+            RgbEffect::Off | RgbEffect::Static(_) | RgbEffect::Fade { .. } => true,
+        }
+    }
+}
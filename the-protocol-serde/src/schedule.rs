@@ -0,0 +1,51 @@
+//! Types for inspecting and managing the device schedule
+use serde::{Deserialize, Serialize};
+use the_protocol::{Funct, SDateTime};
+
+use crate::Recurrence;
+
+/// Identifier of a job in the device schedule
+///
+/// Assigned by the device when the job is scheduled. Identifiers are not reused until the
+/// identifier space wraps around.
+pub type JobId = u32;
+
+/// Number of jobs returned per [crate::CommandV2::ListSchedule] page
+pub const SCHEDULE_PAGE_LEN: usize = 4;
+
+/// A job in the device schedule
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ScheduledJob {
+    /// Identifier of the job, used for cancelling the job
+    pub id: JobId,
+    /// Functionality that is triggered when the job fires
+    pub funct: Funct,
+    /// Time at which the job fires next
+    pub at: SDateTime,
+    /// Rule for rescheduling the job after it fires, `None` for a job that fires once
+    pub recurrence: Option<Recurrence>,
+}
+
+/// One page of the device schedule as returned by [crate::CommandV2::ListSchedule]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct SchedulePage {
+    /// Index of this page
+    pub page: u8,
+    /// Total number of pages in the schedule at the time of listing
+    pub page_count: u8,
+    /// Jobs on this page. Unused slots at the end of the page are `None`.
+    pub jobs: [Option<ScheduledJob>; SCHEDULE_PAGE_LEN],
+}
+
+impl SchedulePage {
+    /// Iterates over the jobs on this page
+    pub fn iter(&self) -> impl Iterator<Item = &ScheduledJob> {
+        self.jobs.iter().flatten()
+    }
+
+    /// Whether this is the last page of the schedule
+    pub fn is_last(&self) -> bool {
+        // The page comes off the wire, so it may be `u8::MAX`
+        self.page.saturating_add(1) >= self.page_count
+    }
+}
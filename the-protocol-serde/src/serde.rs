@@ -1,28 +1,45 @@
 use crate::Codec;
+use crate::format::{self, DefaultFormat};
 use corncobs::max_encoded_len;
 
+/// Error of [Codec::serialize]
 #[derive(Debug)]
 pub enum SerializeError {
-    // TODO: you may add more error variants here
+    /// The output buffer is too small for the encoded message
+    BufferTooSmall,
+    /// The message contains a value that the serializer backend cannot encode
+    Unsupported,
 }
 
+/// Error of [Codec::deserialize_in_place]
 #[derive(Debug)]
 pub enum DeserializeError {
-    // TODO: you may add more error variants here
+    /// The bytes are not a valid COBS packet
+    Cobs,
+    /// The packet does not carry a message of the expected type, e.g., it was truncated or has an
+    /// unknown variant
+    Malformed,
+    /// The link frame fails its checksum, i.e., it was damaged on the line, see `link`
+    Corrupted,
+    /// The frame is not authenticated or encrypted, fails verification or is a replay, see `auth`
+    /// and `encryption`
+    Unauthenticated,
 }
 
 // Blanket implementation of Codec for all types `P` that implement `serde::{Deserialize, Serialize}`
+#[cfg(not(feature = "postcard"))]
 impl<P> Codec<P> for P
 where
-    // `P` must be both serializable and deserializable to form a valid payload
-    P: for<'de> serde::Deserialize<'de> + serde::Serialize,
+    // `P` must be both serializable and deserializable to form a valid payload, and have a bounded
+    // size on the wire
+    P: for<'de> serde::Deserialize<'de> + serde::Serialize + crate::WireSize,
 {
     type DeserializeError = DeserializeError;
     type SerializeError = SerializeError;
 
-    /// Maximum serialized len. We will leave one word extra for implementations to play with (e.g.,
-    /// if an implementation wants to include the framing zero on the decode side.)
-    const MAX_SERIALIZED_LEN: usize = max_encoded_len(size_of::<P>() + size_of::<u32>());
+    /// Maximum serialized len: the COBS packet of the largest message of type `P`, including the
+    /// framing zero. Every message fits a buffer of this size by construction.
+    const MAX_SERIALIZED_LEN: usize = max_encoded_len(P::WIRE_SIZE);
 
     /// Serialize an instance of type `T` into a COBS packet. Returns the sub-slice of `out_buf`
     /// that was allocated.
@@ -36,23 +53,11 @@ where
     /// * `'a` - lifetime of `out_buf` which bounds also the lifetime of return value which is a view to `out_buf`
     /// * `N` - buffer size for the output packet, i.e., maximum size after serialization and anything
     ///   else you might want to include in the packet. This should generally match `MAX_SERIALIZED_LEN`.
-    ///
-    /// # Errors
-    ///
-    /// * panics on all errors
-    ///
-    /// TODO: can we do better?
     fn serialize<'a, const N: usize>(
         &self,
         out_buf: &'a mut [u8; N],
     ) -> Result<&'a mut [u8], Self::SerializeError> {
-        // Serialize the value
-        let n_ser = ssmarshal::serialize(out_buf, self).unwrap();
-        // memcpy
-        let buf_copy = *out_buf;
-        // Encode the whole message into a COBS packet
-        let n = corncobs::encode_buf(&buf_copy[0..n_ser], out_buf);
-        Ok(&mut out_buf[0..n])
+        format::encode::<DefaultFormat, _, N>(self, out_buf)
     }
 
     /// Deserialize an instance of type `T` from a COBS packet
@@ -60,15 +65,37 @@ where
     /// # Arguments
     ///
     /// * `in_buf` - the bytes of a COBS packet
-    ///
-    /// # Errors
-    ///
-    /// * panics on all errors
-    ///
-    /// TODO: can we do better?
     fn deserialize_in_place(in_buf: &mut [u8]) -> Result<Self, Self::DeserializeError> {
-        let n = corncobs::decode_in_place(in_buf).unwrap();
-        let (t, _bytes_used) = ssmarshal::deserialize(&in_buf[0..n]).unwrap();
-        Ok(t)
+        format::decode_in_place::<DefaultFormat, _>(in_buf)
+    }
+}
+
+// With the postcard backend, the maximum length is bounded by [crate::MaxSize] instead
+#[cfg(feature = "postcard")]
+impl<P> Codec<P> for P
+where
+    // `P` must be both serializable and deserializable to form a valid payload, and have a bounded
+    // size on the wire
+    P: for<'de> serde::Deserialize<'de> + serde::Serialize + crate::MaxSize,
+{
+    type DeserializeError = DeserializeError;
+    type SerializeError = SerializeError;
+
+    /// Maximum serialized len: the COBS packet of the largest message of type `P`, including the
+    /// framing zero. Every message fits a buffer of this size by construction.
+    const MAX_SERIALIZED_LEN: usize = max_encoded_len(P::POSTCARD_MAX_SIZE);
+
+    /// Serialize an instance of type `T` into a COBS packet. Returns the sub-slice of `out_buf`
+    /// that was allocated.
+    fn serialize<'a, const N: usize>(
+        &self,
+        out_buf: &'a mut [u8; N],
+    ) -> Result<&'a mut [u8], Self::SerializeError> {
+        format::encode::<DefaultFormat, _, N>(self, out_buf)
+    }
+
+    /// Deserialize an instance of type `T` from a COBS packet
+    fn deserialize_in_place(in_buf: &mut [u8]) -> Result<Self, Self::DeserializeError> {
+        format::decode_in_place::<DefaultFormat, _>(in_buf)
     }
 }
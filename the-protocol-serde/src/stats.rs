@@ -0,0 +1,226 @@
+//! Rolling statistics of the quality of a link
+//!
+//! Each end of a link keeps a [LinkMonitor]. It estimates the round-trip time and derives the
+//! retransmission timeout from it the way TCP does (RFC 6298), so that a slow line does not
+//! retransmit too early and a fast line does not wait too long for lost frames. It also keeps
+//! moving averages of the share of frames received corrupted and of the share of data frames that
+//! were retransmissions, which weigh the most recent frames the most. The device returns the
+//! statistics of its end as [LinkStats] for [crate::CommandV2::LinkStats].
+use serde::{Deserialize, Serialize};
+
+/// Statistics of one end of a link, see [LinkMonitor]
+///
+/// Counters wrap around.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+pub struct LinkStats {
+    /// Number of data frames sent, including retransmissions
+    pub frames_sent: u32,
+    /// Number of data frames sent again
+    pub retransmissions: u32,
+    /// Number of frames received, including corrupted frames
+    pub frames_received: u32,
+    /// Number of frames received corrupted
+    pub frame_errors: u32,
+    /// Share of the recent frames received that were corrupted, in per mille
+    pub frame_error_rate: u16,
+    /// Share of the recent data frames sent that were retransmissions, in per mille
+    pub retransmission_rate: u16,
+    /// Smoothed round-trip time in milliseconds, zero until measured
+    pub srtt_ms: u32,
+    /// Variation of the round-trip time in milliseconds, zero until measured
+    pub rttvar_ms: u32,
+    /// Current retransmission timeout in milliseconds
+    pub rto_ms: u32,
+}
+
+/// Estimator of the round-trip time and the retransmission timeout (RFC 6298)
+#[derive(Clone, Debug)]
+pub struct RttEstimator {
+    /// Smoothed round-trip time and its variation in microseconds, `None` until the first sample
+    smoothed_us: Option<(u64, u64)>,
+    /// Timeout derived from the estimate
+    rto_ms: u64,
+    /// Number of times the timeout has doubled since the peer last made progress
+    backoffs: u32,
+    min_rto_ms: u64,
+    max_rto_ms: u64,
+}
+
+impl RttEstimator {
+    /// Creates an estimator with the timeout `initial_rto_ms` until the first sample. The timeout
+    /// stays between `min_rto_ms` and `max_rto_ms`.
+    pub const fn new(initial_rto_ms: u64, min_rto_ms: u64, max_rto_ms: u64) -> Self {
+        assert!(min_rto_ms <= max_rto_ms);
+        let rto_ms = if initial_rto_ms < min_rto_ms {
+            min_rto_ms
+        } else if initial_rto_ms > max_rto_ms {
+            max_rto_ms
+        } else {
+            initial_rto_ms
+        };
+        Self {
+            smoothed_us: None,
+            rto_ms,
+            backoffs: 0,
+            min_rto_ms,
+            max_rto_ms,
+        }
+    }
+
+    /// Updates the estimate with the round-trip time `rtt_ms` of a frame
+    ///
+    /// Only frames that were sent once may be sampled: the acknowledgement of a retransmitted
+    /// frame may be for any of its copies (Karn's algorithm).
+    pub fn sample(&mut self, rtt_ms: u64) {
+        let r = rtt_ms.saturating_mul(1000);
+        let (srtt, rttvar) = match self.smoothed_us {
+            None => (r, r / 2),
+            // The variation is updated with the previous smoothed time
+            Some((srtt, rttvar)) => (
+                srtt - srtt / 8 + r / 8,
+                rttvar - rttvar / 4 + srtt.abs_diff(r) / 4,
+            ),
+        };
+        self.smoothed_us = Some((srtt, rttvar));
+        // The variation term is at least the granularity of the clock, one millisecond
+        let rto_us = srtt.saturating_add(rttvar.saturating_mul(4).max(1000));
+        self.rto_ms = rto_us
+            .div_ceil(1000)
+            .clamp(self.min_rto_ms, self.max_rto_ms);
+        self.backoffs = 0;
+    }
+
+    /// Doubles the timeout after it expired, as the round trip may have grown beyond the estimate
+    pub fn backoff(&mut self) {
+        // Doubling a timeout of zero never reaches the longest timeout
+        if self.rto_ms() < self.max_rto_ms && self.backoffs < u64::BITS {
+            self.backoffs += 1;
+        }
+    }
+
+    /// Restores the timeout derived from the estimate, as the peer has acknowledged new frames
+    ///
+    /// Unlike TCP, which keeps the doubled timeout until a frame that was sent once is
+    /// acknowledged, the timeout does not stay doubled through a run of losses: Go-Back-N sends
+    /// every frame in flight again, so such frames are rare while frames are being lost.
+    pub fn progress(&mut self) {
+        self.backoffs = 0;
+    }
+
+    /// Current retransmission timeout in milliseconds
+    pub fn rto_ms(&self) -> u64 {
+        let factor = 1u64.checked_shl(self.backoffs).unwrap_or(u64::MAX);
+        self.rto_ms.saturating_mul(factor).min(self.max_rto_ms)
+    }
+
+    /// Smoothed round-trip time in milliseconds, `None` until the first sample
+    pub fn srtt_ms(&self) -> Option<u64> {
+        self.smoothed_us.map(|(srtt, _)| srtt / 1000)
+    }
+
+    /// Variation of the round-trip time in milliseconds, `None` until the first sample
+    pub fn rttvar_ms(&self) -> Option<u64> {
+        self.smoothed_us.map(|(_, rttvar)| rttvar / 1000)
+    }
+}
+
+/// Moving average of the share of events that were failures, in fixed point with 16 fractional
+/// bits
+#[derive(Clone, Copy, Debug)]
+struct Rate(u32);
+
+impl Rate {
+    const ONE: u32 = 1 << 16;
+    /// The latest event weighs 1/2^SHIFT, i.e., the average covers roughly the latest 32 events
+    const SHIFT: u32 = 5;
+
+    fn record(&mut self, failed: bool) {
+        let event = if failed { Self::ONE } else { 0 };
+        self.0 = self.0 - (self.0 >> Self::SHIFT) + (event >> Self::SHIFT);
+    }
+
+    fn per_mille(self) -> u16 {
+        ((self.0 as u64 * 1000 + (Self::ONE / 2) as u64) >> 16) as u16
+    }
+}
+
+/// Collects the [LinkStats] of one end of a link
+#[derive(Clone, Debug)]
+pub struct LinkMonitor {
+    frames_sent: u32,
+    retransmissions: u32,
+    frames_received: u32,
+    frame_errors: u32,
+    error_rate: Rate,
+    retransmission_rate: Rate,
+    rtt: RttEstimator,
+}
+
+impl LinkMonitor {
+    /// Creates a monitor that estimates the retransmission timeout with `rtt`
+    pub const fn new(rtt: RttEstimator) -> Self {
+        Self {
+            frames_sent: 0,
+            retransmissions: 0,
+            frames_received: 0,
+            frame_errors: 0,
+            error_rate: Rate(0),
+            retransmission_rate: Rate(0),
+            rtt,
+        }
+    }
+
+    /// Records a data frame sent, a `retransmission` if it was sent before
+    pub fn sent(&mut self, retransmission: bool) {
+        self.frames_sent = self.frames_sent.wrapping_add(1);
+        if retransmission {
+            self.retransmissions = self.retransmissions.wrapping_add(1);
+        }
+        self.retransmission_rate.record(retransmission);
+    }
+
+    /// Records a frame received, `corrupted` if it failed to decode
+    pub fn received(&mut self, corrupted: bool) {
+        self.frames_received = self.frames_received.wrapping_add(1);
+        if corrupted {
+            self.frame_errors = self.frame_errors.wrapping_add(1);
+        }
+        self.error_rate.record(corrupted);
+    }
+
+    /// Records the round-trip time of a frame that was sent once, see [RttEstimator::sample]
+    pub fn round_trip(&mut self, rtt_ms: u64) {
+        self.rtt.sample(rtt_ms);
+    }
+
+    /// Records that the retransmission timeout expired, see [RttEstimator::backoff]
+    pub fn timed_out(&mut self) {
+        self.rtt.backoff();
+    }
+
+    /// Records that the peer acknowledged new frames, see [RttEstimator::progress]
+    pub fn progress(&mut self) {
+        self.rtt.progress();
+    }
+
+    /// Current retransmission timeout in milliseconds
+    pub fn rto_ms(&self) -> u64 {
+        self.rtt.rto_ms()
+    }
+
+    /// Snapshot of the statistics
+    pub fn stats(&self) -> LinkStats {
+        let ms = |v: Option<u64>| v.unwrap_or(0).min(u32::MAX as u64) as u32;
+        LinkStats {
+            frames_sent: self.frames_sent,
+            retransmissions: self.retransmissions,
+            frames_received: self.frames_received,
+            frame_errors: self.frame_errors,
+            frame_error_rate: self.error_rate.per_mille(),
+            retransmission_rate: self.retransmission_rate.per_mille(),
+            srtt_ms: ms(self.rtt.srtt_ms()),
+            rttvar_ms: ms(self.rtt.rttvar_ms()),
+            rto_ms: ms(Some(self.rtt.rto_ms())),
+        }
+    }
+}
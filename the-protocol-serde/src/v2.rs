@@ -0,0 +1,472 @@
+//! Extended command set on top of the frozen [the_protocol] ABI
+//!
+//! [Command] and [Response] may not be changed, as the staff test software relies on their exact
+//! binary layout. [CommandV2] and [ResponseV2] are supersets of those types that carry the
+//! functionality this project adds on top. Every [Command] lifts into a [CommandV2] and every
+//! [Response] lifts into a [ResponseV2].
+//!
+//! On the wire, legacy and extended messages are told apart by the first byte of the serialized
+//! message. A legacy message starts with the variant index of the enum, which is always smaller
+//! than the number of variants of [Command] or [Response]. An extended message starts with
+//! [V2_TAG] followed by the serialized extended message. Use [Request] and [Reply] to decode a
+//! frame of either revision. The device may also send events, which are decoded together with
+//! replies as a [crate::DeviceMessage].
+use core::fmt;
+
+use serde::{
+    Deserialize, Deserializer, Serialize, Serializer,
+    de::{self, SeqAccess, Unexpected, Visitor},
+};
+use the_protocol::{Command, Funct, Payload, RejectReason, Response, SDateTime};
+
+use crate::{
+    Batch, BatchResponse, BlinkPattern, Diagnostics, JobId, Limits, LinkStats, Recurrence,
+    RgbEffect, SchedulePage,
+};
+
+/// Leading byte of an extended message
+///
+/// Never collides with the legacy encoding, where the first byte is the variant index of
+/// [Command] or [Response].
+pub const V2_TAG: u8 = 0xF2;
+
+/// Revision of the extended protocol, reported as part of [FirmwareInfo]
+pub const PROTOCOL_REVISION: u16 = 2;
+
+/// Extended set of commands supported by the device
+///
+/// The first variants mirror [Command] one-to-one.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+// A batch is much larger than the other commands, but there is no allocator on the device to box
+// it with
+#[allow(clippy::large_enum_variant)]
+pub enum CommandV2 {
+    /// Reset application and hardware to initial state
+    Reset,
+    /// Return the device-internal counter
+    Counter,
+    /// Sets or clears the current time for the device
+    SetDateTime(Option<SDateTime>),
+    /// Actuates specified functionality immediately
+    Immediate(Funct),
+    /// Schedules specified functionality ([Funct]) to start at specified time
+    ///
+    /// Returns the identifier of the scheduled job as [PayloadV2::JobId].
+    Schedule(Funct, SDateTime),
+    /// Schedules specified functionality ([Funct]) to start repeatedly according to the
+    /// [Recurrence]
+    ///
+    /// Returns the identifier of the scheduled job as [PayloadV2::JobId]. Rejected with
+    /// [RejectReason::IllegalCommand] if the recurrence has no future occurrences.
+    ScheduleRecurring(Funct, Recurrence),
+    /// Removes a job from the schedule
+    ///
+    /// Rejected with [RejectReason::IllegalCommand] if there is no job with the identifier.
+    CancelJob(JobId),
+    /// Removes all jobs from the schedule
+    ClearSchedule,
+    /// Return the specified page of the jobs that are currently scheduled as
+    /// [PayloadV2::SchedulePage]
+    ListSchedule {
+        /// Index of the page to return, starting from zero
+        page: u8,
+    },
+    /// Return the state of the led
+    LedState,
+    /// Return the current time of the device
+    DateTime,
+    /// Sets the device-internal counter to the specified value
+    SetCounter(u64),
+    /// Return information on the firmware running on the device
+    FirmwareInfo,
+    /// Return device health information as [PayloadV2::Diagnostics]
+    Diagnostics,
+    /// Executes the commands of the batch in order and returns their responses as
+    /// [PayloadV2::Batch]
+    ///
+    /// Each command is answered as if it were sent on its own with the legacy encoding.
+    Batch(Batch),
+    /// Enables or disables sending [crate::Event]s to the host. Events are disabled after boot.
+    SubscribeEvents(bool),
+    /// Plays a [BlinkPattern] on the led, replacing the current blink period or pattern
+    ///
+    /// [Funct::EnableBlink] and [Funct::DisableBlink] in turn replace the pattern. Rejected with
+    /// [RejectReason::IllegalCommand] if the pattern is not valid.
+    SetBlinkPattern(BlinkPattern),
+    /// Plays an [RgbEffect] on the RGB led, replacing the current effect or the time of day shown
+    /// after [Funct::EnableRgb]
+    ///
+    /// [Funct::EnableRgb] and [Funct::DisableRgb] in turn replace the effect. Rejected with
+    /// [RejectReason::IllegalCommand] if the effect is not valid.
+    SetRgbEffect(RgbEffect),
+    /// Sets the brightness of the RGB led, 0--255, which scales every effect
+    ///
+    /// Rejected with [RejectReason::IllegalCommand] if the brightness exceeds the maximum of the
+    /// device.
+    SetRgbBrightness(u8),
+    /// Return the ranges of command arguments accepted by the device as [PayloadV2::Limits]
+    Limits,
+    /// Return the statistics of the device end of the sliding-window transport as
+    /// [PayloadV2::LinkStats], see [crate::link]
+    LinkStats,
+    /// Switches the serial line to the baud rate after the reply, falling back to the current
+    /// rate unless a valid frame arrives at the new one in time, see [crate::BAUD_FALLBACK_MS]
+    ///
+    /// Rejected with [RejectReason::IllegalCommand] if the rate is outside of the [Limits] of the
+    /// device, or while the previous switch is yet to be confirmed.
+    SetBaudRate(u32),
+}
+
+impl From<Command> for CommandV2 {
+    fn from(cmd: Command) -> Self {
+        match cmd {
+            Command::Reset => CommandV2::Reset,
+            Command::Counter => CommandV2::Counter,
+            Command::SetDateTime(dt) => CommandV2::SetDateTime(dt),
+            Command::Immediate(f) => CommandV2::Immediate(f),
+            Command::Schedule(f, at) => CommandV2::Schedule(f, at),
+        }
+    }
+}
+
+/// Response returned by the device upon completing the processing of a [CommandV2]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[allow(clippy::large_enum_variant)]
+pub enum ResponseV2 {
+    /// The message was accepted and processed as received. `PayloadV2` is used for a
+    /// command-specific return value.
+    Ok(Option<PayloadV2>),
+    /// The message was not processed. [RejectReason] contains the reason for why the message was
+    /// not processed.
+    Rejected(RejectReason),
+    /// The frame was corrupted, but a message was recovered and processed
+    OkRecovered(Option<PayloadV2>, CommandV2),
+    /// The message was not processed, because the device was busy processing earlier messages.
+    /// The message may be sent again later.
+    ///
+    /// A legacy host receives [RejectReason::InternalError] instead.
+    Busy,
+    /// The frame of the message failed verification or was a replay, so the message was not
+    /// processed. The message may be sent again in a new frame. Only sent by a device that
+    /// authenticates frames, see the `auth` module.
+    ///
+    /// Always sent in the extended encoding, as the revision of the frame is unknown.
+    Unauthenticated,
+}
+
+impl ResponseV2 {
+    /// Whether the response is a positive one, as opposed to an error
+    pub fn is_ok(&self) -> bool {
+        match self {
+            ResponseV2::Ok(_) | ResponseV2::OkRecovered(..) => true,
+            ResponseV2::Rejected(_) | ResponseV2::Busy | ResponseV2::Unauthenticated => false,
+        }
+    }
+
+    /// Returns the payload for `Ok` type responses or `None` for `Rejected` response
+    pub fn payload(&self) -> Option<&PayloadV2> {
+        match self {
+            ResponseV2::Ok(payload) | ResponseV2::OkRecovered(payload, _) => payload.as_ref(),
+            ResponseV2::Rejected(_) | ResponseV2::Busy | ResponseV2::Unauthenticated => None,
+        }
+    }
+}
+
+impl From<Response> for ResponseV2 {
+    fn from(resp: Response) -> Self {
+        match resp {
+            Response::Ok(p) => ResponseV2::Ok(p.map(PayloadV2::from)),
+            Response::Rejected(reason) => ResponseV2::Rejected(reason),
+            Response::OkRecovered(p, cmd) => {
+                ResponseV2::OkRecovered(p.map(PayloadV2::from), cmd.into())
+            }
+        }
+    }
+}
+
+/// Payload is used for a command-specific return value
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+// A schedule page or a batch is much larger than the other payloads, but there is no allocator on
+// the device to box it with
+#[allow(clippy::large_enum_variant)]
+pub enum PayloadV2 {
+    /// The internal counter value
+    Counter(u64),
+    /// The state of the led
+    LedState(LedState),
+    /// The current time of the device, `None` if the time has not been set
+    DateTime(Option<SDateTime>),
+    /// Information on the firmware running on the device
+    FirmwareInfo(FirmwareInfo),
+    /// Identifier of a job that was added to the schedule
+    JobId(JobId),
+    /// One page of the device schedule
+    SchedulePage(SchedulePage),
+    /// Device health information
+    Diagnostics(Diagnostics),
+    /// Responses to the commands of a [CommandV2::Batch]
+    Batch(BatchResponse),
+    /// Ranges of command arguments accepted by the device
+    Limits(Limits),
+    /// Quality of the link as seen by the device
+    LinkStats(LinkStats),
+}
+
+impl From<Payload> for PayloadV2 {
+    fn from(payload: Payload) -> Self {
+        match payload {
+            Payload::Counter(c) => PayloadV2::Counter(c),
+        }
+    }
+}
+
+/// State of the led as returned by [CommandV2::LedState]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct LedState {
+    /// Blink period in milliseconds, 0 if the led is not blinking
+    pub blink_period_ms: u64,
+    /// Whether the led is lit at the moment
+    pub is_on: bool,
+}
+
+/// Information on the firmware as returned by [CommandV2::FirmwareInfo]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct FirmwareInfo {
+    /// Major version of the firmware
+    pub major: u16,
+    /// Minor version of the firmware
+    pub minor: u16,
+    /// Patch version of the firmware
+    pub patch: u16,
+    /// Revision of the extended protocol implemented by the firmware, see [PROTOCOL_REVISION]
+    pub protocol: u16,
+}
+
+/// Protocol revision that a message was encoded with
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Version {
+    /// Encoded as [Command] or [Response]
+    Legacy,
+    /// Encoded as [CommandV2] or [ResponseV2], prefixed with [V2_TAG]
+    V2,
+}
+
+/// A command of either protocol revision, as received by the device
+///
+/// Serializes into exactly the same bytes as the wrapped [Command] or, for [Request::V2], as
+/// [V2_TAG] followed by the wrapped [CommandV2].
+#[derive(Clone, Debug, PartialEq)]
+#[allow(clippy::large_enum_variant)]
+pub enum Request {
+    /// Command encoded with the frozen ABI
+    Legacy(Command),
+    /// Command encoded with the extended protocol
+    V2(CommandV2),
+}
+
+impl Request {
+    /// The protocol revision the request was encoded with
+    pub fn version(&self) -> Version {
+        match self {
+            Request::Legacy(_) => Version::Legacy,
+            Request::V2(_) => Version::V2,
+        }
+    }
+}
+
+impl From<Command> for Request {
+    fn from(cmd: Command) -> Self {
+        Request::Legacy(cmd)
+    }
+}
+
+impl From<CommandV2> for Request {
+    fn from(cmd: CommandV2) -> Self {
+        Request::V2(cmd)
+    }
+}
+
+impl From<Request> for CommandV2 {
+    fn from(req: Request) -> Self {
+        match req {
+            Request::Legacy(cmd) => cmd.into(),
+            Request::V2(cmd) => cmd,
+        }
+    }
+}
+
+/// A response of either protocol revision, as sent by the device
+///
+/// Serializes into exactly the same bytes as the wrapped [Response] or, for [Reply::V2], as
+/// [V2_TAG] followed by the wrapped [ResponseV2].
+#[derive(Clone, Debug, PartialEq)]
+#[allow(clippy::large_enum_variant)]
+pub enum Reply {
+    /// Response encoded with the frozen ABI
+    Legacy(Response),
+    /// Response encoded with the extended protocol
+    V2(ResponseV2),
+}
+
+impl Reply {
+    /// Constructs a reply to a request encoded with `version`
+    ///
+    /// Legacy requests get a legacy response. A payload that cannot be represented by [Payload]
+    /// is replaced with [RejectReason::InternalError], which may only happen if the device
+    /// returns an extended payload for a legacy command.
+    pub fn new(version: Version, resp: ResponseV2) -> Self {
+        match version {
+            Version::Legacy => Reply::Legacy(lower_response(resp)),
+            Version::V2 => Reply::V2(resp),
+        }
+    }
+}
+
+impl From<Reply> for ResponseV2 {
+    fn from(reply: Reply) -> Self {
+        match reply {
+            Reply::Legacy(resp) => resp.into(),
+            Reply::V2(resp) => resp,
+        }
+    }
+}
+
+/// Converts an extended response into a legacy one, if possible
+pub(crate) fn lower_response(resp: ResponseV2) -> Response {
+    fn lower_payload(payload: Option<PayloadV2>) -> Result<Option<Payload>, ()> {
+        match payload {
+            None => Ok(None),
+            Some(PayloadV2::Counter(c)) => Ok(Some(Payload::Counter(c))),
+            Some(_) => Err(()),
+        }
+    }
+    fn lower_command(cmd: CommandV2) -> Result<Command, ()> {
+        match cmd {
+            CommandV2::Reset => Ok(Command::Reset),
+            CommandV2::Counter => Ok(Command::Counter),
+            CommandV2::SetDateTime(dt) => Ok(Command::SetDateTime(dt)),
+            CommandV2::Immediate(f) => Ok(Command::Immediate(f)),
+            CommandV2::Schedule(f, at) => Ok(Command::Schedule(f, at)),
+            _ => Err(()),
+        }
+    }
+
+    let lowered = match resp {
+        ResponseV2::Ok(p) => lower_payload(p).map(Response::Ok),
+        ResponseV2::Rejected(reason) => Ok(Response::Rejected(reason)),
+        ResponseV2::Busy | ResponseV2::Unauthenticated => Err(()),
+        ResponseV2::OkRecovered(p, cmd) => lower_payload(p)
+            .and_then(|p| lower_command(cmd).map(|cmd| Response::OkRecovered(p, cmd))),
+    };
+    lowered.unwrap_or(Response::Rejected(RejectReason::InternalError))
+}
+
+impl Serialize for Request {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Request::Legacy(cmd) => cmd.serialize(serializer),
+            Request::V2(cmd) => (V2_TAG, cmd).serialize(serializer),
+        }
+    }
+}
+
+impl Serialize for Reply {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Reply::Legacy(resp) => resp.serialize(serializer),
+            Reply::V2(resp) => (V2_TAG, resp).serialize(serializer),
+        }
+    }
+}
+
+/// Maximum number of elements that follow the leading byte of a message: the fields of the widest
+/// legacy variant ([Command::Schedule])
+pub(crate) const MAX_FIELDS: usize = 2;
+
+/// Returns the next element of `seq` or an error if the message ended prematurely
+pub(crate) fn next<'de, T: Deserialize<'de>, A: SeqAccess<'de>>(
+    seq: &mut A,
+    idx: usize,
+    exp: &dyn de::Expected,
+) -> Result<T, A::Error> {
+    seq.next_element()?
+        .ok_or_else(|| de::Error::invalid_length(idx, exp))
+}
+
+struct RequestVisitor;
+
+impl<'de> Visitor<'de> for RequestVisitor {
+    type Value = Request;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a legacy or an extended command")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Request, A::Error> {
+        // The legacy variant indices are fixed by the frozen ABI of [Command]
+        let cmd = match next::<u8, _>(&mut seq, 0, &self)? {
+            V2_TAG => return Ok(Request::V2(next(&mut seq, 1, &self)?)),
+            0 => Command::Reset,
+            1 => Command::Counter,
+            2 => Command::SetDateTime(next(&mut seq, 1, &self)?),
+            3 => Command::Immediate(next(&mut seq, 1, &self)?),
+            4 => Command::Schedule(next(&mut seq, 1, &self)?, next(&mut seq, 2, &self)?),
+            tag => {
+                return Err(de::Error::invalid_value(
+                    Unexpected::Unsigned(tag.into()),
+                    &self,
+                ));
+            }
+        };
+        Ok(Request::Legacy(cmd))
+    }
+}
+
+impl<'de> Deserialize<'de> for Request {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_tuple(1 + MAX_FIELDS, RequestVisitor)
+    }
+}
+
+struct ReplyVisitor;
+
+impl<'de> Visitor<'de> for ReplyVisitor {
+    type Value = Reply;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a legacy or an extended response")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Reply, A::Error> {
+        let tag = next::<u8, _>(&mut seq, 0, &self)?;
+        reply_with_tag(tag, &mut seq, &self)
+    }
+}
+
+/// Decodes the rest of a reply whose leading byte was `tag`
+pub(crate) fn reply_with_tag<'de, A: SeqAccess<'de>>(
+    tag: u8,
+    seq: &mut A,
+    exp: &dyn de::Expected,
+) -> Result<Reply, A::Error> {
+    // The legacy variant indices are fixed by the frozen ABI of [Response]
+    let resp = match tag {
+        V2_TAG => return Ok(Reply::V2(next(seq, 1, exp)?)),
+        0 => Response::Ok(next(seq, 1, exp)?),
+        1 => Response::Rejected(next(seq, 1, exp)?),
+        2 => Response::OkRecovered(next(seq, 1, exp)?, next(seq, 2, exp)?),
+        tag => {
+            return Err(de::Error::invalid_value(
+                Unexpected::Unsigned(tag.into()),
+                exp,
+            ));
+        }
+    };
+    Ok(Reply::Legacy(resp))
+}
+
+impl<'de> Deserialize<'de> for Reply {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_tuple(1 + MAX_FIELDS, ReplyVisitor)
+    }
+}
@@ -0,0 +1,374 @@
+//! Worst-case encoded size of messages, computed at compile time
+//!
+//! [WireSize] gives the size of a message encoded with [crate::format::Ssmarshal], which writes
+//! integers at their full width, `bool` as one byte, and enum variants and options with a leading
+//! byte. [MaxSize] gives the size with the `postcard` backend, which writes integers wider than
+//! `u8` as varints of up to `ceil(bits / 7)` bytes, signed integers zigzag encoded, and enum
+//! variants with a varint discriminant. Neither backend writes a length for an array.
+//!
+//! The sizes are the largest of all variants of an enum, so that a buffer of the size fits every
+//! message of the type by construction. `MaxSize` mirrors the trait of postcard, which cannot be
+//! implemented for the types of [the_protocol] from this crate.
+//!
+//! The sizes are computed from tables that list the variants and fields of each type as declared.
+//! A table that no longer matches its type fails to compile, so a field added to a message cannot
+//! be left out of its size. The `wire_size` example encodes the widest value of every variant to
+//! check the sizes themselves.
+use the_protocol::{Command, Funct, Payload, RejectReason, Response, SDateTime};
+
+use crate::{
+    Batch, BatchMode, BatchResponse, BlinkPattern, BlinkShape, Color, CommandV2, DeviceMessage,
+    Diagnostics, Event, FirmwareInfo, JobId, LedState, Limits, LinkStats, MorseText, PayloadV2,
+    Press, Recurrence, Reply, Request, ResetReason, ResponseV2, RgbEffect, SchedulePage,
+    ScheduledJob,
+};
+
+/// Type with a bounded size when encoded with [crate::format::Ssmarshal]
+pub trait WireSize {
+    /// Largest number of bytes that a value of the type takes when encoded
+    const WIRE_SIZE: usize;
+}
+
+/// Type with a bounded size when encoded with the `postcard` backend, see `format::Postcard`
+pub trait MaxSize {
+    /// Largest number of bytes that a value of the type takes when encoded
+    const POSTCARD_MAX_SIZE: usize;
+}
+
+/// Largest encoded size of a varint of an integer with `bits` bits
+const fn varint_max(bits: usize) -> usize {
+    bits.div_ceil(7)
+}
+
+/// Larger of `a` and `b`, usable in constants
+const fn max(a: usize, b: usize) -> usize {
+    if a > b { a } else { b }
+}
+
+/// Largest of `sizes`, usable in constants
+const fn largest(sizes: &[usize]) -> usize {
+    let mut largest = 0;
+    let mut i = 0;
+    while i < sizes.len() {
+        largest = max(largest, sizes[i]);
+        i += 1;
+    }
+    largest
+}
+
+/// Implements [WireSize] and [MaxSize] for types of a fixed size
+macro_rules! impl_sizes {
+    ($($ty:ty => $wire:expr, $postcard:expr);* $(;)?) => {
+        $(
+            impl WireSize for $ty {
+                const WIRE_SIZE: usize = $wire;
+            }
+            impl MaxSize for $ty {
+                const POSTCARD_MAX_SIZE: usize = $postcard;
+            }
+        )*
+    };
+}
+
+/// Pattern that matches any value of the type, to check the variants of an enum in a table
+macro_rules! any {
+    ($ty:ty) => {
+        _
+    };
+}
+
+/// Implements [WireSize] and [MaxSize] for a struct as the sum of its fields
+///
+/// The fields are given by name, and the table fails to compile unless they are exactly the fields
+/// of the struct with the same types.
+macro_rules! sizes_struct {
+    ($ty:ident { $($name:ident: $field:ty),* $(,)? }) => {
+        sizes_struct!(@impl $ty { $($field),* });
+        const _: fn($($field),*) -> $ty = |$($name),*| $ty { $($name),* };
+    };
+    // For structs with private fields, which cannot be checked
+    ($ty:ident ( $($field:ty),* $(,)? )) => {
+        sizes_struct!(@impl $ty { $($field),* });
+    };
+    (@impl $ty:ident { $($field:ty),* }) => {
+        impl WireSize for $ty {
+            const WIRE_SIZE: usize = 0 $(+ <$field as WireSize>::WIRE_SIZE)*;
+        }
+        impl MaxSize for $ty {
+            const POSTCARD_MAX_SIZE: usize = 0 $(+ <$field as MaxSize>::POSTCARD_MAX_SIZE)*;
+        }
+    };
+}
+
+/// Implements [WireSize] and [MaxSize] for an enum as the discriminant plus the largest of its
+/// variants
+///
+/// The variants are given with their fields as in the declaration of the enum, and the table fails
+/// to compile unless they are exactly the variants of the enum with the same fields.
+macro_rules! sizes_enum {
+    ($ty:ident {
+        $($variant:ident
+            $(( $($tfield:ty),* $(,)? ))?
+            $({ $($name:ident: $sfield:ty),* $(,)? })?
+        ),* $(,)?
+    }) => {
+        impl WireSize for $ty {
+            const WIRE_SIZE: usize = {
+                let variants = [$(
+                    0
+                    $($(+ <$tfield as WireSize>::WIRE_SIZE)*)?
+                    $($(+ <$sfield as WireSize>::WIRE_SIZE)*)?
+                ),*];
+                // ssmarshal rejects enums with more variants than fit in the byte
+                assert!(variants.len() <= 256);
+                1 + largest(&variants)
+            };
+        }
+        impl MaxSize for $ty {
+            const POSTCARD_MAX_SIZE: usize = {
+                let variants = [$(
+                    0
+                    $($(+ <$tfield as MaxSize>::POSTCARD_MAX_SIZE)*)?
+                    $($(+ <$sfield as MaxSize>::POSTCARD_MAX_SIZE)*)?
+                ),*];
+                // The discriminant is the variant index as a varint
+                let index_bits = usize::BITS - (variants.len() - 1).leading_zeros();
+                max(1, varint_max(index_bits as usize)) + largest(&variants)
+            };
+        }
+        // Every variant, with the number and names of its fields
+        const _: fn(&$ty) = |value| match value {
+            $($ty::$variant
+                $(( $(any!($tfield)),* ))?
+                $({ $($name: any!($sfield)),* })? => {}
+            ),*
+        };
+        // The types of the fields
+        $(
+            $(const _: fn($($tfield),*) -> $ty = $ty::$variant;)?
+            $(const _: fn($($sfield),*) -> $ty = |$($name),*| $ty::$variant { $($name),* };)?
+        )*
+    };
+}
+
+impl_sizes! {
+    u8 => 1, 1;
+    bool => 1, 1;
+    u16 => 2, varint_max(16);
+    u32 => 4, varint_max(32);
+    u64 => 8, varint_max(64);
+    i32 => 4, varint_max(32);
+}
+
+impl<T: WireSize> WireSize for Option<T> {
+    const WIRE_SIZE: usize = 1 + T::WIRE_SIZE;
+}
+
+impl<T: MaxSize> MaxSize for Option<T> {
+    const POSTCARD_MAX_SIZE: usize = 1 + T::POSTCARD_MAX_SIZE;
+}
+
+impl<T: WireSize, const N: usize> WireSize for [T; N] {
+    const WIRE_SIZE: usize = N * T::WIRE_SIZE;
+}
+
+impl<T: MaxSize, const N: usize> MaxSize for [T; N] {
+    const POSTCARD_MAX_SIZE: usize = N * T::POSTCARD_MAX_SIZE;
+}
+
+// The frozen ABI, whose date-time has private fields
+sizes_struct!(SDateTime(i32, u32, u32, u32, u32, u32, u32));
+sizes_enum!(Funct {
+    Increment,
+    EnableBlink { period_ms: u64 },
+    DisableBlink,
+    EnableRgb,
+    DisableRgb,
+});
+sizes_enum!(Command {
+    Reset,
+    Counter,
+    SetDateTime(Option<SDateTime>),
+    Immediate(Funct),
+    Schedule(Funct, SDateTime),
+});
+sizes_enum!(Payload { Counter(u64) });
+sizes_enum!(RejectReason {
+    CorruptedFrame,
+    IllegalCommand,
+    NotImplemented,
+    InternalError,
+});
+sizes_enum!(Response {
+    Ok(Option<Payload>),
+    Rejected(RejectReason),
+    OkRecovered(Option<Payload>, Command),
+});
+
+// The extended protocol
+sizes_enum!(Recurrence {
+    Periodic { start: SDateTime, period_s: u32 },
+    Daily { hour: u8, minute: u8 },
+    Repeat { start: SDateTime, interval_s: u32, count: u32 },
+});
+sizes_enum!(BatchMode { Atomic, BestEffort });
+sizes_struct!(Batch {
+    mode: BatchMode,
+    commands: [Option<Command>; crate::MAX_BATCH_LEN],
+});
+sizes_struct!(BatchResponse {
+    committed: bool,
+    responses: [Option<Response>; crate::MAX_BATCH_LEN],
+});
+sizes_struct!(MorseText {
+    len: u8,
+    chars: [u8; crate::MAX_MORSE_LEN],
+});
+sizes_enum!(BlinkShape {
+    Duty { on_ms: u32, off_ms: u32 },
+    Burst { flashes: u8, on_ms: u32, off_ms: u32, pause_ms: u32 },
+    Morse { unit_ms: u32, text: MorseText },
+});
+sizes_struct!(BlinkPattern {
+    shape: BlinkShape,
+    repetitions: Option<u16>,
+});
+sizes_enum!(Color {
+    Rgb { r: u8, g: u8, b: u8 },
+    Hsv { h: u8, s: u8, v: u8 },
+});
+sizes_enum!(RgbEffect {
+    Off,
+    Static(Color),
+    Breathe { color: Color, period_ms: u32 },
+    Rainbow { period_ms: u32 },
+    Fade { to: Color, duration_ms: u32 },
+});
+sizes_enum!(CommandV2 {
+    Reset,
+    Counter,
+    SetDateTime(Option<SDateTime>),
+    Immediate(Funct),
+    Schedule(Funct, SDateTime),
+    ScheduleRecurring(Funct, Recurrence),
+    CancelJob(JobId),
+    ClearSchedule,
+    ListSchedule { page: u8 },
+    LedState,
+    DateTime,
+    SetCounter(u64),
+    FirmwareInfo,
+    Diagnostics,
+    Batch(Batch),
+    SubscribeEvents(bool),
+    SetBlinkPattern(BlinkPattern),
+    SetRgbEffect(RgbEffect),
+    SetRgbBrightness(u8),
+    Limits,
+    LinkStats,
+    SetBaudRate(u32),
+});
+sizes_struct!(LedState {
+    blink_period_ms: u64,
+    is_on: bool,
+});
+sizes_struct!(FirmwareInfo {
+    major: u16,
+    minor: u16,
+    patch: u16,
+    protocol: u16,
+});
+sizes_struct!(ScheduledJob {
+    id: JobId,
+    funct: Funct,
+    at: SDateTime,
+    recurrence: Option<Recurrence>,
+});
+sizes_struct!(SchedulePage {
+    page: u8,
+    page_count: u8,
+    jobs: [Option<ScheduledJob>; crate::SCHEDULE_PAGE_LEN],
+});
+sizes_enum!(ResetReason {
+    PowerOn,
+    Software,
+    Watchdog,
+    BrownOut,
+    DeepSleep,
+    Other(u8),
+    Unknown,
+});
+sizes_struct!(Diagnostics {
+    uptime_ms: u64,
+    free_schedule_slots: u16,
+    frames_received: u32,
+    corrupted_frames: u32,
+    stale_frames: u32,
+    uart_overruns: u32,
+    busy_rejections: u32,
+    dropped_replies: u32,
+    write_errors: u32,
+    auth_failures: u32,
+    reset_reason: ResetReason,
+});
+sizes_struct!(Limits {
+    min_blink_period_ms: u64,
+    max_blink_period_ms: u64,
+    min_blink_step_ms: u32,
+    max_blink_step_ms: u32,
+    min_rgb_period_ms: u32,
+    max_rgb_period_ms: u32,
+    max_rgb_brightness: u8,
+    min_baud_rate: u32,
+    max_baud_rate: u32,
+});
+sizes_struct!(LinkStats {
+    frames_sent: u32,
+    retransmissions: u32,
+    frames_received: u32,
+    frame_errors: u32,
+    frame_error_rate: u16,
+    retransmission_rate: u16,
+    srtt_ms: u32,
+    rttvar_ms: u32,
+    rto_ms: u32,
+});
+sizes_enum!(PayloadV2 {
+    Counter(u64),
+    LedState(LedState),
+    DateTime(Option<SDateTime>),
+    FirmwareInfo(FirmwareInfo),
+    JobId(JobId),
+    SchedulePage(SchedulePage),
+    Diagnostics(Diagnostics),
+    Batch(BatchResponse),
+    Limits(Limits),
+    LinkStats(LinkStats),
+});
+sizes_enum!(ResponseV2 {
+    Ok(Option<PayloadV2>),
+    Rejected(RejectReason),
+    OkRecovered(Option<PayloadV2>, CommandV2),
+    Busy,
+    Unauthenticated,
+});
+sizes_enum!(Press { Short, Long });
+sizes_enum!(Event {
+    JobFired { id: JobId, at: SDateTime },
+    ClockUnset,
+    Rebooted { reason: ResetReason },
+    ButtonPressed { press: Press, funct: Funct },
+});
+
+// Extended messages are prefixed with a tag byte instead of a discriminant
+impl_sizes! {
+    Request =>
+        max(Command::WIRE_SIZE, 1 + CommandV2::WIRE_SIZE),
+        max(Command::POSTCARD_MAX_SIZE, 1 + CommandV2::POSTCARD_MAX_SIZE);
+    Reply =>
+        max(Response::WIRE_SIZE, 1 + ResponseV2::WIRE_SIZE),
+        max(Response::POSTCARD_MAX_SIZE, 1 + ResponseV2::POSTCARD_MAX_SIZE);
+    DeviceMessage =>
+        max(Reply::WIRE_SIZE, 1 + Event::WIRE_SIZE),
+        max(Reply::POSTCARD_MAX_SIZE, 1 + Event::POSTCARD_MAX_SIZE);
+}